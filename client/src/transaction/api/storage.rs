@@ -1,5 +1,5 @@
 //! Client for service defined in go/storage/api.
-use grpcio::{CallOption, Channel, Client, Result};
+use grpcio::{CallOption, Channel, Client, ClientUnaryReceiver, Result};
 use serde::{Deserialize, Serialize};
 use serde_cbor::value::Value;
 
@@ -94,4 +94,36 @@ impl StorageClient {
     ) -> Result<sync::ProofResponse> {
         self.client.unary_call(&METHOD_SYNC_ITERATE, &request, opt)
     }
+
+    /// Asynchronously fetch a single key and return the corresponding proof.
+    pub fn sync_get_async(
+        &self,
+        request: &sync::GetRequest,
+        opt: CallOption,
+    ) -> Result<ClientUnaryReceiver<sync::ProofResponse>> {
+        self.client
+            .unary_call_async(&METHOD_SYNC_GET, &request, opt)
+    }
+
+    /// Asynchronously fetch all keys under the given prefixes and return the
+    /// corresponding proofs.
+    pub fn sync_get_prefixes_async(
+        &self,
+        request: &sync::GetPrefixesRequest,
+        opt: CallOption,
+    ) -> Result<ClientUnaryReceiver<sync::ProofResponse>> {
+        self.client
+            .unary_call_async(&METHOD_SYNC_GET_PREFIXES, &request, opt)
+    }
+
+    /// Asynchronously seek to a given key and then fetch the specified number of
+    /// following items based on key iteration order.
+    pub fn sync_iterate_async(
+        &self,
+        request: &sync::IterateRequest,
+        opt: CallOption,
+    ) -> Result<ClientUnaryReceiver<sync::ProofResponse>> {
+        self.client
+            .unary_call_async(&METHOD_SYNC_ITERATE, &request, opt)
+    }
 }
@@ -1,8 +1,12 @@
 //! A block snapshot.
-use std::{any::Any, sync::Mutex};
+use std::{
+    any::Any,
+    sync::{Arc, Mutex},
+};
 
 use anyhow::{Context as AnyContext, Result};
-use grpcio::CallOption;
+use futures::{future, Future};
+use grpcio::{CallOption, ClientUnaryReceiver};
 use io_context::Context;
 use oasis_core_runtime::{
     common::{cbor, crypto::hash::Hash, namespace::Namespace},
@@ -99,6 +103,23 @@ impl BlockSnapshot {
             mkvs: Mutex::new(mkvs),
         }
     }
+
+    /// Create a new tree for the block's state root that supports asynchronous
+    /// operations like `Tree::get_async`.
+    ///
+    /// Multiple asynchronous lookups can be in flight at once without requiring
+    /// a thread per request.
+    pub fn async_tree(&self) -> Tree {
+        Tree::make()
+            .with_root(Root {
+                namespace: self.block.header.namespace,
+                version: self.block.header.round,
+                root_type: RootType::State,
+                hash: self.block.header.state_root,
+            })
+            .with_async_read_syncer(Arc::new(self.read_syncer.clone()))
+            .new(Box::new(self.read_syncer.clone()))
+    }
 }
 
 impl MKVS for BlockSnapshot {
@@ -125,6 +146,13 @@ impl MKVS for BlockSnapshot {
         mkvs.prefetch_prefixes(ctx, prefixes, limit).unwrap()
     }
 
+    fn prefetch_keys(&self, ctx: Context, keys: &[Vec<u8>]) {
+        // Prefetching is only an optimization and keys are fetched again when
+        // accessed, so ignore failures.
+        let mkvs = self.mkvs.lock().unwrap();
+        let _ = mkvs.prefetch_keys(ctx, keys);
+    }
+
     fn iter(&self, _ctx: Context) -> Box<dyn Iterator + '_> {
         unimplemented!("block snapshot doesn't support iterators");
     }
@@ -154,6 +182,10 @@ impl ReadSync for RemoteReadSync {
             .map_err(|error| TxnClientError::CallFailed(format!("{}", error)))?)
     }
 
+    fn sync_get_many(&mut self, _ctx: Context, _request: GetManyRequest) -> Result<ProofResponse> {
+        Err(SyncerError::Unsupported.into())
+    }
+
     fn sync_get_prefixes(
         &mut self,
         _ctx: Context,
@@ -166,9 +198,128 @@ impl ReadSync for RemoteReadSync {
     }
 
     fn sync_iterate(&mut self, _ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        if request.reverse {
+            return Err(SyncerError::ReverseIterationUnsupported.into());
+        }
         Ok(self
             .0
             .sync_iterate(&request, CallOption::default().wait_for_ready(true))
             .map_err(|error| TxnClientError::CallFailed(format!("{}", error)))?)
     }
 }
+
+fn call_async<T: Send + 'static>(receiver: grpcio::Result<ClientUnaryReceiver<T>>) -> BoxFuture<T> {
+    let call_failed = |error: grpcio::Error| -> anyhow::Error {
+        TxnClientError::CallFailed(format!("{}", error)).into()
+    };
+    match receiver {
+        Ok(receiver) => Box::new(receiver.map_err(call_failed)),
+        Err(error) => Box::new(future::err(call_failed(error))),
+    }
+}
+
+impl AsyncReadSync for RemoteReadSync {
+    fn sync_get(&self, _ctx: Context, request: GetRequest) -> BoxFuture<ProofResponse> {
+        call_async(
+            self.0
+                .sync_get_async(&request, CallOption::default().wait_for_ready(true)),
+        )
+    }
+
+    fn sync_get_many(&self, _ctx: Context, _request: GetManyRequest) -> BoxFuture<ProofResponse> {
+        Box::new(future::err(SyncerError::Unsupported.into()))
+    }
+
+    fn sync_get_prefixes(
+        &self,
+        _ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> BoxFuture<ProofResponse> {
+        call_async(
+            self.0
+                .sync_get_prefixes_async(&request, CallOption::default().wait_for_ready(true)),
+        )
+    }
+
+    fn sync_iterate(&self, _ctx: Context, request: IterateRequest) -> BoxFuture<ProofResponse> {
+        if request.reverse {
+            return Box::new(future::err(SyncerError::ReverseIterationUnsupported.into()));
+        }
+        call_async(
+            self.0
+                .sync_iterate_async(&request, CallOption::default().wait_for_ready(true)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use grpcio::{ChannelBuilder, EnvBuilder};
+    use oasis_core_runtime::{
+        consensus::roothash::Header,
+        storage::mkvs::{
+            interop::{Driver, StorageServer},
+            sync::NoopReadSyncer,
+            OverlayTree,
+        },
+    };
+
+    use super::*;
+
+    const NUM_KEYS: usize = 10;
+
+    #[test]
+    fn test_block_snapshot() {
+        let server = StorageServer::new();
+
+        let mut tree = OverlayTree::new(
+            Tree::make()
+                .with_root_type(RootType::State)
+                .new(Box::new(NoopReadSyncer)),
+        );
+        for i in 0..NUM_KEYS {
+            let key = format!("key {}", i);
+            let value = format!("value {}", i);
+            tree.insert(Context::background(), key.as_bytes(), value.as_bytes())
+                .expect("insert");
+        }
+        let (write_log, hash) = tree
+            .commit_both(Context::background(), Namespace::default(), 1)
+            .expect("commit");
+        server.apply(&write_log, hash, Namespace::default(), 1);
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let channel = ChannelBuilder::new(env).connect(&server.address());
+        let block = Block {
+            header: Header {
+                round: 1,
+                state_root: hash,
+                ..Default::default()
+            },
+        };
+        let snapshot = BlockSnapshot::new(api::storage::StorageClient::new(channel), block);
+
+        for i in 0..NUM_KEYS {
+            let key = format!("key {}", i);
+            let value = format!("value {}", i);
+            assert_eq!(
+                snapshot.get(Context::background(), key.as_bytes()),
+                Some(value.into_bytes())
+            );
+        }
+        assert_eq!(snapshot.get(Context::background(), b"missing"), None);
+
+        // Reverse iteration falls back to fetching paths as the remote read
+        // syncer doesn't support it.
+        let tree = snapshot.async_tree();
+        let mut it = tree.iter(Context::background());
+        it.seek_last();
+        for i in (0..NUM_KEYS).rev() {
+            assert!(it.is_valid(), "iterator should be valid");
+            assert_eq!(it.get_key(), &Some(format!("key {}", i).into_bytes()));
+            it.prev();
+        }
+        assert!(!it.is_valid(), "iterator should be exhausted");
+        assert!(it.error().is_none(), "iterator should not fail");
+    }
+}
@@ -90,6 +90,8 @@ func (h *clientHost) Handle(ctx context.Context, body *protocol.Body) (*protocol
 		switch {
 		case rq.SyncGet != nil:
 			rsp, err = rs.SyncGet(sctx, rq.SyncGet)
+		case rq.SyncGetMany != nil:
+			rsp, err = syncer.SyncGetMany(sctx, rs, rq.SyncGetMany)
 		case rq.SyncGetPrefixes != nil:
 			rsp, err = rs.SyncGetPrefixes(sctx, rq.SyncGetPrefixes)
 		case rq.SyncIterate != nil:
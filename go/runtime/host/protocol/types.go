@@ -310,6 +310,7 @@ type HostStorageSyncRequest struct {
 	Endpoint HostStorageEndpoint `json:"endpoint,omitempty"`
 
 	SyncGet         *storage.GetRequest         `json:",omitempty"`
+	SyncGetMany     *storage.GetManyRequest     `json:",omitempty"`
 	SyncGetPrefixes *storage.GetPrefixesRequest `json:",omitempty"`
 	SyncIterate     *storage.IterateRequest     `json:",omitempty"`
 }
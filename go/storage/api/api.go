@@ -228,6 +228,9 @@ type TreeID = syncer.TreeID
 // GetRequest is a request for the SyncGet operation.
 type GetRequest = syncer.GetRequest
 
+// GetManyRequest is a request for the SyncGetMany operation.
+type GetManyRequest = syncer.GetManyRequest
+
 // GetPrefixesRequest is a request for the SyncGetPrefixes operation.
 type GetPrefixesRequest = syncer.GetPrefixesRequest
 
@@ -0,0 +1,56 @@
+package syncer
+
+import (
+	"context"
+	"fmt"
+
+	"github.com/oasisprotocol/oasis-core/go/storage/mkvs/node"
+)
+
+// SyncGetMany fetches multiple keys from the given read syncer and returns a
+// single proof covering all of them.
+//
+// The keys are fetched one by one using SyncGet and the resulting proofs are
+// merged. This enables serving batched fetches on top of any read syncer.
+func SyncGetMany(ctx context.Context, rs ReadSyncer, request *GetManyRequest) (*ProofResponse, error) {
+	var pv ProofVerifier
+	pb := NewProofBuilder(request.Tree.Root.Hash, request.Tree.Position)
+	for _, key := range request.Keys {
+		rsp, err := rs.SyncGet(ctx, &GetRequest{
+			Tree: request.Tree,
+			Key:  key,
+		})
+		if err != nil {
+			return nil, err
+		}
+
+		root := rsp.Proof.UntrustedRoot
+		if !root.Equal(&request.Tree.Root.Hash) && !root.Equal(&request.Tree.Position) {
+			return nil, fmt.Errorf("syncer: got proof for unexpected root (%s)", root)
+		}
+		subtree, err := pv.VerifyProof(ctx, root, &rsp.Proof)
+		if err != nil {
+			return nil, err
+		}
+		includeSubtree(pb, subtree)
+	}
+
+	proof, err := pb.Build(ctx)
+	if err != nil {
+		return nil, err
+	}
+	return &ProofResponse{Proof: *proof}, nil
+}
+
+// includeSubtree includes all nodes of a verified subtree in the proof.
+func includeSubtree(pb *ProofBuilder, ptr *node.Pointer) {
+	if ptr == nil || ptr.Node == nil {
+		return
+	}
+
+	pb.Include(ptr.Node)
+	if n, ok := ptr.Node.(*node.InternalNode); ok {
+		includeSubtree(pb, n.Left)
+		includeSubtree(pb, n.Right)
+	}
+}
@@ -36,6 +36,12 @@ type GetRequest struct {
 	IncludeSiblings bool   `json:"include_siblings,omitempty"`
 }
 
+// GetManyRequest is a request for the SyncGetMany operation.
+type GetManyRequest struct {
+	Tree TreeID   `json:"tree"`
+	Keys [][]byte `json:"keys"`
+}
+
 // GetPrefixesRequest is a request for the SyncGetPrefixes operation.
 type GetPrefixesRequest struct {
 	Tree     TreeID   `json:"tree"`
@@ -157,6 +157,56 @@ func TestProof(t *testing.T) {
 	require.Error(err, "VerifyProof should fail with invalid proof")
 }
 
+func TestSyncGetMany(t *testing.T) {
+	require := require.New(t)
+
+	ctx := context.Background()
+	keys, values := generateKeyValuePairsEx("", 10)
+	var ns common.Namespace
+
+	tree := New(nil, nil, node.RootTypeState)
+	for i, key := range keys {
+		err := tree.Insert(ctx, key, values[i])
+		require.NoError(err, "Insert")
+	}
+	_, rootHash, err := tree.Commit(ctx, ns, 0)
+	require.NoError(err, "Commit")
+	root := node.Root{Namespace: ns, Version: 0, Type: node.RootTypeState, Hash: rootHash}
+
+	stats := syncer.NewStatsCollector(tree)
+	rsp, err := syncer.SyncGetMany(ctx, stats, &syncer.GetManyRequest{
+		Tree: syncer.TreeID{Root: root, Position: rootHash},
+		Keys: keys[:5],
+	})
+	require.NoError(err, "SyncGetMany")
+	require.EqualValues(5, stats.SyncGetCount, "SyncGet should be called for each key")
+
+	// The merged proof should verify and include all requested keys.
+	var pv syncer.ProofVerifier
+	subtree, err := pv.VerifyProof(ctx, rootHash, &rsp.Proof)
+	require.NoError(err, "VerifyProof should not fail with a merged proof")
+
+	included := make(map[string][]byte)
+	var collect func(ptr *node.Pointer)
+	collect = func(ptr *node.Pointer) {
+		if ptr == nil || ptr.Node == nil {
+			return
+		}
+		switch n := ptr.Node.(type) {
+		case *node.InternalNode:
+			collect(n.LeafNode)
+			collect(n.Left)
+			collect(n.Right)
+		case *node.LeafNode:
+			included[string(n.Key)] = n.Value
+		}
+	}
+	collect(subtree)
+	for i, key := range keys[:5] {
+		require.EqualValues(values[i], included[string(key)], "proof should include requested key")
+	}
+}
+
 func copyProof(p *syncer.Proof) *syncer.Proof {
 	if p == nil {
 		return nil
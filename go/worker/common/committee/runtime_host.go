@@ -88,6 +88,8 @@ func (h *computeRuntimeHostHandler) Handle(ctx context.Context, body *protocol.B
 		switch {
 		case rq.SyncGet != nil:
 			rsp, err = rs.SyncGet(sctx, rq.SyncGet)
+		case rq.SyncGetMany != nil:
+			rsp, err = syncer.SyncGetMany(sctx, rs, rq.SyncGetMany)
 		case rq.SyncGetPrefixes != nil:
 			rsp, err = rs.SyncGetPrefixes(sctx, rq.SyncGetPrefixes)
 		case rq.SyncIterate != nil:
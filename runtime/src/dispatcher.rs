@@ -37,12 +37,15 @@ use crate::{
     storage::{
         mkvs::{
             sync::{HostReadSyncer, NoopReadSyncer},
-            OverlayTree, Root, RootType, Tree,
+            NodeCache, OverlayTree, Root, RootType, SharedTree, Tree,
         },
         StorageContext,
     },
     transaction::{
-        dispatcher::{Dispatcher as TxnDispatcher, NoopDispatcher as TxnNoopDispatcher},
+        dispatcher::{
+            Dispatcher as TxnDispatcher, NoopDispatcher as TxnNoopDispatcher, QueryHandler,
+        },
+        query::{process_query, QueryPool},
         tree::Tree as TxnTree,
         types::TxnBatch,
         Context as TxnContext,
@@ -52,6 +55,14 @@ use crate::{
 
 /// Maximum amount of requests that can be in the dispatcher queue.
 const BACKLOG_SIZE: usize = 1000;
+/// Maximum number of nodes kept in the node cache shared across state roots.
+const NODE_CACHE_CAPACITY: usize = 100_000;
+/// Maximum total size, in bytes, of values kept in the node cache shared across state roots.
+///
+/// Cached nodes are kept in addition to the per-tree caches (each holding up to 100_000 nodes
+/// and 10MB of values), so together they must stay well within the enclave heap (128MB for the
+/// test runtimes).
+const NODE_CACHE_VALUE_CAPACITY: usize = 10_000_000;
 
 /// Interface for dispatcher initializers.
 pub trait Initializer: Send + Sync {
@@ -94,7 +105,7 @@ type QueueItem = (Context, u64, Body);
 /// This is to ensure that the runtime will terminate in case there is
 /// a panic encountered during dispatch and the runtime is built with
 /// a non-abort panic handler.
-struct AbortOnPanic;
+pub(crate) struct AbortOnPanic;
 
 impl Drop for AbortOnPanic {
     fn drop(&mut self) {
@@ -195,9 +206,45 @@ impl Dispatcher {
         txn_dispatcher.set_abort_batch_flag(self.abort_batch.clone());
 
         // Create common MKVS to use as a cache as long as the root stays the same. Use separate
-        // caches for executing and checking transactions.
-        let mut cache = Cache::new(protocol.clone());
-        let mut cache_check = Cache::new(protocol.clone());
+        // caches for executing and checking transactions, but share fetched nodes across roots.
+        let node_cache = NodeCache::new(NODE_CACHE_CAPACITY, NODE_CACHE_VALUE_CAPACITY);
+        let mut cache = Cache::new(protocol.clone(), node_cache.clone());
+        let mut cache_check = Cache::new(protocol.clone(), node_cache.clone());
+
+        // Process queries in parallel in case the transaction dispatcher supports it.
+        let query_handler = txn_dispatcher.query_handler();
+        let query_pool = query_handler.clone().and_then(|handler| {
+            let tree_protocol = protocol.clone();
+            let local_protocol = protocol.clone();
+            QueryPool::start(
+                handler,
+                move |root| {
+                    let read_syncer =
+                        HostReadSyncer::new(tree_protocol.clone(), HostStorageEndpoint::Runtime);
+                    SharedTree::with_node_cache(Box::new(read_syncer), root, node_cache.clone())
+                },
+                move |ctx| {
+                    Arc::new(ProtocolUntrustedLocalStorage::new(
+                        Context::create_child(ctx),
+                        local_protocol.clone(),
+                    ))
+                },
+            )
+        });
+        match query_pool {
+            Some(ref query_pool) => {
+                info!(self.logger, "Processing queries in parallel";
+                    "threads" => query_pool.threads(),
+                );
+            }
+            None if query_handler.is_some() => {
+                warn!(
+                    self.logger,
+                    "No query threads available, processing queries inline"
+                );
+            }
+            None => {}
+        }
 
         'dispatch: loop {
             // Check if abort was requested and if so, signal that the batch
@@ -268,10 +315,32 @@ impl Dispatcher {
                     header,
                     args,
                 } => {
+                    self.verify_query(&protocol, &header);
+
+                    if let Some(ref query_pool) = query_pool {
+                        // Query is processed and responded to by the query pool.
+                        let protocol = protocol.clone();
+                        query_pool.queue(
+                            ctx,
+                            header,
+                            method,
+                            args,
+                            Box::new(move |result| {
+                                let response = match result {
+                                    Ok(data) => Body::RuntimeQueryResponse { data },
+                                    Err(error) => Body::Error(error),
+                                };
+                                protocol.send_response(id, response).unwrap();
+                            }),
+                        );
+                        continue 'dispatch;
+                    }
+
                     // Query.
                     self.dispatch_query(
                         &mut cache_check,
                         &mut txn_dispatcher,
+                        query_handler.as_deref(),
                         &protocol,
                         ctx,
                         method,
@@ -303,16 +372,8 @@ impl Dispatcher {
         Ok(())
     }
 
-    fn dispatch_query(
-        &self,
-        cache: &mut Cache,
-        txn_dispatcher: &mut dyn TxnDispatcher,
-        protocol: &Arc<Protocol>,
-        ctx: Context,
-        method: String,
-        header: Header,
-        args: cbor::Value,
-    ) -> Result<Body, Error> {
+    /// Verify a query request before it is dispatched.
+    fn verify_query(&self, protocol: &Arc<Protocol>, header: &Header) {
         debug!(self.logger, "Received query request";
             "state_root" => ?header.state_root,
             "round" => ?header.round,
@@ -327,8 +388,20 @@ impl Dispatcher {
                 protocol.get_runtime_id(),
             );
         }
+    }
 
-        // Create a new context and dispatch the batch.
+    fn dispatch_query(
+        &self,
+        cache: &mut Cache,
+        txn_dispatcher: &mut dyn TxnDispatcher,
+        query_handler: Option<&dyn QueryHandler>,
+        protocol: &Arc<Protocol>,
+        ctx: Context,
+        method: String,
+        header: Header,
+        args: cbor::Value,
+    ) -> Result<Body, Error> {
+        // Create a new context and dispatch the query.
         let ctx = ctx.freeze();
         cache.maybe_replace(Root {
             namespace: header.namespace,
@@ -342,10 +415,11 @@ impl Dispatcher {
             protocol.clone(),
         ));
 
-        let txn_ctx = TxnContext::new(ctx.clone(), &header, &[], true);
-        let mut overlay = OverlayTree::new(&mut cache.mkvs);
-        let result = StorageContext::enter(&mut overlay, untrusted_local, || {
-            txn_dispatcher.query(txn_ctx, &method, args)
+        let result = process_query(ctx, &header, &mut cache.mkvs, untrusted_local, |txn_ctx| {
+            match query_handler {
+                Some(handler) => handler.query(txn_ctx, &method, args),
+                None => txn_dispatcher.query(txn_ctx, &method, args),
+            }
         });
 
         result.map(|data| Body::RuntimeQueryResponse { data })
@@ -362,7 +436,7 @@ impl Dispatcher {
         _io_root: Hash,
     ) -> Result<Body, Error> {
         let mut overlay = OverlayTree::new(&mut cache.mkvs);
-        let results = StorageContext::enter(&mut overlay, untrusted_local.clone(), || {
+        let results = StorageContext::enter_overlay(&mut overlay, untrusted_local.clone(), || {
             txn_dispatcher.check_batch(txn_ctx, &inputs)
         });
 
@@ -383,9 +457,10 @@ impl Dispatcher {
     ) -> Result<Body, Error> {
         let header = txn_ctx.header.clone();
         let mut overlay = OverlayTree::new(&mut cache.mkvs);
-        let mut results = StorageContext::enter(&mut overlay, untrusted_local.clone(), || {
-            txn_dispatcher.execute_batch(txn_ctx, &inputs)
-        })?;
+        let mut results =
+            StorageContext::enter_overlay(&mut overlay, untrusted_local.clone(), || {
+                txn_dispatcher.execute_batch(txn_ctx, &inputs)
+            })?;
 
         // Finalize state.
         let (state_write_log, new_state_root) = overlay
@@ -701,23 +776,26 @@ impl Dispatcher {
 
 struct Cache {
     protocol: Arc<Protocol>,
+    node_cache: NodeCache,
     mkvs: Tree,
     root: Root,
 }
 
 impl Cache {
-    fn new(protocol: Arc<Protocol>) -> Self {
+    fn new(protocol: Arc<Protocol>, node_cache: NodeCache) -> Self {
         Self {
-            mkvs: Self::new_tree(&protocol, Default::default()),
+            mkvs: Self::new_tree(&protocol, &node_cache, Default::default()),
             root: Default::default(),
             protocol,
+            node_cache,
         }
     }
 
-    fn new_tree(protocol: &Arc<Protocol>, root: Root) -> Tree {
+    fn new_tree(protocol: &Arc<Protocol>, node_cache: &NodeCache, root: Root) -> Tree {
         let read_syncer = HostReadSyncer::new(protocol.clone(), HostStorageEndpoint::Runtime);
         Tree::make()
             .with_capacity(100_000, 10_000_000)
+            .with_node_cache(node_cache.clone())
             .with_root(root)
             .new(Box::new(read_syncer))
     }
@@ -727,7 +805,7 @@ impl Cache {
             return;
         }
 
-        self.mkvs = Self::new_tree(&self.protocol, root);
+        self.mkvs = Self::new_tree(&self.protocol, &self.node_cache, root);
         self.root = root;
     }
 
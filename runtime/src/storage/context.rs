@@ -4,10 +4,63 @@
 //! implementations across the current thread.
 use std::{cell::RefCell, sync::Arc};
 
-use super::{KeyValue, MKVS};
+use super::{
+    mkvs::{FallibleMKVS, OverlayTree, Savepoint},
+    KeyValue, MKVS,
+};
+
+/// Storage which supports scopes whose updates can be discarded.
+trait Scoped {
+    /// Open a new scope.
+    fn open_scope(&mut self) -> Savepoint;
+
+    /// Close the given scope, either keeping or discarding its updates.
+    fn close_scope(&mut self, savepoint: Savepoint, keep: bool);
+}
+
+impl<T: FallibleMKVS> Scoped for OverlayTree<T> {
+    fn open_scope(&mut self) -> Savepoint {
+        self.savepoint()
+    }
+
+    fn close_scope(&mut self, savepoint: Savepoint, keep: bool) {
+        // The savepoint is always active as the overlay cannot be committed while a scope is open
+        // and other savepoints are not accessible from within the storage context.
+        if !keep {
+            self.rollback_to(savepoint)
+                .expect("scope savepoint must be active");
+        }
+        self.release(savepoint)
+            .expect("scope savepoint must be active");
+    }
+}
+
+/// A guard that closes a scope, discarding its updates unless closed explicitly.
+///
+/// This makes sure that the scope is closed even if the closure running in it panics.
+struct ScopeGuard {
+    scoped: *mut dyn Scoped,
+    savepoint: Option<Savepoint>,
+}
+
+impl ScopeGuard {
+    fn close(&mut self, keep: bool) {
+        if let Some(savepoint) = self.savepoint.take() {
+            // This is safe because the guard never outlives StorageContext::with_scope.
+            unsafe { (*self.scoped).close_scope(savepoint, keep) };
+        }
+    }
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        self.close(false);
+    }
+}
 
 struct Ctx {
     mkvs: *mut dyn MKVS,
+    scoped: Option<*mut dyn Scoped>,
     untrusted_local: Arc<dyn KeyValue>,
 }
 
@@ -26,12 +79,38 @@ impl CtxGuard {
         // the same thread.
         let mkvs = unsafe { std::mem::transmute::<&mut dyn MKVS, &mut (dyn MKVS + 'static)>(mkvs) };
 
+        Self::enter(Ctx {
+            mkvs,
+            scoped: None,
+            untrusted_local,
+        })
+    }
+
+    fn new_overlay<T>(overlay: &mut OverlayTree<T>, untrusted_local: Arc<dyn KeyValue>) -> Self
+    where
+        T: FallibleMKVS,
+    {
+        // This is safe because the references are only valid within StorageContext::enter_overlay
+        // within the same thread and are never used at the same time.
+        let overlay: *mut OverlayTree<T> = overlay;
+        let mkvs = unsafe {
+            std::mem::transmute::<&mut dyn MKVS, &mut (dyn MKVS + 'static)>(&mut *overlay)
+        };
+        let scoped = unsafe {
+            std::mem::transmute::<&mut dyn Scoped, &mut (dyn Scoped + 'static)>(&mut *overlay)
+        };
+
+        Self::enter(Ctx {
+            mkvs,
+            scoped: Some(scoped),
+            untrusted_local,
+        })
+    }
+
+    fn enter(new_ctx: Ctx) -> Self {
         CTX.with(|ctx| {
             assert!(ctx.borrow().is_none(), "nested enter is not allowed");
-            ctx.borrow_mut().replace(Ctx {
-                mkvs,
-                untrusted_local,
-            });
+            ctx.borrow_mut().replace(new_ctx);
         });
 
         CtxGuard
@@ -60,6 +139,50 @@ impl StorageContext {
         f()
     }
 
+    /// Enter the storage context backed by an overlay tree.
+    ///
+    /// In addition to what `enter` provides, this makes it possible to run closures in
+    /// their own scope via `StorageContext::with_scope`.
+    pub fn enter_overlay<T, F, R>(
+        overlay: &mut OverlayTree<T>,
+        untrusted_local: Arc<dyn KeyValue>,
+        f: F,
+    ) -> R
+    where
+        T: FallibleMKVS,
+        F: FnOnce() -> R,
+    {
+        let _guard = CtxGuard::new_overlay(overlay, untrusted_local);
+        f()
+    }
+
+    /// Run a closure in its own storage scope.
+    ///
+    /// Any MKVS updates made by the closure are kept in case it returns `Ok` and discarded
+    /// otherwise. Scopes can be nested.
+    ///
+    /// In case the storage context has not been entered via `enter_overlay`, the closure is
+    /// run directly and its updates are always kept.
+    pub fn with_scope<F, R, E>(f: F) -> Result<R, E>
+    where
+        F: FnOnce() -> Result<R, E>,
+    {
+        let scoped = match CTX.with(|ctx| ctx.borrow().as_ref().and_then(|ctx| ctx.scoped)) {
+            Some(scoped) => scoped,
+            None => return f(),
+        };
+
+        // This is safe because the pointer is only valid within StorageContext::enter_overlay
+        // within the same thread and is not used while the closure runs.
+        let mut guard = ScopeGuard {
+            scoped,
+            savepoint: Some(unsafe { (*scoped).open_scope() }),
+        };
+        let result = f();
+        guard.close(result.is_ok());
+        result
+    }
+
     /// Run a closure with the thread-local storage context.
     ///
     /// # Panics
@@ -3,7 +3,7 @@ use std::{any::Any, ptr::NonNull, sync::Arc};
 use anyhow::Result;
 use io_context::Context;
 
-use crate::storage::mkvs::{cache::lru_cache::CacheItemBox, sync::*, tree::*};
+use crate::storage::mkvs::{cache::eviction::CacheItemBox, sync::*, tree::*};
 
 /// Statistics about the contents of the cache.
 #[derive(Debug, Default)]
@@ -0,0 +1,564 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    pin::Pin,
+    ptr::NonNull,
+    rc::Rc,
+};
+
+use intrusive_collections::{IntrusivePointer, LinkedList, LinkedListLink};
+use thiserror::Error;
+
+use crate::storage::mkvs::{cache::*, tree::*};
+
+#[derive(Error, Debug)]
+#[error("mkvs: tried to remove locked node")]
+pub struct RemoveLockedError;
+
+/// Strategy used by the cache to decide which nodes to evict once it is full.
+///
+/// The cache uses separate policy instances for internal nodes and for leaf
+/// nodes, each with its own capacity. Sizes of nodes are determined using
+/// `CacheItem::get_cached_size`.
+pub trait EvictionPolicy {
+    /// Start tracking the given node, or mark it as used if it is already
+    /// being tracked.
+    fn add(&mut self, ptr: NodePtrRef);
+
+    /// Mark the given node as used. Returns false if the node is not tracked.
+    fn use_node(&mut self, ptr: NodePtrRef) -> bool;
+
+    /// Stop tracking the given node. Returns false if the node was not tracked.
+    fn remove(&mut self, ptr: NodePtrRef) -> bool;
+
+    /// Stop tracking and return nodes to evict in order to make space for a
+    /// new node of the given size.
+    ///
+    /// Returns an error if the locked node would need to be evicted.
+    fn evict_for(
+        &mut self,
+        size: usize,
+        locked_ptr: Option<&NodePtrRef>,
+    ) -> Result<Vec<NodePtrRef>, RemoveLockedError>;
+
+    /// Mark the current position in the eviction order as the one before any
+    /// nodes are visited. See `Cache::mark_position`.
+    fn mark(&mut self) {}
+
+    /// Notify the policy that the nodes reachable from the given root changed.
+    fn root_updated(&mut self, _root: &NodePtrRef) {}
+
+    /// Total size of all tracked nodes.
+    fn size(&self) -> usize;
+}
+
+fn ptr_key(ptr: &NodePtrRef) -> usize {
+    Rc::as_ptr(ptr) as usize
+}
+
+#[derive(Clone, Default)]
+pub struct CacheItemBox<Item: CacheItem + Default> {
+    item: Rc<RefCell<Item>>,
+    link: LinkedListLink,
+}
+
+unsafe impl<T: CacheItem + Default> IntrusivePointer<CacheItemBox<T>>
+    for Pin<Box<CacheItemBox<T>>>
+{
+    #[inline]
+    fn into_raw(self) -> *const CacheItemBox<T> {
+        unsafe { Box::into_raw(Pin::into_inner_unchecked(self)) }
+    }
+    #[inline]
+    unsafe fn from_raw(ptr: *const CacheItemBox<T>) -> Self {
+        Box::into_pin(Box::from_raw(ptr as *mut CacheItemBox<T>))
+    }
+}
+
+intrusive_adapter!(
+    CacheItemAdapter<Item> = Pin<Box<CacheItemBox<Item>>>:
+        CacheItemBox<Item> { link: LinkedListLink }
+        where Item: CacheItem + Default
+);
+
+/// Least recently used eviction policy.
+///
+/// This is the default policy.
+pub struct LRUPolicy {
+    list: LinkedList<CacheItemAdapter<NodePointer>>,
+    size: usize,
+    capacity: usize,
+    mark: CacheExtra<NodePointer>,
+}
+
+impl LRUPolicy {
+    /// Construct a new policy with the given capacity. A capacity of 0 means
+    /// that the capacity is unlimited.
+    pub fn new(capacity: usize) -> LRUPolicy {
+        LRUPolicy {
+            list: LinkedList::new(CacheItemAdapter::new()),
+            size: 0,
+            capacity: capacity,
+            mark: None,
+        }
+    }
+}
+
+impl EvictionPolicy for LRUPolicy {
+    fn add(&mut self, val: NodePtrRef) {
+        let mut val_ref = val.borrow_mut();
+        if val_ref.get_cache_extra().is_none() {
+            self.size += val_ref.get_cached_size();
+            let mut item_box = Box::pin(CacheItemBox {
+                item: val.clone(),
+                link: LinkedListLink::new(),
+            });
+            val_ref.set_cache_extra(NonNull::new(&mut *item_box));
+            if let Some(non_null_pos) = &self.mark {
+                let mut pos_cursor =
+                    unsafe { self.list.cursor_mut_from_ptr(non_null_pos.as_ptr()) };
+                pos_cursor.insert_after(item_box);
+            } else {
+                self.list.push_front(item_box);
+            }
+        } else {
+            drop(val_ref);
+            self.use_node(val);
+        }
+    }
+
+    fn use_node(&mut self, val: NodePtrRef) -> bool {
+        let val_ref = val.borrow();
+        match val_ref.get_cache_extra() {
+            None => false,
+            Some(non_null) => {
+                let mut item_cursor = unsafe { self.list.cursor_mut_from_ptr(non_null.as_ptr()) };
+                let removed_box = item_cursor.remove().unwrap();
+                self.list.push_front(removed_box);
+                true
+            }
+        }
+    }
+
+    fn remove(&mut self, val: NodePtrRef) -> bool {
+        let extra = val.borrow().get_cache_extra();
+        match extra {
+            None => false,
+            Some(non_null) => {
+                if let Some(non_null_mark) = self.mark {
+                    if non_null.as_ptr() == non_null_mark.as_ptr() {
+                        self.mark = None;
+                    }
+                }
+
+                let mut item_cursor = unsafe { self.list.cursor_mut_from_ptr(non_null.as_ptr()) };
+                match item_cursor.remove() {
+                    None => false,
+                    Some(item_box) => {
+                        let mut val = item_box.item.borrow_mut();
+                        val.set_cache_extra(None);
+                        self.size -= val.get_cached_size();
+                        true
+                    }
+                }
+            }
+        }
+    }
+
+    fn evict_for(
+        &mut self,
+        target_size: usize,
+        locked_val: Option<&NodePtrRef>,
+    ) -> Result<Vec<NodePtrRef>, RemoveLockedError> {
+        let mut evicted: Vec<NodePtrRef> = Vec::new();
+        if self.capacity > 0 {
+            while !self.list.is_empty() && self.size + target_size > self.capacity {
+                let back = (*self.list.back().get().unwrap()).item.clone();
+                if let Some(locked_val) = locked_val {
+                    if back.as_ptr() == locked_val.as_ptr() {
+                        return Err(RemoveLockedError);
+                    }
+                }
+                if self.remove(back.clone()) {
+                    evicted.push(back);
+                }
+            }
+        }
+        Ok(evicted)
+    }
+
+    fn mark(&mut self) {
+        self.mark = self.list.front().get().map(|front| {
+            front
+                .item
+                .borrow()
+                .get_cache_extra()
+                .expect("item was just retrieved from list, cache extra must exist")
+        });
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+}
+
+struct LFUEntry {
+    ptr: NodePtrRef,
+    frequency: u64,
+    sequence: u64,
+    size: usize,
+}
+
+/// Least frequently used eviction policy.
+///
+/// Nodes which were used the least number of times since they were added to
+/// the cache are evicted first. Among those, the least recently used nodes
+/// are evicted first.
+pub struct LFUPolicy {
+    entries: HashMap<usize, LFUEntry>,
+    /// Tracked nodes ordered by frequency and last use.
+    order: BTreeSet<(u64, u64, usize)>,
+    next_sequence: u64,
+    size: usize,
+    capacity: usize,
+}
+
+impl LFUPolicy {
+    /// Construct a new policy with the given capacity. A capacity of 0 means
+    /// that the capacity is unlimited.
+    pub fn new(capacity: usize) -> LFUPolicy {
+        LFUPolicy {
+            entries: HashMap::new(),
+            order: BTreeSet::new(),
+            next_sequence: 0,
+            size: 0,
+            capacity,
+        }
+    }
+}
+
+impl EvictionPolicy for LFUPolicy {
+    fn add(&mut self, ptr: NodePtrRef) {
+        if self.use_node(ptr.clone()) {
+            return;
+        }
+
+        let key = ptr_key(&ptr);
+        let size = ptr.borrow().get_cached_size();
+        self.order.insert((1, self.next_sequence, key));
+        self.entries.insert(
+            key,
+            LFUEntry {
+                ptr,
+                frequency: 1,
+                sequence: self.next_sequence,
+                size,
+            },
+        );
+        self.next_sequence += 1;
+        self.size += size;
+    }
+
+    fn use_node(&mut self, ptr: NodePtrRef) -> bool {
+        let key = ptr_key(&ptr);
+        match self.entries.get_mut(&key) {
+            None => false,
+            Some(entry) => {
+                self.order.remove(&(entry.frequency, entry.sequence, key));
+                entry.frequency += 1;
+                entry.sequence = self.next_sequence;
+                self.next_sequence += 1;
+                self.order.insert((entry.frequency, entry.sequence, key));
+                true
+            }
+        }
+    }
+
+    fn remove(&mut self, ptr: NodePtrRef) -> bool {
+        let key = ptr_key(&ptr);
+        match self.entries.remove(&key) {
+            None => false,
+            Some(entry) => {
+                self.order.remove(&(entry.frequency, entry.sequence, key));
+                self.size -= entry.size;
+                true
+            }
+        }
+    }
+
+    fn evict_for(
+        &mut self,
+        target_size: usize,
+        locked_ptr: Option<&NodePtrRef>,
+    ) -> Result<Vec<NodePtrRef>, RemoveLockedError> {
+        let mut evicted: Vec<NodePtrRef> = Vec::new();
+        if self.capacity > 0 {
+            while self.size + target_size > self.capacity {
+                let (_, _, key) = match self.order.iter().next() {
+                    Some(first) => *first,
+                    None => break,
+                };
+                let ptr = self.entries[&key].ptr.clone();
+                if let Some(locked_ptr) = locked_ptr {
+                    if ptr.as_ptr() == locked_ptr.as_ptr() {
+                        return Err(RemoveLockedError);
+                    }
+                }
+                self.remove(ptr.clone());
+                evicted.push(ptr);
+            }
+        }
+        Ok(evicted)
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// Eviction policy which keeps the nodes in the top levels of the tree resident
+/// and uses another policy for all other nodes.
+///
+/// Pinned nodes do not count towards the capacity of the other policy. Nodes are
+/// pinned based on their level below the current root, as of the last time the
+/// cache synced or committed nodes.
+pub struct PinTopLevelsPolicy<P: EvictionPolicy> {
+    levels: usize,
+    inner: P,
+    /// Nodes in the top levels of the tree as of the last root update.
+    top: HashMap<usize, NodePtrRef>,
+    /// Tracked nodes which are pinned.
+    pinned: HashMap<usize, NodePtrRef>,
+    pinned_size: usize,
+}
+
+impl<P: EvictionPolicy> PinTopLevelsPolicy<P> {
+    /// Construct a new policy pinning the given number of levels of nodes,
+    /// starting with the root, and using the given policy for all other nodes.
+    pub fn new(levels: usize, inner: P) -> Self {
+        Self {
+            levels,
+            inner,
+            top: HashMap::new(),
+            pinned: HashMap::new(),
+            pinned_size: 0,
+        }
+    }
+
+    fn collect_top(&mut self, ptr: &NodePtrRef, level: usize) {
+        if level >= self.levels {
+            return;
+        }
+        let node_ref = match ptr.borrow().node {
+            Some(ref node_ref) => node_ref.clone(),
+            None => return,
+        };
+        self.top.insert(ptr_key(ptr), ptr.clone());
+
+        let node = node_ref.borrow();
+        if let NodeBox::Internal(ref n) = *node {
+            self.collect_top(&n.leaf_node, level + 1);
+            self.collect_top(&n.left, level + 1);
+            self.collect_top(&n.right, level + 1);
+        }
+    }
+
+    fn pin(&mut self, ptr: NodePtrRef) {
+        self.pinned_size += ptr.borrow().get_cached_size();
+        self.pinned.insert(ptr_key(&ptr), ptr);
+    }
+}
+
+impl<P: EvictionPolicy> EvictionPolicy for PinTopLevelsPolicy<P> {
+    fn add(&mut self, ptr: NodePtrRef) {
+        let key = ptr_key(&ptr);
+        if self.pinned.contains_key(&key) {
+            return;
+        }
+        if self.top.contains_key(&key) {
+            self.inner.remove(ptr.clone());
+            self.pin(ptr);
+            return;
+        }
+        self.inner.add(ptr)
+    }
+
+    fn use_node(&mut self, ptr: NodePtrRef) -> bool {
+        if self.pinned.contains_key(&ptr_key(&ptr)) {
+            return true;
+        }
+        self.inner.use_node(ptr)
+    }
+
+    fn remove(&mut self, ptr: NodePtrRef) -> bool {
+        match self.pinned.remove(&ptr_key(&ptr)) {
+            Some(ptr) => {
+                self.pinned_size -= ptr.borrow().get_cached_size();
+                true
+            }
+            None => self.inner.remove(ptr),
+        }
+    }
+
+    fn evict_for(
+        &mut self,
+        size: usize,
+        locked_ptr: Option<&NodePtrRef>,
+    ) -> Result<Vec<NodePtrRef>, RemoveLockedError> {
+        let mut evicted = Vec::new();
+        loop {
+            // Nodes which moved into the top levels since they were added are
+            // pinned instead of being evicted.
+            let mut pinned = false;
+            for ptr in self.inner.evict_for(size, locked_ptr)? {
+                if self.top.contains_key(&ptr_key(&ptr)) {
+                    self.pin(ptr);
+                    pinned = true;
+                } else {
+                    evicted.push(ptr);
+                }
+            }
+            if !pinned {
+                return Ok(evicted);
+            }
+        }
+    }
+
+    fn mark(&mut self) {
+        self.inner.mark()
+    }
+
+    fn root_updated(&mut self, root: &NodePtrRef) {
+        self.top.clear();
+        self.collect_top(root, 0);
+
+        // Unpin nodes which are no longer in the top levels.
+        let unpinned: Vec<usize> = self
+            .pinned
+            .keys()
+            .filter(|key| !self.top.contains_key(key))
+            .cloned()
+            .collect();
+        for key in unpinned {
+            let ptr = self.pinned.remove(&key).unwrap();
+            self.pinned_size -= ptr.borrow().get_cached_size();
+            self.inner.add(ptr);
+        }
+
+        self.inner.root_updated(root)
+    }
+
+    fn size(&self) -> usize {
+        self.inner.size() + self.pinned_size
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::mkvs::sync::*;
+
+    use io_context::Context;
+
+    use super::*;
+
+    fn leaf_ptr(key: &[u8]) -> NodePtrRef {
+        NodePointer::from_node(NodeBox::Leaf(LeafNode {
+            key: key.to_vec(),
+            value: vec![],
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_lfu_policy() {
+        let mut policy = LFUPolicy::new(3);
+        let ptrs: Vec<NodePtrRef> = (0..4u8).map(|i| leaf_ptr(&[i])).collect();
+        for ptr in &ptrs[..3] {
+            assert!(policy.evict_for(1, None).expect("evict_for").is_empty());
+            policy.add(ptr.clone());
+        }
+        assert_eq!(policy.size(), 3);
+
+        // The least frequently used node should be evicted, even if it was used last.
+        policy.use_node(ptrs[2].clone());
+        policy.use_node(ptrs[2].clone());
+        policy.use_node(ptrs[1].clone());
+        policy.use_node(ptrs[1].clone());
+        policy.use_node(ptrs[0].clone());
+        let evicted = policy.evict_for(1, None).expect("evict_for");
+        assert_eq!(evicted.len(), 1);
+        assert!(Rc::ptr_eq(&evicted[0], &ptrs[0]));
+        policy.add(ptrs[3].clone());
+
+        // Locked nodes should not be evicted.
+        assert!(policy.evict_for(1, Some(&ptrs[3])).is_err());
+        assert!(policy.remove(ptrs[3].clone()));
+        assert!(!policy.remove(ptrs[3].clone()));
+        assert_eq!(policy.size(), 2);
+    }
+
+    #[test]
+    fn test_pin_top_levels_policy() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..1000u32 {
+            tree.insert(
+                Context::background(),
+                format!("key {}", i).as_bytes(),
+                b"value",
+            )
+            .expect("insert");
+        }
+        let hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+
+        // Scan a remote tree with a small cache, which pins the top levels.
+        const LEVELS: usize = 5;
+        let remote_tree = Tree::make()
+            .with_capacity(16, 0)
+            .with_eviction_policy(|capacity| {
+                Box::new(PinTopLevelsPolicy::new(LEVELS, LRUPolicy::new(capacity)))
+            })
+            .with_root(Root {
+                root_type: RootType::State,
+                hash,
+                ..Default::default()
+            })
+            .new(Box::new(tree));
+        for i in 0..1000u32 {
+            assert_eq!(
+                remote_tree
+                    .get(Context::background(), format!("key {}", i).as_bytes())
+                    .expect("get"),
+                Some(b"value".to_vec())
+            );
+        }
+
+        // All nodes in the top levels should still be resident.
+        fn check_resident(ptr: &NodePtrRef, level: usize) -> usize {
+            if level >= LEVELS || ptr.borrow().is_null() {
+                return 0;
+            }
+            let node_ref = ptr
+                .borrow()
+                .node
+                .clone()
+                .expect("node in top levels should be resident");
+            let mut count = 1;
+            if let NodeBox::Internal(ref n) = *node_ref.borrow() {
+                count += check_resident(&n.leaf_node, level + 1);
+                count += check_resident(&n.left, level + 1);
+                count += check_resident(&n.right, level + 1);
+            }
+            count
+        }
+        let pending_root = remote_tree.cache.borrow().get_pending_root();
+        let pinned = check_resident(&pending_root, 0);
+        assert!(
+            pinned > 16,
+            "more nodes should be pinned than fit the cache"
+        );
+    }
+}
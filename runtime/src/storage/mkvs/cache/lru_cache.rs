@@ -1,184 +1,97 @@
-use std::{any::Any, cell::RefCell, pin::Pin, ptr::NonNull, rc::Rc, sync::Arc};
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::{HashSet, VecDeque},
+    mem,
+    rc::Rc,
+    sync::Arc,
+};
 
 use anyhow::{anyhow, Result};
-use intrusive_collections::{IntrusivePointer, LinkedList, LinkedListLink};
 use io_context::Context;
-use thiserror::Error;
 
 use crate::storage::mkvs::{cache::*, sync::*, tree::*};
 
-#[derive(Error, Debug)]
-#[error("mkvs: tried to remove locked node")]
-struct RemoveLockedError;
-
-#[derive(Clone, Default)]
-pub struct CacheItemBox<Item: CacheItem + Default> {
-    item: Rc<RefCell<Item>>,
-    link: LinkedListLink,
-}
-
-unsafe impl<T: CacheItem + Default> IntrusivePointer<CacheItemBox<T>>
-    for Pin<Box<CacheItemBox<T>>>
-{
-    #[inline]
-    fn into_raw(self) -> *const CacheItemBox<T> {
-        unsafe { Box::into_raw(Pin::into_inner_unchecked(self)) }
-    }
-    #[inline]
-    unsafe fn from_raw(ptr: *const CacheItemBox<T>) -> Self {
-        Box::into_pin(Box::from_raw(ptr as *mut CacheItemBox<T>))
-    }
-}
-
-intrusive_adapter!(
-    CacheItemAdapter<Item> = Pin<Box<CacheItemBox<Item>>>:
-        CacheItemBox<Item> { link: LinkedListLink }
-        where Item: CacheItem + Default
-);
-
-struct LRUList<V>
-where
-    V: CacheItem + Default,
-{
-    pub list: LinkedList<CacheItemAdapter<V>>,
-    pub size: usize,
-    pub capacity: usize,
-    pub mark: CacheExtra<V>,
+/// Bounded set of keys known to be absent from the tree.
+///
+/// When full, the oldest keys are evicted first.
+struct NegativeCache {
+    keys: HashSet<Key>,
+    order: VecDeque<Key>,
+    capacity: usize,
 }
 
-impl<V> LRUList<V>
-where
-    V: CacheItem + Default,
-{
-    pub fn new(capacity: usize) -> LRUList<V> {
-        LRUList {
-            list: LinkedList::new(CacheItemAdapter::new()),
-            size: 0,
-            capacity: capacity,
-            mark: None,
+impl NegativeCache {
+    fn new(capacity: usize) -> NegativeCache {
+        NegativeCache {
+            keys: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
         }
     }
 
-    fn mark(&mut self) {
-        self.mark = self.list.front().get().map(|front| {
-            front
-                .item
-                .borrow()
-                .get_cache_extra()
-                .expect("item was just retrieved from list, cache extra must exist")
-        });
-    }
-
-    fn add(&mut self, val: Rc<RefCell<V>>) {
-        let mut val_ref = val.borrow_mut();
-        if val_ref.get_cache_extra().is_none() {
-            self.size += val_ref.get_cached_size();
-            let mut item_box = Box::pin(CacheItemBox {
-                item: val.clone(),
-                link: LinkedListLink::new(),
-            });
-            val_ref.set_cache_extra(NonNull::new(&mut *item_box));
-            if let Some(non_null_pos) = &self.mark {
-                let mut pos_cursor =
-                    unsafe { self.list.cursor_mut_from_ptr(non_null_pos.as_ptr()) };
-                pos_cursor.insert_after(item_box);
-            } else {
-                self.list.push_front(item_box);
-            }
-        } else {
-            self.use_val(val.clone());
-        }
+    fn contains(&self, key: &[u8]) -> bool {
+        self.keys.contains(key)
     }
 
-    fn use_val(&mut self, val: Rc<RefCell<V>>) -> bool {
-        let val_ref = val.borrow();
-        match val_ref.get_cache_extra() {
-            None => false,
-            Some(non_null) => {
-                let mut item_cursor = unsafe { self.list.cursor_mut_from_ptr(non_null.as_ptr()) };
-                let removed_box = item_cursor.remove().unwrap();
-                self.list.push_front(removed_box);
-                true
-            }
+    fn insert(&mut self, key: &[u8]) {
+        if self.capacity == 0 || !self.keys.insert(key.to_vec()) {
+            return;
         }
-    }
-
-    fn remove(&mut self, val: Rc<RefCell<V>>) -> bool {
-        let extra = val.borrow().get_cache_extra();
-        match extra {
-            None => false,
-            Some(non_null) => {
-                if let Some(non_null_mark) = self.mark {
-                    if non_null.as_ptr() == non_null_mark.as_ptr() {
-                        self.mark = None;
-                    }
-                }
-
-                let mut item_cursor = unsafe { self.list.cursor_mut_from_ptr(non_null.as_ptr()) };
-                match item_cursor.remove() {
-                    None => false,
-                    Some(item_box) => {
-                        let mut val = item_box.item.borrow_mut();
-                        val.set_cache_extra(None);
-                        self.size -= val.get_cached_size();
-                        true
-                    }
-                }
-            }
+        self.order.push_back(key.to_vec());
+        while self.order.len() > self.capacity {
+            let evicted = self.order.pop_front().unwrap();
+            self.keys.remove(&evicted);
         }
     }
 
-    fn evict_for_val(
-        &mut self,
-        val: Rc<RefCell<V>>,
-        locked_val: Option<&Rc<RefCell<V>>>,
-    ) -> Result<Vec<Rc<RefCell<V>>>, RemoveLockedError> {
-        let mut evicted: Vec<Rc<RefCell<V>>> = Vec::new();
-        if self.capacity > 0 {
-            let target_size = val.borrow().get_cached_size();
-            while !self.list.is_empty() && self.size + target_size > self.capacity {
-                let back = (*self.list.back().get().unwrap()).item.clone();
-                if let Some(locked_val) = locked_val {
-                    if back.as_ptr() == locked_val.as_ptr() {
-                        return Err(RemoveLockedError);
-                    }
-                }
-                if self.remove(back.clone()) {
-                    evicted.push(back);
-                }
-            }
-        }
-        Ok(evicted)
+    fn remove(&mut self, key: &[u8]) {
+        // The key is left in the eviction queue, where it may cause a later
+        // entry for the same key to be evicted early.
+        self.keys.remove(key);
     }
 }
 
-/// Cache implementation with a simple LRU eviction strategy.
+/// Cache implementation with pluggable eviction policies, using LRU eviction
+/// by default.
 pub struct LRUCache {
     read_syncer: Box<dyn ReadSync>,
+    node_cache: Option<NodeCache>,
 
     pending_root: NodePtrRef,
     sync_root: Root,
 
-    lru_leaf: LRUList<NodePointer>,
-    lru_internal: LRUList<NodePointer>,
+    leaf_policy: Box<dyn EvictionPolicy>,
+    internal_policy: Box<dyn EvictionPolicy>,
+    negative: NegativeCache,
+
+    /// Whether nodes were installed from the node cache since the eviction
+    /// policies were last notified.
+    root_update_pending: bool,
 }
 
 impl LRUCache {
     /// Construct a new cache instance.
     ///
-    /// * `node_capacity` is the maximum number of internal nodes held by the
-    ///   cache before eviction.
-    /// * `value_capacity` is the total size, in bytes, of values held
-    ///   by the cache before eviction.
+    /// * `internal_policy` is the eviction policy used for internal nodes.
+    /// * `leaf_policy` is the eviction policy used for leaf nodes.
+    /// * `negative_capacity` is the maximum number of keys known to be
+    ///   absent held by the cache before eviction. If set to 0, absent keys
+    ///   are not cached.
     /// * `read_syncer` is the read syncer used as backing for the cache.
+    /// * `node_cache` is an optional shared node cache consulted before
+    ///   fetching nodes from the read syncer.
     pub fn new(
-        node_capacity: usize,
-        value_capacity: usize,
+        internal_policy: Box<dyn EvictionPolicy>,
+        leaf_policy: Box<dyn EvictionPolicy>,
+        negative_capacity: usize,
         read_syncer: Box<dyn ReadSync>,
+        node_cache: Option<NodeCache>,
         root_type: RootType,
     ) -> Box<LRUCache> {
         Box::new(LRUCache {
             read_syncer: read_syncer,
+            node_cache,
 
             pending_root: Rc::new(RefCell::new(NodePointer {
                 node: None,
@@ -189,11 +102,35 @@ impl LRUCache {
                 ..Default::default()
             },
 
-            lru_leaf: LRUList::new(value_capacity),
-            lru_internal: LRUList::new(node_capacity),
+            leaf_policy,
+            internal_policy,
+            negative: NegativeCache::new(negative_capacity),
+            root_update_pending: false,
         })
     }
 
+    /// Check whether the given key is known to be absent from the tree.
+    pub fn is_absent(&self, key: &[u8]) -> bool {
+        self.negative.contains(key)
+    }
+
+    /// Record that the given key is absent from the tree.
+    pub fn record_absent(&mut self, key: &[u8]) {
+        self.negative.insert(key)
+    }
+
+    /// Forget that the given key is absent from the tree. This must be called
+    /// before the key is inserted into the tree.
+    pub fn forget_absent(&mut self, key: &[u8]) {
+        self.negative.remove(key)
+    }
+
+    /// Replace the read syncer used as backing for the cache, returning the
+    /// previous one.
+    pub fn replace_read_syncer(&mut self, read_syncer: Box<dyn ReadSync>) -> Box<dyn ReadSync> {
+        mem::replace(&mut self.read_syncer, read_syncer)
+    }
+
     fn new_internal_node_ptr(&mut self, node: Option<NodeRef>) -> NodePtrRef {
         Rc::new(RefCell::new(NodePointer {
             node: node,
@@ -226,25 +163,30 @@ impl LRUCache {
         match classify_noderef!(? ptr.borrow().node) {
             NodeKind::Internal => {
                 let evicted = self
-                    .lru_internal
-                    .evict_for_val(ptr.clone(), locked_ptr.clone())?;
+                    .internal_policy
+                    .evict_for(ptr.borrow().get_cached_size(), locked_ptr.clone())?;
                 for node in evicted {
                     self.try_remove_node(node.clone(), locked_ptr.clone())?;
                 }
-                self.lru_internal.add(ptr.clone());
+                self.internal_policy.add(ptr.clone());
             }
             NodeKind::Leaf => {
                 let evicted = self
-                    .lru_leaf
-                    .evict_for_val(ptr.clone(), locked_ptr.clone())?;
+                    .leaf_policy
+                    .evict_for(ptr.borrow().get_cached_size(), locked_ptr.clone())?;
                 for node in evicted {
                     self.try_remove_node(node.clone(), locked_ptr.clone())?;
                 }
-                self.lru_leaf.add(ptr.clone());
+                self.leaf_policy.add(ptr.clone());
             }
             NodeKind::None => return Ok(()),
         };
 
+        if let Some(ref node_cache) = self.node_cache {
+            // The shared node cache is only an optimization, so ignore failures.
+            let _ = node_cache.insert(&ptr.borrow().get_node().borrow());
+        }
+
         Ok(())
     }
 
@@ -306,11 +248,11 @@ impl LRUCache {
 
             match classify_noderef!(? top.0.borrow().node) {
                 NodeKind::Internal => {
-                    self.lru_internal.remove(top.0.clone());
+                    self.internal_policy.remove(top.0.clone());
                     top.0.borrow_mut().node = None;
                 }
                 NodeKind::Leaf => {
-                    self.lru_leaf.remove(top.0.clone());
+                    self.leaf_policy.remove(top.0.clone());
                     top.0.borrow_mut().node = None;
                 }
                 NodeKind::None => {}
@@ -320,6 +262,35 @@ impl LRUCache {
         Ok(())
     }
 
+    fn fetch_from_node_cache(&mut self, ptr: NodePtrRef) -> Result<bool> {
+        let node = match self.node_cache {
+            Some(ref node_cache) => node_cache.get(&ptr.borrow().hash)?,
+            None => None,
+        };
+        let node = match node {
+            // Only install nodes which match the pointer, otherwise fall back
+            // to the read syncer which verifies what it returns.
+            Some(node) if node.get_hash() == ptr.borrow().hash => node,
+            _ => return Ok(false),
+        };
+
+        ptr.borrow_mut().node = Some(Rc::new(RefCell::new(node)));
+        let committed = self.commit_merged_node(ptr.clone(), &ptr).is_ok();
+        // Policies are notified once at the start of the next operation instead
+        // of for every node, as each lookup may install many of them.
+        self.root_update_pending = true;
+        Ok(committed)
+    }
+
+    /// Notify the eviction policies that nodes reachable from the pending root
+    /// have been synced or committed.
+    fn notify_root_updated(&mut self) {
+        self.root_update_pending = false;
+        let pending_root = self.pending_root.clone();
+        self.internal_policy.root_updated(&pending_root);
+        self.leaf_policy.root_updated(&pending_root);
+    }
+
     fn commit_merged_node(
         &mut self,
         ptr: NodePtrRef,
@@ -358,8 +329,8 @@ impl Cache for LRUCache {
 
     fn stats(&self) -> CacheStats {
         CacheStats {
-            internal_node_count: self.lru_internal.size,
-            leaf_value_size: self.lru_leaf.size,
+            internal_node_count: self.internal_policy.size(),
+            leaf_value_size: self.leaf_policy.size(),
         }
     }
 
@@ -377,6 +348,7 @@ impl Cache for LRUCache {
 
     fn set_sync_root(&mut self, root: Root) {
         self.sync_root = root;
+        self.notify_root_updated();
     }
 
     fn get_read_syncer(&self) -> &Box<dyn ReadSync> {
@@ -451,7 +423,12 @@ impl Cache for LRUCache {
             drop(ptr);
         }
 
-        // Node not available locally, fetch from read syncer.
+        // Node not available locally, try the shared node cache first.
+        if self.fetch_from_node_cache(ptr_ref.clone())? {
+            return Ok(ptr_ref.borrow().node.clone());
+        }
+
+        // Fetch from read syncer.
         if let Some(fetcher) = fetcher {
             self.remote_sync(ctx, ptr_ref.clone(), fetcher)?;
         } else {
@@ -503,7 +480,22 @@ impl Cache for LRUCache {
 
         // Merge resulting nodes.
         let mut merged_nodes: Vec<NodePtrRef> = Vec::new();
-        merge_verified_subtree(dst_ptr, subtree, &mut merged_nodes)?;
+        let replaced = {
+            // A clean pending root with a different hash has been replaced as a
+            // result of local modifications (e.g., a removal collapsing the tree).
+            let dst = dst_ptr.borrow();
+            dst.clean && !dst.is_null() && dst.hash != expected_root
+        };
+        if replaced {
+            merge_verified_subtree_by_hash(dst_ptr, subtree.clone(), &mut merged_nodes)?;
+        } else {
+            merge_verified_subtree(dst_ptr, subtree.clone(), &mut merged_nodes)?;
+        }
+        if ptr.borrow().node.is_none() {
+            // The pending root has been modified and the pointer might not have
+            // been reachable from it, so merge into the pointer directly.
+            merge_verified_subtree_by_hash(ptr.clone(), subtree, &mut merged_nodes)?;
+        }
         let mut remove = false;
         for node_ref in merged_nodes {
             if remove {
@@ -516,14 +508,15 @@ impl Cache for LRUCache {
                 remove = true;
             }
         }
+        self.notify_root_updated();
 
         Ok(())
     }
 
     fn use_node(&mut self, ptr: NodePtrRef) -> bool {
         match classify_noderef!(? ptr.borrow().node) {
-            NodeKind::Internal => self.lru_internal.use_val(ptr),
-            NodeKind::Leaf => self.lru_leaf.use_val(ptr),
+            NodeKind::Internal => self.internal_policy.use_node(ptr),
+            NodeKind::Leaf => self.leaf_policy.use_node(ptr),
             NodeKind::None => false,
         }
     }
@@ -534,23 +527,20 @@ impl Cache for LRUCache {
     }
 
     fn rollback_node(&mut self, ptr: NodePtrRef, kind: NodeKind) {
-        if ptr.borrow().get_cache_extra().is_none() {
-            // Node has not yet been committed to cache.
-            return;
-        }
-
-        let lru = match kind {
-            NodeKind::Internal => &mut self.lru_internal,
-            NodeKind::Leaf => &mut self.lru_leaf,
+        // Nodes which have not yet been committed to cache are ignored.
+        let policy = match kind {
+            NodeKind::Internal => &mut self.internal_policy,
+            NodeKind::Leaf => &mut self.leaf_policy,
             NodeKind::None => panic!("lru_cache: rollback works only for Internal and Leaf nodes!"),
         };
-        lru.remove(ptr.clone());
-
-        ptr.borrow_mut().set_cache_extra(None);
+        policy.remove(ptr);
     }
 
     fn mark_position(&mut self) {
-        self.lru_internal.mark();
-        self.lru_leaf.mark();
+        if self.root_update_pending {
+            self.notify_root_updated();
+        }
+        self.internal_policy.mark();
+        self.leaf_policy.mark();
     }
 }
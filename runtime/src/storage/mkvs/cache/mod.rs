@@ -1,5 +1,9 @@
 mod cache;
+mod eviction;
 mod lru_cache;
+mod node_cache;
 
 pub use cache::*;
+pub use eviction::*;
 pub use lru_cache::*;
+pub use node_cache::*;
@@ -0,0 +1,247 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{marshal::*, tree::*},
+};
+
+struct Entry {
+    data: Vec<u8>,
+    value_size: usize,
+    last_used: u64,
+}
+
+struct NodeCacheInner {
+    node_capacity: usize,
+    value_capacity: usize,
+    value_size: usize,
+    entries: HashMap<Hash, Entry>,
+    /// Node hashes ordered by their last use.
+    lru: BTreeMap<u64, Hash>,
+    clock: u64,
+}
+
+impl NodeCacheInner {
+    fn touch(&mut self, hash: &Hash) -> Option<&Entry> {
+        let entry = self.entries.get_mut(hash)?;
+        self.clock += 1;
+        self.lru.remove(&entry.last_used);
+        self.lru.insert(self.clock, *hash);
+        entry.last_used = self.clock;
+        Some(entry)
+    }
+
+    fn is_full(&self, value_size: usize) -> bool {
+        (self.node_capacity > 0 && self.entries.len() >= self.node_capacity)
+            || (self.value_capacity > 0 && self.value_size + value_size > self.value_capacity)
+    }
+
+    fn evict(&mut self) -> bool {
+        let (last_used, evicted) = match self.lru.iter().next() {
+            Some((last_used, evicted)) => (*last_used, *evicted),
+            None => return false,
+        };
+        self.lru.remove(&last_used);
+        if let Some(entry) = self.entries.remove(&evicted) {
+            self.value_size -= entry.value_size;
+        }
+        true
+    }
+}
+
+/// Return the size of the value stored in the given node.
+///
+/// Internal nodes are stored together with their leaf node, so the size of
+/// its value is included.
+fn value_size(node: &NodeBox) -> usize {
+    match node {
+        NodeBox::Leaf(ref n) => n.value.len(),
+        NodeBox::Internal(ref n) => match n.leaf_node.borrow().node {
+            Some(ref leaf) => value_size(&leaf.borrow()),
+            None => 0,
+        },
+    }
+}
+
+/// A content-addressed node cache which can be shared between trees.
+///
+/// Since nodes are addressed by their hash, a node cached while reading one
+/// root can be reused by trees opened at any other root that contains the same
+/// node, for example subtrees that did not change between two versions. Trees
+/// consult the node cache before fetching nodes from their read syncer and add
+/// all nodes they commit into their own cache to it.
+#[derive(Clone)]
+pub struct NodeCache {
+    inner: Arc<Mutex<NodeCacheInner>>,
+}
+
+impl NodeCache {
+    /// Create a new node cache.
+    ///
+    /// * `node_capacity` is the maximum number of nodes held by the cache
+    ///   before eviction.
+    /// * `value_capacity` is the total size, in bytes, of values held by the
+    ///   cache before eviction.
+    ///
+    /// If set to 0, the relevant capacity is unlimited.
+    pub fn new(node_capacity: usize, value_capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(NodeCacheInner {
+                node_capacity,
+                value_capacity,
+                value_size: 0,
+                entries: HashMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+            })),
+        }
+    }
+
+    /// Return the number of nodes in the cache.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    /// Check whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Look up the node with the given hash.
+    pub fn get(&self, hash: &Hash) -> Result<Option<NodeBox>> {
+        let mut inner = self.inner.lock().unwrap();
+        let entry = match inner.touch(hash) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let mut node = NodeBox::default();
+        node.unmarshal_binary(&entry.data)?;
+        Ok(Some(node))
+    }
+
+    /// Add a clean node to the cache.
+    pub fn insert(&self, node: &NodeBox) -> Result<()> {
+        let hash = node.get_hash();
+        let mut inner = self.inner.lock().unwrap();
+        if inner.touch(&hash).is_some() {
+            return Ok(());
+        }
+        let data = node.marshal_binary()?;
+        let value_size = value_size(node);
+        if inner.value_capacity > 0 && value_size > inner.value_capacity {
+            // Values which could never fit are not cached.
+            return Ok(());
+        }
+
+        while inner.is_full(value_size) && inner.evict() {}
+
+        inner.clock += 1;
+        let last_used = inner.clock;
+        inner.value_size += value_size;
+        inner.lru.insert(last_used, hash);
+        inner.entries.insert(
+            hash,
+            Entry {
+                data,
+                value_size,
+                last_used,
+            },
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    fn leaf(key: &[u8]) -> NodeBox {
+        let mut node = NodeBox::Leaf(LeafNode {
+            clean: true,
+            version: 1,
+            key: key.to_vec(),
+            value: b"value".to_vec(),
+            ..Default::default()
+        });
+        node.update_hash();
+        node
+    }
+
+    #[test]
+    fn test_node_cache_eviction() {
+        let cache = NodeCache::new(2, 0);
+        let (a, b, c) = (leaf(b"a"), leaf(b"b"), leaf(b"c"));
+
+        cache.insert(&a).unwrap();
+        cache.insert(&b).unwrap();
+        assert_eq!(cache.len(), 2);
+
+        // Using a should cause b to be evicted first.
+        let cached = cache
+            .get(&a.get_hash())
+            .unwrap()
+            .expect("a should be cached");
+        assert_eq!(cached.get_hash(), a.get_hash());
+        cache.insert(&c).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&b.get_hash()).unwrap().is_none());
+        assert!(cache.get(&a.get_hash()).unwrap().is_some());
+        assert!(cache.get(&c.get_hash()).unwrap().is_some());
+
+        // Internal nodes are stored together with their leaf node.
+        let mut internal = NodeBox::Internal(InternalNode {
+            clean: true,
+            version: 1,
+            leaf_node: Rc::new(RefCell::new(NodePointer {
+                clean: true,
+                hash: a.get_hash(),
+                node: Some(Rc::new(RefCell::new(leaf(b"a")))),
+                ..Default::default()
+            })),
+            left: NodePointer::null_ptr(),
+            right: NodePointer::null_ptr(),
+            ..Default::default()
+        });
+        internal.update_hash();
+        cache.insert(&internal).unwrap();
+        let cached = cache
+            .get(&internal.get_hash())
+            .unwrap()
+            .expect("internal node should be cached");
+        assert_eq!(cached.get_hash(), internal.get_hash());
+        match cached {
+            NodeBox::Internal(ref n) => assert!(n.leaf_node.borrow().node.is_some()),
+            _ => panic!("expected an internal node"),
+        }
+    }
+
+    #[test]
+    fn test_node_cache_value_eviction() {
+        // Each leaf holds a 5-byte value, so only two of them fit.
+        let cache = NodeCache::new(0, 12);
+        let (a, b, c) = (leaf(b"a"), leaf(b"b"), leaf(b"c"));
+
+        cache.insert(&a).unwrap();
+        cache.insert(&b).unwrap();
+        assert_eq!(cache.len(), 2);
+        cache.insert(&c).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&a.get_hash()).unwrap().is_none());
+        assert!(cache.get(&b.get_hash()).unwrap().is_some());
+        assert!(cache.get(&c.get_hash()).unwrap().is_some());
+
+        // Values larger than the capacity are never cached.
+        let cache = NodeCache::new(0, 4);
+        cache.insert(&a).unwrap();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Result};
+use io_context::Context;
+use serde_cbor;
+use snap;
+
+use crate::{
+    common::{cbor, crypto::hash::Hash},
+    storage::mkvs::{self, cache::Cache, checkpoint::*, sync::*, tree::*},
+};
+
+/// Create a single checkpoint chunk with the entries of the tree at the given
+/// root, starting at the given offset, and write it to the given writer.
+///
+/// Entries are added to the chunk until its proof reaches the chunk size. Returns
+/// the chunk digest and the offset of the next chunk, or `None` in case there are
+/// no more entries.
+pub fn create_chunk<W: Write>(
+    ctx: Context,
+    tree: &Tree,
+    root: Root,
+    offset: &[u8],
+    chunk_size: u64,
+    mut w: W,
+) -> Result<(Hash, Option<Key>)> {
+    use mkvs::Iterator;
+
+    {
+        let pending_root = tree.cache.borrow().get_pending_root();
+        let pending_root = pending_root.borrow();
+        if !pending_root.clean || pending_root.hash != root.hash || tree.root_type != root.root_type
+        {
+            return Err(CheckpointError::RootMismatch.into());
+        }
+    }
+
+    let mut it = tree.iter(ctx).with_proof(root.hash);
+    let mut next_offset: Option<Key> = None;
+
+    // Build the chunk until the proof becomes too large or we have reached the end. Always
+    // make progress past the offset, even if a single entry exceeds the chunk size.
+    it.seek(offset);
+    while it.is_valid() {
+        let proof_size = it
+            .get_proof_builder()
+            .expect("iterator has a proof builder")
+            .size();
+        let progressed = next_offset.as_ref().map_or(false, |key| &key[..] != offset);
+        if proof_size >= chunk_size && progressed {
+            break;
+        }
+
+        next_offset = it.get_key().clone();
+        mkvs::Iterator::next(&mut it);
+    }
+    if let Some(error) = it.error() {
+        return Err(anyhow!("chunk: failed to iterate: {}", error));
+    }
+    if !it.is_valid() {
+        // We have finished iterating.
+        next_offset = None;
+    }
+
+    // Build our chunk.
+    let proof = it.get_proof()?;
+    let mut data = Vec::new();
+    {
+        let mut sw = snap::write::FrameEncoder::new(&mut data);
+        for entry in &proof.entries {
+            sw.write_all(&cbor::to_vec(entry))?;
+        }
+        sw.flush()?;
+    }
+    w.write_all(&data)?;
+
+    Ok((Hash::digest_bytes(&data), next_offset))
+}
+
+/// Create a checkpoint of the tree at the given root, splitting it into chunks
+/// based on the given chunk size.
+///
+/// The `chunk_writer` is called with the index of each chunk in order and must
+/// return the writer the chunk should be written to.
+pub fn create_checkpoint<F, W>(
+    ctx: Context,
+    tree: &Tree,
+    root: Root,
+    chunk_size: u64,
+    mut chunk_writer: F,
+) -> Result<Metadata>
+where
+    F: FnMut(u64) -> Result<W>,
+    W: Write,
+{
+    let ctx = ctx.freeze();
+    let mut chunks = Vec::new();
+    let mut offset = Key::new();
+    loop {
+        let w = chunk_writer(chunks.len() as u64)?;
+        let (digest, next_offset) = create_chunk(
+            Context::create_child(&ctx),
+            tree,
+            root,
+            &offset,
+            chunk_size,
+            w,
+        )?;
+        chunks.push(digest);
+
+        match next_offset {
+            Some(next_offset) => offset = next_offset,
+            None => break,
+        }
+    }
+
+    Ok(Metadata {
+        version: CHECKPOINT_VERSION,
+        root,
+        chunks,
+    })
+}
+
+/// Decode a checkpoint chunk and verify it against the chunk metadata, returning
+/// the subtree containing the nodes included in the chunk.
+pub(super) fn verify_chunk<R: Read>(
+    ctx: Context,
+    chunk: &ChunkMetadata,
+    mut r: R,
+) -> Result<NodePtrRef> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+
+    // Verify overall chunk integrity.
+    let digest = Hash::digest_bytes(&data);
+    if digest != chunk.digest {
+        return Err(CheckpointError::ChunkCorrupted {
+            expected: chunk.digest,
+            got: digest,
+        }
+        .into());
+    }
+
+    // Reconstruct the proof. Treat decode errors after integrity verification as proof
+    // verification failures.
+    let mut proof = Proof {
+        untrusted_root: chunk.root.hash,
+        entries: Vec::new(),
+    };
+    let sr = snap::read::FrameDecoder::new(&data[..]);
+    for entry in serde_cbor::Deserializer::from_reader(sr).into_iter::<Option<RawProofEntry>>() {
+        let entry = entry.map_err(|err| {
+            CheckpointError::ChunkProofVerificationFailed(format!(
+                "failed to decode chunk: {}",
+                err
+            ))
+        })?;
+        proof.entries.push(entry);
+    }
+
+    // Verify the proof.
+    let pv = ProofVerifier;
+    pv.verify_proof(ctx, chunk.root.hash, &proof)
+        .map_err(|err| CheckpointError::ChunkProofVerificationFailed(err.to_string()).into())
+}
@@ -0,0 +1,25 @@
+use thiserror::Error;
+
+use crate::common::crypto::hash::Hash;
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("checkpoint: unsupported checkpoint version {0}")]
+    UnsupportedVersion(u16),
+    #[error("checkpoint: tree is not committed at the checkpoint root")]
+    RootMismatch,
+    #[error("checkpoint: chunk not found")]
+    ChunkNotFound,
+    #[error("checkpoint: restore already in progress")]
+    RestoreAlreadyInProgress,
+    #[error("checkpoint: no restore in progress")]
+    NoRestoreInProgress,
+    #[error("checkpoint: chunk already restored")]
+    ChunkAlreadyRestored,
+    #[error("chunk: chunk proof verification failed: {0}")]
+    ChunkProofVerificationFailed(String),
+    #[error("chunk: corrupted chunk: digest incorrect (expected: {expected:?} got: {got:?})")]
+    ChunkCorrupted { expected: Hash, got: Hash },
+    #[error("checkpoint: restored tree is incomplete")]
+    IncompleteCheckpoint,
+}
@@ -0,0 +1,217 @@
+//! State checkpoints for MKVS trees.
+//!
+//! Checkpoints use the same format as the Go implementation, so chunks
+//! created by either implementation can be restored by the other.
+mod chunk;
+mod errors;
+mod restorer;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::{cbor, crypto::hash::Hash},
+    storage::mkvs::tree::Root,
+};
+
+pub use chunk::*;
+pub use errors::*;
+pub use restorer::*;
+
+/// Version of the checkpoint format.
+pub const CHECKPOINT_VERSION: u16 = 1;
+
+/// Checkpoint chunk metadata.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChunkMetadata {
+    pub version: u16,
+    pub root: Root,
+    pub index: u64,
+    pub digest: Hash,
+}
+
+/// Checkpoint metadata.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub version: u16,
+    pub root: Root,
+    pub chunks: Vec<Hash>,
+}
+
+impl Metadata {
+    /// Return the encoded cryptographic hash of the checkpoint metadata.
+    pub fn encoded_hash(&self) -> Hash {
+        Hash::digest_bytes(&cbor::to_vec(self))
+    }
+
+    /// Return the chunk metadata for the corresponding chunk.
+    pub fn get_chunk_metadata(&self, index: u64) -> Result<ChunkMetadata, CheckpointError> {
+        let digest = self
+            .chunks
+            .get(index as usize)
+            .ok_or(CheckpointError::ChunkNotFound)?;
+
+        Ok(ChunkMetadata {
+            version: self.version,
+            root: self.root,
+            index,
+            digest: *digest,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path, sync::Arc};
+
+    use io_context::Context;
+    use tempfile;
+
+    use crate::{
+        common::namespace::Namespace,
+        storage::mkvs::{
+            db::{FileNodeDB, NodeDB, NodeDBReadSyncer},
+            sync::NoopReadSyncer,
+            tree::{RootType, Tree},
+        },
+    };
+
+    use super::*;
+
+    const NUM_KEYS: usize = 1000;
+
+    fn build_tree(value_suffix: &str) -> (Tree, Root) {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..NUM_KEYS {
+            let key = format!("key {}", i);
+            let value = format!("value {}{}", i, value_suffix);
+            tree.insert(Context::background(), key.as_bytes(), value.as_bytes())
+                .expect("insert");
+        }
+        let hash = tree
+            .commit(Context::background(), Namespace::default(), 1)
+            .expect("commit");
+        let root = Root {
+            namespace: Namespace::default(),
+            version: 1,
+            root_type: RootType::State,
+            hash,
+        };
+        (tree, root)
+    }
+
+    fn create_chunks(tree: &Tree, root: Root, dir: &Path) -> (Metadata, Vec<Vec<u8>>) {
+        let checkpoint = create_checkpoint(Context::background(), tree, root, 16 * 1024, |i| {
+            Ok(fs::File::create(dir.join(i.to_string()))?)
+        })
+        .expect("create_checkpoint");
+        let chunks = (0..checkpoint.chunks.len())
+            .map(|i| fs::read(dir.join(i.to_string())).expect("read chunk"))
+            .collect();
+        (checkpoint, chunks)
+    }
+
+    #[test]
+    fn test_checkpoint_restore() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let (tree, root) = build_tree("");
+        let (checkpoint, chunks) = create_chunks(&tree, root, dir.path());
+        assert_eq!(checkpoint.version, CHECKPOINT_VERSION);
+        assert_eq!(checkpoint.root, root);
+        assert!(chunks.len() > 1, "checkpoint should have multiple chunks");
+        for (digest, chunk) in checkpoint.chunks.iter().zip(chunks.iter()) {
+            assert_eq!(*digest, Hash::digest_bytes(chunk));
+        }
+
+        let db = Arc::new(FileNodeDB::open(dir.path().join("nodes.db")).expect("open"));
+        let mut restorer = Restorer::new(db.clone());
+        assert!(restorer.get_current_checkpoint().is_none());
+        restorer
+            .start_restore(checkpoint.clone())
+            .expect("start_restore");
+        assert_eq!(restorer.get_current_checkpoint(), Some(&checkpoint));
+        assert!(restorer.start_restore(checkpoint.clone()).is_err());
+
+        // Corrupted chunks should be rejected without aborting the restore.
+        let mut corrupted = chunks[0].clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+        let err = restorer
+            .restore_chunk(Context::background(), 0, &corrupted[..])
+            .expect_err("corrupted chunk should fail");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(CheckpointError::ChunkCorrupted { .. })
+        ));
+        assert!(restorer
+            .restore_chunk(Context::background(), chunks.len() as u64, &chunks[0][..])
+            .is_err());
+
+        // Restore chunks in reverse order.
+        for (index, chunk) in chunks.iter().enumerate().rev() {
+            assert!(!db.has_root(&root));
+            let done = restorer
+                .restore_chunk(Context::background(), index as u64, &chunk[..])
+                .expect("restore_chunk");
+            assert_eq!(done, index == 0);
+        }
+        assert!(restorer.get_current_checkpoint().is_none());
+        assert!(db.has_root(&root));
+
+        let restored = Tree::make()
+            .with_root(root)
+            .new(Box::new(NodeDBReadSyncer::new(db.clone())));
+        for i in 0..NUM_KEYS {
+            let key = format!("key {}", i);
+            let value = format!("value {}", i);
+            assert_eq!(
+                restored
+                    .get(Context::background(), key.as_bytes())
+                    .expect("get"),
+                Some(value.into_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_invalid() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let other_dir = tempfile::tempdir().expect("tempdir");
+        let (tree, root) = build_tree("");
+        let (other_tree, other_root) = build_tree(" other");
+
+        // Creating a checkpoint for a different root should fail.
+        let result = create_checkpoint(Context::background(), &tree, other_root, 1024, |_| {
+            Ok(Vec::new())
+        });
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(CheckpointError::RootMismatch)
+        ));
+
+        // Chunks which match the metadata but not the root should abort the restore.
+        let (_, other_chunks) = create_chunks(&other_tree, other_root, other_dir.path());
+        let (mut checkpoint, _) = create_chunks(&tree, root, dir.path());
+        checkpoint.chunks[0] = Hash::digest_bytes(&other_chunks[0]);
+
+        let db = Arc::new(FileNodeDB::open(dir.path().join("nodes.db")).expect("open"));
+        let mut restorer = Restorer::new(db);
+        restorer.start_restore(checkpoint).expect("start_restore");
+        let err = restorer
+            .restore_chunk(Context::background(), 0, &other_chunks[0][..])
+            .expect_err("invalid chunk should fail");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(CheckpointError::ChunkProofVerificationFailed(_))
+        ));
+        assert!(restorer.get_current_checkpoint().is_none());
+        let err = restorer
+            .restore_chunk(Context::background(), 0, &other_chunks[0][..])
+            .expect_err("restore should be aborted");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(CheckpointError::NoRestoreInProgress)
+        ));
+    }
+}
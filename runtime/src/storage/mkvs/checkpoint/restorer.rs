@@ -0,0 +1,155 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::Read,
+    mem,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use io_context::Context;
+
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{checkpoint::*, db::NodeDB, tree::*},
+};
+
+use super::chunk::verify_chunk;
+
+/// A checkpoint restorer.
+///
+/// Nodes from restored chunks are kept in memory until all chunks of the
+/// checkpoint have been restored. The complete tree is then persisted to the
+/// node database together with the checkpoint root, after which it can be
+/// opened using a `NodeDBReadSyncer`.
+pub struct Restorer {
+    ndb: Arc<dyn NodeDB>,
+    /// Checkpoint that is currently being restored, if any.
+    current_checkpoint: Option<Metadata>,
+    /// Indices of chunks which have not yet been restored.
+    pending_chunks: BTreeSet<u64>,
+    /// Nodes restored so far, indexed by their hash.
+    nodes: HashMap<Hash, NodeRef>,
+}
+
+impl Restorer {
+    /// Create a new checkpoint restorer which restores into the given node database.
+    pub fn new(ndb: Arc<dyn NodeDB>) -> Self {
+        Self {
+            ndb,
+            current_checkpoint: None,
+            pending_chunks: BTreeSet::new(),
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// Start a checkpoint restoration process.
+    pub fn start_restore(&mut self, checkpoint: Metadata) -> Result<()> {
+        if self.current_checkpoint.is_some() {
+            return Err(CheckpointError::RestoreAlreadyInProgress.into());
+        }
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(CheckpointError::UnsupportedVersion(checkpoint.version).into());
+        }
+
+        self.pending_chunks = (0..checkpoint.chunks.len() as u64).collect();
+        self.current_checkpoint = Some(checkpoint);
+
+        Ok(())
+    }
+
+    /// Abort a checkpoint restore in progress.
+    ///
+    /// It is not an error to call this method when no restore is in progress.
+    pub fn abort_restore(&mut self) {
+        self.current_checkpoint = None;
+        self.pending_chunks.clear();
+        self.nodes.clear();
+    }
+
+    /// Return the checkpoint that is being restored, if any.
+    pub fn get_current_checkpoint(&self) -> Option<&Metadata> {
+        self.current_checkpoint.as_ref()
+    }
+
+    /// Restore the chunk with the given index.
+    ///
+    /// In case the chunk matches the checkpoint metadata but fails proof
+    /// verification, the checkpoint itself is invalid and the restore is aborted.
+    ///
+    /// Returns true when the checkpoint has been fully restored.
+    pub fn restore_chunk<R: Read>(&mut self, ctx: Context, index: u64, r: R) -> Result<bool> {
+        let chunk = match self.current_checkpoint {
+            Some(ref checkpoint) => checkpoint.get_chunk_metadata(index)?,
+            None => return Err(CheckpointError::NoRestoreInProgress.into()),
+        };
+        if !self.pending_chunks.contains(&index) {
+            return Err(CheckpointError::ChunkAlreadyRestored.into());
+        }
+
+        let subtree = match verify_chunk(ctx, &chunk, r) {
+            Ok(subtree) => subtree,
+            Err(err) => {
+                if let Some(CheckpointError::ChunkProofVerificationFailed(_)) = err.downcast_ref() {
+                    self.abort_restore();
+                }
+                return Err(err);
+            }
+        };
+        collect_nodes(&subtree, &mut self.nodes);
+
+        // Mark the given chunk as restored.
+        self.pending_chunks.remove(&index);
+        if !self.pending_chunks.is_empty() {
+            return Ok(false);
+        }
+
+        // All chunks have been restored, persist the tree if it is complete.
+        let checkpoint = self.current_checkpoint.take().unwrap();
+        let nodes = mem::take(&mut self.nodes);
+        let mut reachable = Vec::new();
+        collect_reachable_nodes(checkpoint.root.hash, &nodes, &mut reachable)?;
+        self.ndb.commit(checkpoint.root, &reachable)?;
+
+        Ok(true)
+    }
+}
+
+/// Collect all nodes included in a verified subtree.
+fn collect_nodes(ptr: &NodePtrRef, nodes: &mut HashMap<Hash, NodeRef>) {
+    let ptr = ptr.borrow();
+    if let Some(ref node_ref) = ptr.node {
+        if let NodeBox::Internal(ref n) = *node_ref.borrow() {
+            collect_nodes(&n.leaf_node, nodes);
+            collect_nodes(&n.left, nodes);
+            collect_nodes(&n.right, nodes);
+        }
+        nodes.insert(ptr.hash, node_ref.clone());
+    }
+}
+
+/// Collect all nodes reachable from the given root, making sure that none are missing.
+fn collect_reachable_nodes(
+    root: Hash,
+    nodes: &HashMap<Hash, NodeRef>,
+    reachable: &mut Vec<NodeRef>,
+) -> Result<()> {
+    let mut visited = HashSet::new();
+    let mut stack = vec![root];
+    while let Some(hash) = stack.pop() {
+        if hash.is_empty() || !visited.insert(hash) {
+            continue;
+        }
+
+        let node_ref = nodes
+            .get(&hash)
+            .ok_or(CheckpointError::IncompleteCheckpoint)?;
+        if let NodeBox::Internal(ref n) = *node_ref.borrow() {
+            stack.push(n.leaf_node.borrow().hash);
+            stack.push(n.left.borrow().hash);
+            stack.push(n.right.borrow().hash);
+        }
+        reachable.push(node_ref.clone());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NodeDBError {
+    #[error("mkvs: node not found in node db")]
+    NodeNotFound,
+    #[error("mkvs: root not found")]
+    RootNotFound,
+    #[error("mkvs: malformed node db")]
+    Malformed,
+}
@@ -0,0 +1,477 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::Path,
+    rc::Rc,
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{
+    common::{cbor, crypto::hash::Hash, namespace::Namespace},
+    storage::mkvs::{db::*, marshal::*, tree::*},
+};
+
+/// Magic header at the start of every node database file.
+const MAGIC: &[u8; 8] = b"mkvsndb1";
+/// Record containing a marshalled node, prefixed by its hash.
+const RECORD_NODE: u8 = 0x01;
+/// Record containing a CBOR-serialized root.
+const RECORD_ROOT: u8 = 0x02;
+/// Size of the record header (kind and payload length).
+const RECORD_HEADER_SIZE: u64 = 1 + 4;
+
+struct Inner {
+    file: File,
+    /// Offset and length of each stored node's serialization.
+    nodes: HashMap<Hash, (u64, usize)>,
+    /// Committed roots, indexed by version.
+    roots: BTreeMap<u64, Vec<Root>>,
+    /// Offset at which the next record will be written.
+    end: u64,
+}
+
+impl Inner {
+    fn load(&mut self) -> Result<()> {
+        let len = self.file.metadata()?.len();
+        if len == 0 {
+            self.file.write_all(MAGIC)?;
+            self.file.sync_data()?;
+            self.end = MAGIC.len() as u64;
+            return Ok(());
+        }
+
+        let mut reader = BufReader::new(&self.file);
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| NodeDBError::Malformed)?;
+        if &magic != MAGIC {
+            return Err(NodeDBError::Malformed.into());
+        }
+
+        let mut offset = MAGIC.len() as u64;
+        loop {
+            let kind = match reader.read_u8() {
+                Ok(kind) => kind,
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            };
+            let size = match reader.read_u32::<LittleEndian>() {
+                Ok(size) => size as usize,
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            };
+            let mut payload = vec![0u8; size];
+            match reader.read_exact(&mut payload) {
+                Ok(()) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+
+            match kind {
+                RECORD_NODE => {
+                    if size < Hash::len() {
+                        return Err(NodeDBError::Malformed.into());
+                    }
+                    let hash = Hash::from(&payload[..Hash::len()]);
+                    let node_offset = offset + RECORD_HEADER_SIZE + Hash::len() as u64;
+                    self.nodes.insert(hash, (node_offset, size - Hash::len()));
+                }
+                RECORD_ROOT => {
+                    let root: Root = cbor::from_slice(&payload)?;
+                    self.roots.entry(root.version).or_default().push(root);
+                }
+                _ => return Err(NodeDBError::Malformed.into()),
+            }
+            offset += RECORD_HEADER_SIZE + size as u64;
+        }
+        drop(reader);
+
+        // Discard any partially written trailing record.
+        if offset < len {
+            self.file.set_len(offset)?;
+        }
+        self.end = offset;
+
+        Ok(())
+    }
+
+    fn append_record(buffer: &mut Vec<u8>, kind: u8, payload: &[&[u8]]) -> Result<()> {
+        let size: usize = payload.iter().map(|p| p.len()).sum();
+        buffer.write_u8(kind)?;
+        buffer.write_u32::<LittleEndian>(size as u32)?;
+        for p in payload {
+            buffer.extend_from_slice(p);
+        }
+        Ok(())
+    }
+}
+
+/// A node database backed by a single append-only file.
+///
+/// The file index is rebuilt when the database is opened, so this backend
+/// is meant for tooling and tests rather than for very large trees.
+pub struct FileNodeDB {
+    inner: Mutex<Inner>,
+}
+
+impl FileNodeDB {
+    /// Open (or create) a node database at the given path.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+        let mut inner = Inner {
+            file,
+            nodes: HashMap::new(),
+            roots: BTreeMap::new(),
+            end: 0,
+        };
+        inner.load()?;
+
+        Ok(Self {
+            inner: Mutex::new(inner),
+        })
+    }
+}
+
+impl NodeDB for FileNodeDB {
+    fn get_node(&self, hash: &Hash) -> Result<NodeRef> {
+        let mut inner = self.inner.lock().unwrap();
+        let (offset, size) = match inner.nodes.get(hash) {
+            Some(location) => *location,
+            None => return Err(NodeDBError::NodeNotFound.into()),
+        };
+
+        let mut data = vec![0u8; size];
+        inner.file.seek(SeekFrom::Start(offset))?;
+        inner.file.read_exact(&mut data)?;
+
+        let mut node = NodeBox::default();
+        node.unmarshal_binary(&data)?;
+        if node.get_hash() != *hash {
+            return Err(NodeDBError::Malformed.into());
+        }
+
+        Ok(Rc::new(RefCell::new(node)))
+    }
+
+    fn has_node(&self, hash: &Hash) -> Result<bool> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.nodes.contains_key(hash))
+    }
+
+    fn has_root(&self, root: &Root) -> bool {
+        let inner = self.inner.lock().unwrap();
+        match inner.roots.get(&root.version) {
+            Some(roots) => roots.contains(root),
+            None => false,
+        }
+    }
+
+    fn get_roots_for_version(&self, namespace: &Namespace, version: u64) -> Vec<Root> {
+        let inner = self.inner.lock().unwrap();
+        match inner.roots.get(&version) {
+            Some(roots) => roots
+                .iter()
+                .filter(|root| root.namespace == *namespace)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn commit(&self, root: Root, nodes: &[NodeRef]) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let mut buffer = Vec::new();
+        let mut added = HashMap::new();
+        for node_ref in nodes {
+            let node = node_ref.borrow();
+            if !node.is_clean() {
+                panic!("mkvs/db: attempted to persist a dirty node");
+            }
+            let hash = node.get_hash();
+            if inner.nodes.contains_key(&hash) || added.contains_key(&hash) {
+                continue;
+            }
+
+            let data = node.marshal_binary()?;
+            let node_offset =
+                inner.end + buffer.len() as u64 + RECORD_HEADER_SIZE + Hash::len() as u64;
+            added.insert(hash, (node_offset, data.len()));
+            Inner::append_record(&mut buffer, RECORD_NODE, &[hash.as_ref(), &data])?;
+        }
+
+        let already_committed = match inner.roots.get(&root.version) {
+            Some(roots) => roots.contains(&root),
+            None => false,
+        };
+        if !already_committed {
+            Inner::append_record(&mut buffer, RECORD_ROOT, &[&cbor::to_vec(&root)])?;
+        }
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let end = inner.end;
+        inner.file.seek(SeekFrom::Start(end))?;
+        inner.file.write_all(&buffer)?;
+        inner.file.sync_data()?;
+        inner.end += buffer.len() as u64;
+
+        inner.nodes.extend(added);
+        if !already_committed {
+            inner.roots.entry(root.version).or_default().push(root);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use anyhow::anyhow;
+
+    use io_context::Context;
+    use tempfile;
+
+    use crate::storage::mkvs::{sync::NoopReadSyncer, Iterator};
+
+    use super::*;
+
+    #[test]
+    fn test_file_node_db() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("nodes.db");
+        let namespace = Namespace::default();
+
+        let mut roots = Vec::new();
+        {
+            let db = Arc::new(FileNodeDB::open(&path).expect("open"));
+            let mut tree = Tree::make()
+                .with_root_type(RootType::State)
+                .with_node_db(db.clone())
+                .new(Box::new(NoopReadSyncer));
+            for version in 0..3u64 {
+                for i in 0..50 {
+                    let key = format!("key {}", i);
+                    let value = format!("value {} at {}", i, version);
+                    tree.insert(Context::background(), key.as_bytes(), value.as_bytes())
+                        .expect("insert");
+                }
+                let hash = tree
+                    .commit(Context::background(), namespace, version)
+                    .expect("commit");
+                let root = Root {
+                    namespace,
+                    version,
+                    root_type: RootType::State,
+                    hash,
+                };
+                assert!(db.has_root(&root));
+                assert!(db.has_node(&hash).expect("has_node"));
+                roots.push(root);
+            }
+        }
+
+        // Simulate a partially written trailing record.
+        let len = fs::metadata(&path).expect("metadata").len();
+        let mut file = OpenOptions::new().append(true).open(&path).expect("open");
+        file.write_all(&[RECORD_NODE, 0xff]).expect("write");
+        drop(file);
+
+        // Reopen the database and make sure all historical roots are available.
+        let db: Arc<dyn NodeDB> = Arc::new(FileNodeDB::open(&path).expect("reopen"));
+        assert_eq!(fs::metadata(&path).expect("metadata").len(), len);
+        for root in &roots {
+            assert!(db.has_root(root));
+            assert_eq!(
+                db.get_roots_for_version(&namespace, root.version),
+                vec![*root]
+            );
+
+            let tree = Tree::make()
+                .with_root(*root)
+                .new(Box::new(NodeDBReadSyncer::new(db.clone())));
+            for i in 0..50 {
+                let key = format!("key {}", i);
+                let value = format!("value {} at {}", i, root.version);
+                assert_eq!(
+                    tree.get(Context::background(), key.as_bytes())
+                        .expect("get"),
+                    Some(value.into_bytes())
+                );
+            }
+
+            let mut it = tree.iter(Context::background());
+            it.rewind();
+            let mut count = 0;
+            while it.is_valid() {
+                count += 1;
+                Iterator::next(&mut it);
+            }
+            assert!(it.error().is_none());
+            assert_eq!(count, 50);
+        }
+
+        // Unknown roots should be rejected.
+        let bogus_root = Root {
+            version: 10,
+            ..roots[0]
+        };
+        let tree = Tree::make()
+            .with_root(bogus_root)
+            .new(Box::new(NodeDBReadSyncer::new(db.clone())));
+        assert!(tree.get(Context::background(), b"key 0").is_err());
+    }
+
+    #[test]
+    fn test_file_node_db_missing_nodes() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let namespace = Namespace::default();
+
+        // Build a tree which is not backed by the node database.
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..50 {
+            let key = format!("key {}", i);
+            tree.insert(Context::background(), key.as_bytes(), b"value")
+                .expect("insert");
+        }
+        let hash = tree
+            .commit(Context::background(), namespace, 0)
+            .expect("commit");
+        let root = Root {
+            namespace,
+            version: 0,
+            root_type: RootType::State,
+            hash,
+        };
+
+        // Committing a root whose clean nodes are neither in the database nor available
+        // locally should fail without recording the root.
+        let db = Arc::new(FileNodeDB::open(dir.path().join("nodes.db")).expect("open"));
+        let mut remote_tree = Tree::make()
+            .with_root(root)
+            .with_node_db(db.clone())
+            .new(Box::new(tree));
+        remote_tree
+            .insert(Context::background(), b"key 0", b"new value")
+            .expect("insert");
+        assert!(remote_tree
+            .commit(Context::background(), namespace, 1)
+            .is_err());
+        assert!(db.get_roots_for_version(&namespace, 1).is_empty());
+        assert!(!db.has_node(&hash).expect("has_node"));
+
+        // Once all nodes are available locally, missing clean nodes should be persisted.
+        let mut it = remote_tree.iter(Context::background());
+        it.rewind();
+        while it.is_valid() {
+            Iterator::next(&mut it);
+        }
+        assert!(it.error().is_none());
+        drop(it);
+        let hash = remote_tree
+            .commit(Context::background(), namespace, 1)
+            .expect("commit");
+        let root = Root {
+            namespace,
+            version: 1,
+            root_type: RootType::State,
+            hash,
+        };
+        assert!(db.has_root(&root));
+
+        let tree = Tree::make()
+            .with_root(root)
+            .new(Box::new(NodeDBReadSyncer::new(db)));
+        for i in 1..50 {
+            let key = format!("key {}", i);
+            assert_eq!(
+                tree.get(Context::background(), key.as_bytes())
+                    .expect("get"),
+                Some(b"value".to_vec())
+            );
+        }
+    }
+
+    /// Node database using the default `has_node` which can be made to fail.
+    struct FaultyNodeDB {
+        inner: FileNodeDB,
+        fail: AtomicBool,
+    }
+
+    impl NodeDB for FaultyNodeDB {
+        fn get_node(&self, hash: &Hash) -> Result<NodeRef> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(anyhow!("injected failure"));
+            }
+            self.inner.get_node(hash)
+        }
+
+        fn has_root(&self, root: &Root) -> bool {
+            self.inner.has_root(root)
+        }
+
+        fn get_roots_for_version(&self, namespace: &Namespace, version: u64) -> Vec<Root> {
+            self.inner.get_roots_for_version(namespace, version)
+        }
+
+        fn commit(&self, root: Root, nodes: &[NodeRef]) -> Result<()> {
+            self.inner.commit(root, nodes)
+        }
+    }
+
+    #[test]
+    fn test_file_node_db_has_node_errors() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let namespace = Namespace::default();
+        let db = Arc::new(FaultyNodeDB {
+            inner: FileNodeDB::open(dir.path().join("nodes.db")).expect("open"),
+            fail: AtomicBool::new(false),
+        });
+
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .with_node_db(db.clone())
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..50 {
+            let key = format!("key {}", i);
+            tree.insert(Context::background(), key.as_bytes(), b"value")
+                .expect("insert");
+        }
+        let hash = tree
+            .commit(Context::background(), namespace, 0)
+            .expect("commit");
+        assert!(db.has_node(&hash).expect("has_node"));
+        assert!(!db
+            .has_node(&Hash::digest_bytes(b"missing"))
+            .expect("has_node"));
+
+        // Node database errors must not be treated as missing nodes.
+        db.fail.store(true, Ordering::SeqCst);
+        assert!(db.has_node(&hash).is_err());
+        tree.insert(Context::background(), b"key 0", b"new value")
+            .expect("insert");
+        assert!(tree.commit(Context::background(), namespace, 1).is_err());
+        assert!(db.get_roots_for_version(&namespace, 1).is_empty());
+    }
+}
@@ -0,0 +1,12 @@
+//! Persistent node database for MKVS trees.
+mod errors;
+#[cfg(not(target_env = "sgx"))]
+mod file;
+mod nodedb;
+mod syncer;
+
+pub use errors::*;
+#[cfg(not(target_env = "sgx"))]
+pub use file::*;
+pub use nodedb::*;
+pub use syncer::*;
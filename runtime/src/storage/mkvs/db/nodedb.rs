@@ -0,0 +1,43 @@
+use anyhow::Result;
+
+use super::NodeDBError;
+
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    storage::mkvs::tree::{NodeRef, Root},
+};
+
+/// A persistent database of MKVS nodes.
+///
+/// Nodes are content-addressed by their hash, so nodes shared between
+/// different versions of a tree are only stored once. Committed roots are
+/// indexed by namespace and version.
+pub trait NodeDB: Send + Sync {
+    /// Look up a node by its hash.
+    fn get_node(&self, hash: &Hash) -> Result<NodeRef>;
+
+    /// Check whether a node with the given hash is stored in the database.
+    ///
+    /// The default implementation looks up the node, implementations should
+    /// override it in case they can check for presence without reading it.
+    fn has_node(&self, hash: &Hash) -> Result<bool> {
+        match self.get_node(hash) {
+            Ok(_) => Ok(true),
+            Err(err) => match err.downcast_ref::<NodeDBError>() {
+                Some(NodeDBError::NodeNotFound) => Ok(false),
+                _ => Err(err),
+            },
+        }
+    }
+
+    /// Check whether the given root has been committed to the database.
+    fn has_root(&self, root: &Root) -> bool;
+
+    /// Return all roots committed under the given namespace and version.
+    fn get_roots_for_version(&self, namespace: &Namespace, version: u64) -> Vec<Root>;
+
+    /// Persist the given (clean) nodes and record the root they belong to.
+    ///
+    /// The root only becomes visible once all of its nodes have been persisted.
+    fn commit(&self, root: Root, nodes: &[NodeRef]) -> Result<()>;
+}
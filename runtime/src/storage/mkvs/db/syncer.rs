@@ -0,0 +1,111 @@
+use std::{any::Any, sync::Arc};
+
+use anyhow::Result;
+use io_context::Context;
+
+use crate::storage::mkvs::{cache::Cache, db::*, sync::*, tree::*};
+
+/// A read syncer which fetches individual nodes from a node database.
+///
+/// Every request is answered with a partial proof containing only the node
+/// at the requested position.
+struct NodeFetcher {
+    db: Arc<dyn NodeDB>,
+}
+
+impl NodeFetcher {
+    fn fetch(&self, ctx: Context, tree: TreeID) -> Result<ProofResponse> {
+        let node_ref = self.db.get_node(&tree.position)?;
+        let mut pb = ProofBuilder::new(tree.root.hash, tree.position);
+        pb.include(&node_ref.borrow());
+        Ok(ProofResponse {
+            proof: pb.build(ctx)?,
+        })
+    }
+}
+
+impl ReadSync for NodeFetcher {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        self.fetch(ctx, request.tree)
+    }
+
+    fn sync_get_many(&mut self, ctx: Context, request: GetManyRequest) -> Result<ProofResponse> {
+        self.fetch(ctx, request.tree)
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        self.fetch(ctx, request.tree)
+    }
+
+    fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        self.fetch(ctx, request.tree)
+    }
+}
+
+/// A read syncer serving proofs for roots committed to a node database.
+///
+/// This can be used as the read syncer of trees opened at historical roots.
+pub struct NodeDBReadSyncer {
+    db: Arc<dyn NodeDB>,
+    tree: Option<Tree>,
+}
+
+impl NodeDBReadSyncer {
+    /// Construct a new read syncer backed by the given node database.
+    pub fn new(db: Arc<dyn NodeDB>) -> Self {
+        Self { db, tree: None }
+    }
+
+    fn tree_for(&mut self, root: &Root) -> Result<&Tree> {
+        if let Some(ref tree) = self.tree {
+            if tree.cache.borrow().get_sync_root() == *root {
+                return Ok(self.tree.as_ref().unwrap());
+            }
+        }
+        if !self.db.has_root(root) {
+            return Err(NodeDBError::RootNotFound.into());
+        }
+
+        self.tree = Some(Tree::make().with_root(*root).new(Box::new(NodeFetcher {
+            db: self.db.clone(),
+        })));
+        Ok(self.tree.as_ref().unwrap())
+    }
+}
+
+impl ReadSync for NodeDBReadSyncer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        self.tree_for(&request.tree.root)?.sync_get(ctx, request)
+    }
+
+    fn sync_get_many(&mut self, ctx: Context, request: GetManyRequest) -> Result<ProofResponse> {
+        self.tree_for(&request.tree.root)?
+            .sync_get_many(ctx, request)
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        self.tree_for(&request.tree.root)?
+            .sync_get_prefixes(ctx, request)
+    }
+
+    fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        self.tree_for(&request.tree.root)?
+            .sync_iterate(ctx, request)
+    }
+}
@@ -0,0 +1,534 @@
+//! Transparently encrypted MKVS.
+use std::iter;
+
+use anyhow::{Error, Result};
+use io_context::Context;
+
+use crate::{
+    common::{
+        crypto::{
+            hash::Hash,
+            mrae::{
+                deoxysii::{DeoxysII, KEY_SIZE, NONCE_SIZE, TAG_SIZE},
+                nonce::Nonce,
+            },
+        },
+        namespace::Namespace,
+    },
+    storage::mkvs::{self, Key, Prefix, WriteLog, MKVS},
+};
+
+/// Key and value encryption using a single MRAE key.
+struct Cipher {
+    d2: DeoxysII,
+    prefix: Vec<u8>,
+}
+
+impl Cipher {
+    /// Derive the encrypted key for the given plaintext key.
+    ///
+    /// Keys are encrypted deterministically using an all-zero nonce so that the
+    /// same plaintext key always maps to the same encrypted key.
+    fn encrypt_key(&self, key: &[u8]) -> Vec<u8> {
+        let nonce = [0u8; NONCE_SIZE];
+        let mut encrypted = self.prefix.clone();
+        encrypted.append(&mut self.d2.seal(&nonce, key.to_vec(), vec![]));
+        encrypted
+    }
+
+    /// Decrypt the given encrypted key, returning `None` if it is not a valid
+    /// encrypted key under this cipher.
+    fn decrypt_key(&self, encrypted: &[u8]) -> Option<Key> {
+        let nonce = [0u8; NONCE_SIZE];
+        if !encrypted.starts_with(&self.prefix) {
+            return None;
+        }
+        self.d2
+            .open(&nonce, encrypted[self.prefix.len()..].to_vec(), vec![])
+            .ok()
+    }
+
+    /// Encrypt the value stored under the given encrypted key.
+    ///
+    /// The encrypted key is used as additional data so that values cannot be
+    /// moved between keys. The result is ciphertext || tag || nonce.
+    fn encrypt_value(&self, encrypted_key: &[u8], value: &[u8], nonce: &Nonce) -> Vec<u8> {
+        let mut ciphertext = self.d2.seal(nonce, value.to_vec(), encrypted_key.to_vec());
+        ciphertext.extend_from_slice(&nonce[..]);
+        ciphertext
+    }
+
+    /// Decrypt the value stored under the given encrypted key, returning `None`
+    /// if it fails to authenticate.
+    fn decrypt_value(&self, encrypted_key: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        if ciphertext.len() < TAG_SIZE + NONCE_SIZE {
+            return None;
+        }
+
+        let nonce_offset = ciphertext.len() - NONCE_SIZE;
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&ciphertext[nonce_offset..]);
+
+        self.d2
+            .open(
+                &nonce,
+                ciphertext[..nonce_offset].to_vec(),
+                encrypted_key.to_vec(),
+            )
+            .ok()
+    }
+}
+
+/// An MKVS wrapper which transparently encrypts all keys and values stored
+/// in the wrapped MKVS.
+///
+/// Keys are encrypted deterministically so that lookups remain possible, while
+/// values are encrypted using nonces taken from the given nonce which is
+/// incremented after each insert. As the resulting state must be the same on
+/// all nodes executing a runtime, the initial nonce must be derived
+/// deterministically (e.g., from the round) instead of being random.
+///
+/// Note that encrypted keys are not ordered in the same way as the plaintext
+/// keys. This means that iteration happens in an unspecified (but stable)
+/// order, that seeking only works for exact keys, and that operations on
+/// plaintext prefixes and ranges need to scan all encrypted entries.
+///
+/// Entries which fail to decrypt (e.g., because they were encrypted using a
+/// different key) are treated as if they were absent.
+pub struct EncryptedMKVS<M: MKVS> {
+    inner: M,
+    cipher: Cipher,
+    nonce: Nonce,
+}
+
+impl<M: MKVS> EncryptedMKVS<M> {
+    /// Create a new encrypted MKVS wrapping the given MKVS.
+    ///
+    /// The `key` is the MRAE key used for encryption, usually the state key
+    /// obtained from the key manager.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not `KEY_SIZE` bytes long.
+    pub fn new(inner: M, key: &[u8], nonce: Nonce) -> Self {
+        if key.len() != KEY_SIZE {
+            panic!("mkvs: invalid encryption key size {}", key.len());
+        }
+        let mut raw_key = [0u8; KEY_SIZE];
+        raw_key.copy_from_slice(key);
+
+        Self {
+            inner,
+            cipher: Cipher {
+                d2: DeoxysII::new(&raw_key),
+                prefix: vec![],
+            },
+            nonce,
+        }
+    }
+
+    /// Store all encrypted entries under the given key prefix.
+    ///
+    /// All entries under the prefix must be encrypted with the same key, other
+    /// entries in the wrapped MKVS are never touched.
+    pub fn with_prefix(mut self, prefix: &[u8]) -> Self {
+        self.cipher.prefix = prefix.to_vec();
+        self
+    }
+
+    /// Unwrap the wrapped MKVS.
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Collect the encrypted keys of all entries whose plaintext keys match.
+    ///
+    /// Entries which fail to decrypt are skipped. Returns `None` if iteration
+    /// fails.
+    fn find_keys<F>(&self, ctx: Context, predicate: F) -> Option<Vec<Vec<u8>>>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let mut it = self.inner.iter_prefix(ctx, &self.cipher.prefix);
+        let mut keys = vec![];
+        while it.is_valid() {
+            let key = it.get_key().clone().expect("iterator is valid");
+            if let Some(plaintext) = self.cipher.decrypt_key(&key) {
+                if predicate(&plaintext) {
+                    keys.push(key);
+                }
+            }
+            mkvs::Iterator::next(&mut *it);
+        }
+        if it.error().is_some() {
+            return None;
+        }
+        Some(keys)
+    }
+
+    /// Remove the entries with the given encrypted keys.
+    ///
+    /// Nothing is removed in case the keys could not be collected.
+    fn remove_keys(&mut self, ctx: Context, keys: Option<Vec<Vec<u8>>>) {
+        let ctx = ctx.freeze();
+        for key in keys.unwrap_or_default() {
+            self.inner.remove(Context::create_child(&ctx), &key);
+        }
+    }
+}
+
+impl<M: MKVS> MKVS for EncryptedMKVS<M> {
+    fn get(&self, ctx: Context, key: &[u8]) -> Option<Vec<u8>> {
+        let key = self.cipher.encrypt_key(key);
+        self.inner
+            .get(ctx, &key)
+            .and_then(|value| self.cipher.decrypt_value(&key, &value))
+    }
+
+    fn cache_contains_key(&self, ctx: Context, key: &[u8]) -> bool {
+        self.inner
+            .cache_contains_key(ctx, &self.cipher.encrypt_key(key))
+    }
+
+    fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        let key = self.cipher.encrypt_key(key);
+        let value = self.cipher.encrypt_value(&key, value, &self.nonce);
+        self.nonce
+            .increment()
+            .expect("mkvs: failed to increment nonce");
+
+        self.inner
+            .insert(ctx, &key, &value)
+            .and_then(|value| self.cipher.decrypt_value(&key, &value))
+    }
+
+    /// Check the limits of the wrapped MKVS against the sizes of the encrypted
+    /// key and value.
+    fn check_limits(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let key = self.cipher.encrypt_key(key);
+        let value = vec![0u8; value.len() + TAG_SIZE + NONCE_SIZE];
+        self.inner.check_limits(&key, &value)
+    }
+
+    fn remove(&mut self, ctx: Context, key: &[u8]) -> Option<Vec<u8>> {
+        let key = self.cipher.encrypt_key(key);
+        self.inner
+            .remove(ctx, &key)
+            .and_then(|value| self.cipher.decrypt_value(&key, &value))
+    }
+
+    fn remove_prefix(&mut self, ctx: Context, prefix: &[u8]) {
+        let ctx = ctx.freeze();
+        let keys = self.find_keys(Context::create_child(&ctx), |key| key.starts_with(prefix));
+        self.remove_keys(Context::create_child(&ctx), keys);
+    }
+
+    fn remove_range(&mut self, ctx: Context, start: &[u8], end: &[u8]) {
+        let ctx = ctx.freeze();
+        let keys = self.find_keys(Context::create_child(&ctx), |key| key >= start && key < end);
+        self.remove_keys(Context::create_child(&ctx), keys);
+    }
+
+    /// Populate the in-memory tree with nodes for all encrypted entries.
+    ///
+    /// Plaintext prefixes do not correspond to prefixes of encrypted keys, so
+    /// entries are prefetched under the prefix of the encrypted MKVS instead.
+    fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) {
+        if prefixes.is_empty() {
+            return;
+        }
+        self.inner
+            .prefetch_prefixes(ctx, &vec![self.cipher.prefix.clone().into()], limit)
+    }
+
+    fn prefetch_keys(&self, ctx: Context, keys: &[Vec<u8>]) {
+        let keys: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|key| self.cipher.encrypt_key(key))
+            .collect();
+        self.inner.prefetch_keys(ctx, &keys)
+    }
+
+    fn iter(&self, ctx: Context) -> Box<dyn mkvs::Iterator + '_> {
+        Box::new(EncryptedIterator::new(
+            self.inner.iter_prefix(ctx, &self.cipher.prefix),
+            &self.cipher,
+            None,
+        ))
+    }
+
+    /// Returns an iterator over all entries with plaintext keys starting with
+    /// the given prefix.
+    ///
+    /// As encrypted keys do not preserve prefixes, this scans all encrypted
+    /// entries and skips the ones that do not match.
+    fn iter_prefix(&self, ctx: Context, prefix: &[u8]) -> Box<dyn mkvs::Iterator + '_> {
+        Box::new(EncryptedIterator::new(
+            self.inner.iter_prefix(ctx, &self.cipher.prefix),
+            &self.cipher,
+            Some(prefix.to_vec()),
+        ))
+    }
+
+    fn commit(
+        &mut self,
+        ctx: Context,
+        namespace: Namespace,
+        version: u64,
+    ) -> Result<(WriteLog, Hash)> {
+        self.inner.commit(ctx, namespace, version)
+    }
+}
+
+/// An iterator over decrypted entries of an encrypted MKVS.
+struct EncryptedIterator<'a> {
+    inner: Box<dyn mkvs::Iterator + 'a>,
+    cipher: &'a Cipher,
+    /// Plaintext key prefix that all returned keys must have, if any.
+    filter: Option<Vec<u8>>,
+    key: Option<Key>,
+    value: Option<Vec<u8>>,
+}
+
+impl<'a> EncryptedIterator<'a> {
+    fn new(
+        inner: Box<dyn mkvs::Iterator + 'a>,
+        cipher: &'a Cipher,
+        filter: Option<Vec<u8>>,
+    ) -> Self {
+        let mut it = Self {
+            inner,
+            cipher,
+            filter,
+            key: None,
+            value: None,
+        };
+        it.update(true);
+        it
+    }
+
+    /// Decrypt the entry under the inner iterator, skipping entries which fail
+    /// to decrypt or do not match the filter in the given direction.
+    fn update(&mut self, forward: bool) {
+        self.key = None;
+        self.value = None;
+
+        while self.inner.is_valid() {
+            let encrypted_key = self.inner.get_key().clone().expect("iterator is valid");
+            let value = self.inner.get_value().as_ref().expect("iterator is valid");
+            let entry = self.cipher.decrypt_key(&encrypted_key).and_then(|key| {
+                self.cipher
+                    .decrypt_value(&encrypted_key, value)
+                    .map(|value| (key, value))
+            });
+            match entry {
+                Some((key, value))
+                    if self
+                        .filter
+                        .as_ref()
+                        .map_or(true, |prefix| key.starts_with(prefix)) =>
+                {
+                    self.key = Some(key);
+                    self.value = Some(value);
+                    return;
+                }
+                _ => {
+                    if forward {
+                        mkvs::Iterator::next(&mut *self.inner);
+                    } else {
+                        self.inner.prev();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> iter::Iterator for EncryptedIterator<'a> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use mkvs::Iterator;
+
+        if !self.is_valid() {
+            return None;
+        }
+
+        let key = self.key.clone().expect("iterator is valid");
+        let value = self.value.clone().expect("iterator is valid");
+        mkvs::Iterator::next(self);
+
+        Some((key, value))
+    }
+}
+
+impl<'a> mkvs::Iterator for EncryptedIterator<'a> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        self.inner.set_prefetch(prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.key.is_some()
+    }
+
+    fn error(&self) -> &Option<Error> {
+        self.inner.error()
+    }
+
+    fn rewind(&mut self) {
+        self.inner.rewind();
+        self.update(true);
+    }
+
+    /// Moves the iterator to the given key if it exists. Otherwise the iterator
+    /// is positioned at an unspecified key.
+    fn seek(&mut self, key: &[u8]) {
+        self.inner.seek(&self.cipher.encrypt_key(key));
+        self.update(true);
+    }
+
+    fn seek_last(&mut self) {
+        self.inner.seek_last();
+        self.update(false);
+    }
+
+    /// Moves the iterator to the given key if it exists. Otherwise the iterator
+    /// is positioned at an unspecified key.
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        self.inner.seek_for_prev(&self.cipher.encrypt_key(key));
+        self.update(false);
+    }
+
+    fn get_key(&self) -> &Option<Key> {
+        &self.key
+    }
+
+    fn get_value(&self) -> &Option<Vec<u8>> {
+        &self.value
+    }
+
+    fn next(&mut self) {
+        if self.is_valid() {
+            mkvs::Iterator::next(&mut *self.inner);
+            self.update(true);
+        }
+    }
+
+    fn prev(&mut self) {
+        if self.is_valid() {
+            self.inner.prev();
+            self.update(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::storage::mkvs::{sync::*, OverlayTree, RootType, Tree};
+
+    use super::*;
+
+    const KEY: [u8; KEY_SIZE] = [42u8; KEY_SIZE];
+
+    fn new_tree() -> OverlayTree<Tree> {
+        OverlayTree::new(
+            Tree::make()
+                .with_root_type(RootType::State)
+                .new(Box::new(NoopReadSyncer)),
+        )
+    }
+
+    #[test]
+    fn test_encrypted_mkvs() {
+        let mut mkvs =
+            EncryptedMKVS::new(new_tree(), &KEY, Nonce::new([0u8; NONCE_SIZE])).with_prefix(b"E");
+
+        let mut expected = BTreeMap::new();
+        for i in 0..50u32 {
+            let key = format!("{}key {:02}", if i % 2 == 0 { 'a' } else { 'b' }, i);
+            let value = format!("value {}", i);
+            assert_eq!(
+                MKVS::insert(
+                    &mut mkvs,
+                    Context::background(),
+                    key.as_bytes(),
+                    value.as_bytes()
+                ),
+                None
+            );
+            expected.insert(key.into_bytes(), value.into_bytes());
+        }
+        assert_eq!(
+            MKVS::insert(&mut mkvs, Context::background(), b"akey 00", b"updated"),
+            Some(b"value 0".to_vec())
+        );
+        expected.insert(b"akey 00".to_vec(), b"updated".to_vec());
+        assert_eq!(
+            MKVS::get(&mkvs, Context::background(), b"akey 00"),
+            Some(b"updated".to_vec())
+        );
+        assert_eq!(MKVS::get(&mkvs, Context::background(), b"missing"), None);
+
+        // Iteration should return all decrypted entries.
+        let mut it = MKVS::iter(&mkvs, Context::background());
+        it.rewind();
+        let entries: BTreeMap<_, _> = it.collect();
+        assert_eq!(entries, expected);
+
+        // Seeking to an existing key should position the iterator at that key.
+        {
+            let mut it = MKVS::iter(&mkvs, Context::background());
+            it.seek(b"bkey 11");
+            assert_eq!(it.get_key(), &Some(b"bkey 11".to_vec()));
+            assert_eq!(it.get_value(), &Some(b"value 11".to_vec()));
+        }
+
+        let entries: BTreeMap<_, _> =
+            MKVS::iter_prefix(&mkvs, Context::background(), b"b").collect();
+        assert_eq!(entries.len(), 25);
+        assert!(entries.keys().all(|key| key.starts_with(b"b")));
+
+        // Neither plaintext keys nor values should be stored in the wrapped MKVS.
+        let inner = mkvs.into_inner();
+        let mut it = MKVS::iter(&inner, Context::background());
+        it.rewind();
+        for (key, value) in it {
+            assert!(key.starts_with(b"E"));
+            assert!(!expected.contains_key(&key));
+            assert!(expected.values().all(|v| v != &value));
+        }
+
+        // Values should not be readable using a different key.
+        let mut mkvs = EncryptedMKVS::new(inner, &[1u8; KEY_SIZE], Nonce::new([0u8; NONCE_SIZE]))
+            .with_prefix(b"E");
+        assert_eq!(MKVS::get(&mkvs, Context::background(), b"akey 00"), None);
+        assert_eq!(MKVS::iter(&mkvs, Context::background()).count(), 0);
+        MKVS::remove_prefix(&mut mkvs, Context::background(), b"a");
+
+        // Removing entries by plaintext prefix and range should work.
+        let mut mkvs = EncryptedMKVS::new(mkvs.into_inner(), &KEY, Nonce::new([1u8; NONCE_SIZE]))
+            .with_prefix(b"E");
+        assert_eq!(
+            MKVS::remove(&mut mkvs, Context::background(), b"akey 00"),
+            Some(b"updated".to_vec())
+        );
+        MKVS::remove_prefix(&mut mkvs, Context::background(), b"a");
+        MKVS::remove_range(&mut mkvs, Context::background(), b"bkey 00", b"bkey 20");
+        let entries: Vec<_> = MKVS::iter(&mkvs, Context::background()).collect();
+        assert_eq!(entries.len(), 15);
+        assert!(entries.iter().all(|(key, _)| key[..] >= b"bkey 20"[..]));
+
+        // Values moved to a different key should fail to authenticate.
+        let (from, to) = (
+            mkvs.cipher.encrypt_key(b"bkey 21"),
+            mkvs.cipher.encrypt_key(b"bkey 23"),
+        );
+        let value =
+            MKVS::get(&mkvs.inner, Context::background(), &from).expect("value should exist");
+        MKVS::insert(&mut mkvs.inner, Context::background(), &to, &value);
+        assert_eq!(MKVS::get(&mkvs, Context::background(), b"bkey 23"), None);
+        assert_eq!(MKVS::iter(&mkvs, Context::background()).count(), 14);
+    }
+}
@@ -6,6 +6,7 @@ use crate::{
 
 mod protocol_server;
 mod rpc;
+mod storage_server;
 
 /// MKVS interoperability driver.
 pub trait Driver {
@@ -23,4 +24,4 @@ pub trait Driver {
     );
 }
 
-pub use self::protocol_server::ProtocolServer;
+pub use self::{protocol_server::ProtocolServer, storage_server::StorageServer};
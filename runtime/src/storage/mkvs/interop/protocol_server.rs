@@ -13,21 +13,41 @@ use grpcio::{ChannelBuilder, EnvBuilder};
 use io_context::Context;
 use tempfile::{self, TempDir};
 
-use super::{rpc, Driver};
+use super::{rpc, storage_server::StorageServer, Driver};
 use crate::{
     common::{crypto::hash::Hash, namespace::Namespace},
     storage::mkvs::{sync::*, tree::RootType, WriteLog},
 };
 
 /// Location of the protocol server binary.
-const PROTOCOL_SERVER_BINARY: &'static str = env!("OASIS_STORAGE_PROTOCOL_SERVER_BINARY");
+///
+/// When set, the (Go) protocol server is always used.
+const PROTOCOL_SERVER_BINARY: Option<&'static str> =
+    option_env!("OASIS_STORAGE_PROTOCOL_SERVER_BINARY");
+
+/// Environment variable that must be set to use the in-process Rust storage
+/// server when the protocol server binary is not available.
+///
+/// Note that in this case the tests only check the Rust implementation against
+/// itself and not interoperability with the Go implementation.
+const IN_PROCESS_ENV: &str = "OASIS_STORAGE_PROTOCOL_SERVER_IN_PROCESS";
+
+/// Storage server backing the protocol server.
+enum Backend {
+    /// External protocol server process.
+    External {
+        process: Child,
+        #[allow(unused)]
+        datadir: TempDir,
+    },
+    /// In-process Rust storage server.
+    InProcess(#[allow(unused)] StorageServer),
+}
 
 /// Interoperability protocol server for testing storage.
 pub struct ProtocolServer {
-    server_process: Child,
+    backend: Backend,
     client: rpc::StorageClient,
-    #[allow(unused)]
-    datadir: TempDir,
 }
 
 struct ProtocolServerReadSyncer {
@@ -37,6 +57,38 @@ struct ProtocolServerReadSyncer {
 impl ProtocolServer {
     /// Create a new protocol server for testing.
     pub fn new() -> Self {
+        let (backend, address) = match PROTOCOL_SERVER_BINARY {
+            Some(binary) => {
+                eprintln!("storage interop: using protocol server binary {}", binary);
+                Self::spawn_external(binary)
+            }
+            None => {
+                if std::env::var_os(IN_PROCESS_ENV).is_none() {
+                    panic!(
+                        "storage interop: OASIS_STORAGE_PROTOCOL_SERVER_BINARY not set at build \
+                         time, set {} to use the in-process Rust storage server",
+                        IN_PROCESS_ENV
+                    );
+                }
+                eprintln!("storage interop: using in-process Rust storage server");
+                let server = StorageServer::new();
+                let address = server.address();
+                (Backend::InProcess(server), address)
+            }
+        };
+
+        // Create connection with the protocol server.
+        let env = Arc::new(EnvBuilder::new().build());
+        let channel = ChannelBuilder::new(env)
+            .max_receive_message_len(i32::max_value())
+            .max_send_message_len(i32::max_value())
+            .connect(&address);
+        let client = rpc::StorageClient::new(channel);
+
+        Self { backend, client }
+    }
+
+    fn spawn_external(binary: &str) -> (Backend, String) {
         let datadir = tempfile::Builder::new()
             .prefix("oasis-test-storage-protocol-server")
             .tempdir()
@@ -44,7 +96,7 @@ impl ProtocolServer {
         let socket_path = datadir.path().join("socket");
 
         // Start protocol server.
-        let server_process = Command::new(PROTOCOL_SERVER_BINARY)
+        let process = Command::new(binary)
             .arg("proto-server")
             .arg("--datadir")
             .arg(datadir.path())
@@ -52,20 +104,9 @@ impl ProtocolServer {
             .arg(socket_path.clone())
             .spawn()
             .expect("protocol server failed to start");
+        let address = format!("unix:{}", socket_path.to_str().unwrap());
 
-        // Create connection with the protocol server.
-        let env = Arc::new(EnvBuilder::new().build());
-        let channel = ChannelBuilder::new(env)
-            .max_receive_message_len(i32::max_value())
-            .max_send_message_len(i32::max_value())
-            .connect(&format!("unix:{}", socket_path.to_str().unwrap()));
-        let client = rpc::StorageClient::new(channel);
-
-        Self {
-            server_process,
-            client,
-            datadir,
-        }
+        (Backend::External { process, datadir }, address)
     }
 
     /// Return a ReadSync backed by the protocol server
@@ -79,8 +120,13 @@ impl ProtocolServer {
 impl Drop for ProtocolServer {
     fn drop(&mut self) {
         // Stop protocol server.
-        drop(self.server_process.kill());
-        drop(self.server_process.wait());
+        if let Backend::External {
+            ref mut process, ..
+        } = self.backend
+        {
+            drop(process.kill());
+            drop(process.wait());
+        }
     }
 }
 
@@ -120,6 +166,11 @@ impl ReadSync for ProtocolServerReadSyncer {
         Ok(self.client.sync_get(&request)?)
     }
 
+    fn sync_get_many(&mut self, _ctx: Context, _request: GetManyRequest) -> Result<ProofResponse> {
+        // The storage gRPC service does not expose batched fetches.
+        Err(SyncerError::Unsupported.into())
+    }
+
     fn sync_get_prefixes(
         &mut self,
         _ctx: Context,
@@ -129,6 +180,9 @@ impl ReadSync for ProtocolServerReadSyncer {
     }
 
     fn sync_iterate(&mut self, _ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        if request.reverse {
+            return Err(SyncerError::ReverseIterationUnsupported.into());
+        }
         Ok(self.client.sync_iterate(&request)?)
     }
 }
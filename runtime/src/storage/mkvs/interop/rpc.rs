@@ -11,7 +11,7 @@ use crate::{
 
 // NOTE: The return value is intentionally ignored as it is not required
 //       during the interoperability tests.
-const METHOD_APPLY: Method<ApplyRequest, Value> = Method {
+pub(super) const METHOD_APPLY: Method<ApplyRequest, Value> = Method {
     ty: MethodType::Unary,
     name: "/oasis-core.Storage/Apply",
     req_mar: Marshaller {
@@ -24,7 +24,7 @@ const METHOD_APPLY: Method<ApplyRequest, Value> = Method {
     },
 };
 
-const METHOD_SYNC_GET: Method<sync::GetRequest, sync::ProofResponse> = Method {
+pub(super) const METHOD_SYNC_GET: Method<sync::GetRequest, sync::ProofResponse> = Method {
     ty: MethodType::Unary,
     name: "/oasis-core.Storage/SyncGet",
     req_mar: Marshaller {
@@ -37,20 +37,21 @@ const METHOD_SYNC_GET: Method<sync::GetRequest, sync::ProofResponse> = Method {
     },
 };
 
-const METHOD_SYNC_GET_PREFIXES: Method<sync::GetPrefixesRequest, sync::ProofResponse> = Method {
-    ty: MethodType::Unary,
-    name: "/oasis-core.Storage/SyncGetPrefixes",
-    req_mar: Marshaller {
-        ser: cbor_encode,
-        de: cbor_decode,
-    },
-    resp_mar: Marshaller {
-        ser: cbor_encode,
-        de: cbor_decode,
-    },
-};
-
-const METHOD_SYNC_ITERATE: Method<sync::IterateRequest, sync::ProofResponse> = Method {
+pub(super) const METHOD_SYNC_GET_PREFIXES: Method<sync::GetPrefixesRequest, sync::ProofResponse> =
+    Method {
+        ty: MethodType::Unary,
+        name: "/oasis-core.Storage/SyncGetPrefixes",
+        req_mar: Marshaller {
+            ser: cbor_encode,
+            de: cbor_decode,
+        },
+        resp_mar: Marshaller {
+            ser: cbor_encode,
+            de: cbor_decode,
+        },
+    };
+
+pub(super) const METHOD_SYNC_ITERATE: Method<sync::IterateRequest, sync::ProofResponse> = Method {
     ty: MethodType::Unary,
     name: "/oasis-core.Storage/SyncIterate",
     req_mar: Marshaller {
@@ -0,0 +1,206 @@
+//! An in-process storage gRPC server backed by Rust MKVS trees.
+//!
+//! This makes it possible to run the interoperability tests without an
+//! external protocol server. It should only be used for testing.
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use futures::Future;
+use grpcio::{
+    EnvBuilder, Method, RpcContext, RpcStatus, RpcStatusCode, Server, ServerBuilder,
+    ServiceBuilder, UnarySink,
+};
+use io_context::Context;
+use serde_cbor::Value;
+use tempfile::{self, TempDir};
+
+use super::{rpc, Driver};
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    storage::mkvs::{
+        db::{FileNodeDB, NodeDB, NodeDBReadSyncer},
+        sync::*,
+        tree::*,
+        WriteLog,
+    },
+};
+
+/// Shared state of the storage service.
+#[derive(Clone)]
+struct StorageService {
+    db: Arc<dyn NodeDB>,
+    read_syncer: Arc<Mutex<NodeDBReadSyncer>>,
+}
+
+impl StorageService {
+    fn apply(&self, request: rpc::ApplyRequest) -> Result<Value> {
+        let read_syncer = Box::new(NodeDBReadSyncer::new(self.db.clone()));
+        let mut tree = if request.src_root.is_empty() {
+            Tree::make()
+                .with_root_type(request.root_type)
+                .with_node_db(self.db.clone())
+                .new(read_syncer)
+        } else {
+            Tree::make()
+                .with_root(Root {
+                    namespace: request.namespace,
+                    version: request.src_round,
+                    root_type: request.root_type,
+                    hash: request.src_root,
+                })
+                .with_node_db(self.db.clone())
+                .new(read_syncer)
+        };
+
+        let hash = tree.apply_write_log(
+            Context::background(),
+            &request.writelog,
+            request.namespace,
+            request.dst_round,
+        )?;
+        if hash != request.dst_root {
+            return Err(TreeError::RootMismatch {
+                expected: request.dst_root,
+                got: hash,
+            }
+            .into());
+        }
+
+        Ok(Value::Null)
+    }
+
+    fn sync<F>(&self, f: F) -> Result<ProofResponse>
+    where
+        F: FnOnce(&mut NodeDBReadSyncer, Context) -> Result<ProofResponse>,
+    {
+        let mut read_syncer = self.read_syncer.lock().unwrap();
+        f(&mut read_syncer, Context::background())
+    }
+}
+
+/// Respond to a unary call with the result of the handler.
+fn respond<T>(ctx: RpcContext, sink: UnarySink<T>, result: Result<T>) {
+    let f = match result {
+        Ok(rsp) => sink.success(rsp),
+        Err(err) => sink.fail(RpcStatus::new(
+            RpcStatusCode::Internal,
+            Some(err.to_string()),
+        )),
+    };
+    ctx.spawn(f.map_err(|_| ()));
+}
+
+/// Register a unary handler for the given method on the storage service.
+fn add_handler<Req, Resp, F>(
+    builder: ServiceBuilder,
+    method: &Method<Req, Resp>,
+    service: &StorageService,
+    handler: F,
+) -> ServiceBuilder
+where
+    Req: 'static,
+    Resp: 'static,
+    F: Fn(&StorageService, Req) -> Result<Resp> + Clone + Send + 'static,
+{
+    let service = service.clone();
+    builder.add_unary_handler(
+        method,
+        move |ctx: RpcContext, request: Req, sink: UnarySink<Resp>| {
+            respond(ctx, sink, handler(&service, request))
+        },
+    )
+}
+
+/// In-process storage server implementing the `/oasis-core.Storage/*`
+/// methods needed by the interoperability tests.
+pub struct StorageServer {
+    service: StorageService,
+    server: Server,
+    #[allow(unused)]
+    datadir: TempDir,
+}
+
+impl StorageServer {
+    /// Start a new storage server listening on a local port.
+    pub fn new() -> Self {
+        let datadir = tempfile::Builder::new()
+            .prefix("oasis-test-storage-server")
+            .tempdir()
+            .expect("failed to create temporary data directory");
+        let db: Arc<dyn NodeDB> = Arc::new(
+            FileNodeDB::open(datadir.path().join("nodes.db")).expect("failed to open node db"),
+        );
+        let service = StorageService {
+            db: db.clone(),
+            read_syncer: Arc::new(Mutex::new(NodeDBReadSyncer::new(db))),
+        };
+
+        let mut builder = ServiceBuilder::new();
+        let handle = service.clone();
+        builder = add_handler(builder, &rpc::METHOD_APPLY, &service, |s, request| {
+            s.apply(request)
+        });
+        builder = add_handler(builder, &rpc::METHOD_SYNC_GET, &service, |s, request| {
+            s.sync(|rs, ctx| rs.sync_get(ctx, request))
+        });
+        builder = add_handler(
+            builder,
+            &rpc::METHOD_SYNC_GET_PREFIXES,
+            &service,
+            |s, request| s.sync(|rs, ctx| rs.sync_get_prefixes(ctx, request)),
+        );
+        builder = add_handler(
+            builder,
+            &rpc::METHOD_SYNC_ITERATE,
+            &service,
+            |s, request| s.sync(|rs, ctx| rs.sync_iterate(ctx, request)),
+        );
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let mut server = ServerBuilder::new(env)
+            .register_service(builder.build())
+            .bind("127.0.0.1", 0)
+            .build()
+            .expect("storage server failed to start");
+        server.start();
+
+        Self {
+            service: handle,
+            server,
+            datadir,
+        }
+    }
+
+    /// Return the address the server is listening on.
+    pub fn address(&self) -> String {
+        let (host, port) = &self.server.bind_addrs()[0];
+        format!("{}:{}", host, port)
+    }
+}
+
+impl Driver for StorageServer {
+    fn apply(&self, write_log: &WriteLog, root_hash: Hash, namespace: Namespace, version: u64) {
+        self.apply_existing(write_log, Hash::empty_hash(), root_hash, namespace, version)
+    }
+
+    fn apply_existing(
+        &self,
+        write_log: &WriteLog,
+        existing_root: Hash,
+        root_hash: Hash,
+        namespace: Namespace,
+        version: u64,
+    ) {
+        self.service
+            .apply(rpc::ApplyRequest {
+                namespace,
+                root_type: RootType::State, // Doesn't matter for tests.
+                src_round: version,
+                src_root: existing_root,
+                dst_round: version,
+                dst_root: root_hash,
+                writelog: write_log.clone(),
+            })
+            .expect("apply failed");
+    }
+}
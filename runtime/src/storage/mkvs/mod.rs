@@ -4,7 +4,7 @@ use std::{
     ops::{Deref, DerefMut},
 };
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use base64;
 use io_context::Context;
 use serde::{self, ser::SerializeSeq, Deserialize, Serialize, Serializer};
@@ -15,14 +15,24 @@ use crate::common::{crypto::hash::Hash, namespace::Namespace};
 #[macro_use]
 mod tree;
 mod cache;
-#[cfg(test)]
-mod interop;
+pub mod checkpoint;
+pub mod db;
+mod encrypted;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod interop;
 pub mod marshal;
 pub mod sync;
 #[cfg(test)]
 mod tests;
 
-pub use tree::{Depth, Key, NodeBox, OverlayTree, Root, RootType, Tree};
+pub use cache::{
+    EvictionPolicy, LFUPolicy, LRUPolicy, NodeCache, PinTopLevelsPolicy, RemoveLockedError,
+};
+pub use encrypted::EncryptedMKVS;
+pub use tree::{
+    diff, export, import, verify_transition, Depth, Key, NodeBox, NodePointer, NodePtrRef, Options,
+    OverlayTree, PrefixIterator, Root, RootType, Savepoint, SharedTree, Tree, TreeStats,
+};
 
 /// The type of entry in the log.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -136,19 +146,73 @@ pub trait MKVS {
     /// If the database did have this key present, the value is updated, and the old value is
     /// returned.
     ///
+    /// Panics if the key or value exceed any size limits. Use `check_limits` first in case the
+    /// sizes are not under the caller's control.
+    ///
     /// [`None`]: std::option::Option
     fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Option<Vec<u8>>;
 
+    /// Check that an entry with the given key and value could be inserted without
+    /// exceeding any size limits.
+    fn check_limits(&self, _key: &[u8], _value: &[u8]) -> Result<()> {
+        // Default implementation has no limits.
+        Ok(())
+    }
+
     /// Remove entry with given key, returning the value at the key if the key was previously
     /// in the database.
     fn remove(&mut self, ctx: Context, key: &[u8]) -> Option<Vec<u8>>;
 
+    /// Remove all entries with keys starting with the given prefix.
+    ///
+    /// The default implementation iterates over the prefix and removes each key.
+    fn remove_prefix(&mut self, ctx: Context, prefix: &[u8]) {
+        let ctx = ctx.freeze();
+        let keys = collect_keys(self.iter(Context::create_child(&ctx)), prefix, |key| {
+            key.starts_with(prefix)
+        })
+        .expect("remove_prefix");
+        for key in keys {
+            self.remove(Context::create_child(&ctx), &key);
+        }
+    }
+
+    /// Remove all entries with keys in the range [`start`, `end`).
+    ///
+    /// The default implementation iterates over the range and removes each key.
+    fn remove_range(&mut self, ctx: Context, start: &[u8], end: &[u8]) {
+        let ctx = ctx.freeze();
+        let keys = collect_keys(self.iter(Context::create_child(&ctx)), start, |key| {
+            key < end
+        })
+        .expect("remove_range");
+        for key in keys {
+            self.remove(Context::create_child(&ctx), &key);
+        }
+    }
+
     /// Populate the in-memory tree with nodes for keys starting with given prefixes.
     fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16);
 
+    /// Populate the in-memory tree with nodes for the given keys.
+    ///
+    /// The default implementation does nothing as prefetching is only an optimization.
+    fn prefetch_keys(&self, _ctx: Context, _keys: &[Vec<u8>]) {}
+
     /// Returns an iterator over the tree.
     fn iter(&self, ctx: Context) -> Box<dyn Iterator + '_>;
 
+    /// Returns an iterator over all entries with keys starting with the given prefix.
+    ///
+    /// The iterator stops at the prefix boundary and prefetches entries under the
+    /// prefix when it first needs to fetch nodes from a remote read syncer.
+    ///
+    /// The default implementation restricts `iter` to the prefix without any
+    /// prefetching.
+    fn iter_prefix(&self, ctx: Context, prefix: &[u8]) -> Box<dyn Iterator + '_> {
+        Box::new(PrefixIterator::new(self.iter(ctx), prefix))
+    }
+
     /// Commit all database changes to the underlying store.
     fn commit(
         &mut self,
@@ -180,20 +244,93 @@ pub trait FallibleMKVS {
     /// [`None`]: std::option::Option
     fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>>;
 
+    /// Check that an entry with the given key and value could be inserted without
+    /// exceeding any size limits.
+    fn check_limits(&self, _key: &[u8], _value: &[u8]) -> Result<()> {
+        // Default implementation has no limits.
+        Ok(())
+    }
+
     /// Remove entry with given key, returning the value at the key if the key was previously
     /// in the database.
     fn remove(&mut self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>>;
 
+    /// Remove all entries with keys starting with the given prefix.
+    ///
+    /// The default implementation iterates over the prefix and removes each key.
+    fn remove_prefix(&mut self, ctx: Context, prefix: &[u8]) -> Result<()> {
+        let ctx = ctx.freeze();
+        let keys = collect_keys(self.iter(Context::create_child(&ctx)), prefix, |key| {
+            key.starts_with(prefix)
+        })?;
+        for key in keys {
+            self.remove(Context::create_child(&ctx), &key)?;
+        }
+        Ok(())
+    }
+
+    /// Remove all entries with keys in the range [`start`, `end`).
+    ///
+    /// The default implementation iterates over the range and removes each key.
+    fn remove_range(&mut self, ctx: Context, start: &[u8], end: &[u8]) -> Result<()> {
+        let ctx = ctx.freeze();
+        let keys = collect_keys(self.iter(Context::create_child(&ctx)), start, |key| {
+            key < end
+        })?;
+        for key in keys {
+            self.remove(Context::create_child(&ctx), &key)?;
+        }
+        Ok(())
+    }
+
     /// Populate the in-memory tree with nodes for keys starting with given prefixes.
     fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) -> Result<()>;
 
+    /// Populate the in-memory tree with nodes for the given keys.
+    ///
+    /// The default implementation does nothing as prefetching is only an optimization.
+    fn prefetch_keys(&self, _ctx: Context, _keys: &[Vec<u8>]) -> Result<()> {
+        Ok(())
+    }
+
     /// Returns an iterator over the tree.
     fn iter(&self, ctx: Context) -> Box<dyn Iterator + '_>;
 
+    /// Returns an iterator over all entries with keys starting with the given prefix.
+    ///
+    /// The iterator stops at the prefix boundary and prefetches entries under the
+    /// prefix when it first needs to fetch nodes from a remote read syncer.
+    ///
+    /// The default implementation restricts `iter` to the prefix without any
+    /// prefetching.
+    fn iter_prefix(&self, ctx: Context, prefix: &[u8]) -> Box<dyn Iterator + '_> {
+        Box::new(PrefixIterator::new(self.iter(ctx), prefix))
+    }
+
     /// Commit all database changes to the underlying store.
     fn commit(&mut self, ctx: Context, namespace: Namespace, version: u64) -> Result<Hash>;
 }
 
+/// Collect all keys, starting at `start`, until `matches` returns false.
+fn collect_keys<F>(mut it: Box<dyn Iterator + '_>, start: &[u8], matches: F) -> Result<Vec<Key>>
+where
+    F: Fn(&[u8]) -> bool,
+{
+    let mut keys = Vec::new();
+    it.seek(start);
+    while let Some(key) = it.get_key() {
+        if !matches(key) {
+            break;
+        }
+        keys.push(key.clone());
+        Iterator::next(&mut it);
+    }
+    if let Some(error) = it.error() {
+        return Err(anyhow!("{}", error));
+    }
+    Ok(keys)
+}
+
 /// An MKVS iterator.
 pub trait Iterator: iter::Iterator<Item = (Vec<u8>, Vec<u8>)> {
     /// Sets the number of next elements to prefetch.
@@ -211,6 +348,12 @@ pub trait Iterator: iter::Iterator<Item = (Vec<u8>, Vec<u8>)> {
     /// Moves the iterator either at the given key or at the next larger key.
     fn seek(&mut self, key: &[u8]);
 
+    /// Moves the iterator to the last key in the tree.
+    fn seek_last(&mut self);
+
+    /// Moves the iterator either at the given key or at the previous smaller key.
+    fn seek_for_prev(&mut self, key: &[u8]);
+
     /// The key under the iterator.
     fn get_key(&self) -> &Option<Key>;
 
@@ -219,6 +362,55 @@ pub trait Iterator: iter::Iterator<Item = (Vec<u8>, Vec<u8>)> {
 
     /// Advance the iterator to the next key.
     fn next(&mut self);
+
+    /// Move the iterator back to the previous key.
+    fn prev(&mut self);
+}
+
+impl<I: Iterator + ?Sized> Iterator for Box<I> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        I::set_prefetch(self, prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        I::is_valid(self)
+    }
+
+    fn error(&self) -> &Option<Error> {
+        I::error(self)
+    }
+
+    fn rewind(&mut self) {
+        I::rewind(self)
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        I::seek(self, key)
+    }
+
+    fn seek_last(&mut self) {
+        I::seek_last(self)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        I::seek_for_prev(self, key)
+    }
+
+    fn get_key(&self) -> &Option<Key> {
+        I::get_key(self)
+    }
+
+    fn get_value(&self) -> &Option<Vec<u8>> {
+        I::get_value(self)
+    }
+
+    fn next(&mut self) {
+        <I as Iterator>::next(self)
+    }
+
+    fn prev(&mut self) {
+        I::prev(self)
+    }
 }
 
 impl<T: MKVS + ?Sized> MKVS for &mut T {
@@ -234,18 +426,38 @@ impl<T: MKVS + ?Sized> MKVS for &mut T {
         T::insert(self, ctx, key, value)
     }
 
+    fn check_limits(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        T::check_limits(self, key, value)
+    }
+
     fn remove(&mut self, ctx: Context, key: &[u8]) -> Option<Vec<u8>> {
         T::remove(self, ctx, key)
     }
 
+    fn remove_prefix(&mut self, ctx: Context, prefix: &[u8]) {
+        T::remove_prefix(self, ctx, prefix)
+    }
+
+    fn remove_range(&mut self, ctx: Context, start: &[u8], end: &[u8]) {
+        T::remove_range(self, ctx, start, end)
+    }
+
     fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) {
         T::prefetch_prefixes(self, ctx, prefixes, limit)
     }
 
+    fn prefetch_keys(&self, ctx: Context, keys: &[Vec<u8>]) {
+        T::prefetch_keys(self, ctx, keys)
+    }
+
     fn iter(&self, ctx: Context) -> Box<dyn Iterator + '_> {
         T::iter(self, ctx)
     }
 
+    fn iter_prefix(&self, ctx: Context, prefix: &[u8]) -> Box<dyn Iterator + '_> {
+        T::iter_prefix(self, ctx, prefix)
+    }
+
     fn commit(
         &mut self,
         ctx: Context,
@@ -269,18 +481,38 @@ impl<T: FallibleMKVS + ?Sized> FallibleMKVS for &mut T {
         T::insert(self, ctx, key, value)
     }
 
+    fn check_limits(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        T::check_limits(self, key, value)
+    }
+
     fn remove(&mut self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
         T::remove(self, ctx, key)
     }
 
+    fn remove_prefix(&mut self, ctx: Context, prefix: &[u8]) -> Result<()> {
+        T::remove_prefix(self, ctx, prefix)
+    }
+
+    fn remove_range(&mut self, ctx: Context, start: &[u8], end: &[u8]) -> Result<()> {
+        T::remove_range(self, ctx, start, end)
+    }
+
     fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) -> Result<()> {
         T::prefetch_prefixes(self, ctx, prefixes, limit)
     }
 
+    fn prefetch_keys(&self, ctx: Context, keys: &[Vec<u8>]) -> Result<()> {
+        T::prefetch_keys(self, ctx, keys)
+    }
+
     fn iter(&self, ctx: Context) -> Box<dyn Iterator + '_> {
         T::iter(self, ctx)
     }
 
+    fn iter_prefix(&self, ctx: Context, prefix: &[u8]) -> Box<dyn Iterator + '_> {
+        T::iter_prefix(self, ctx, prefix)
+    }
+
     fn commit(&mut self, ctx: Context, namespace: Namespace, version: u64) -> Result<Hash> {
         T::commit(self, ctx, namespace, version)
     }
@@ -290,7 +522,48 @@ impl<T: FallibleMKVS + ?Sized> FallibleMKVS for &mut T {
 mod _tests {
     use super::*;
 
-    use crate::common::cbor;
+    use crate::{
+        common::cbor,
+        storage::mkvs::{sync::NoopReadSyncer, tree::RootType},
+    };
+
+    /// MKVS which only implements the required methods.
+    struct RequiredOnly(Tree);
+
+    impl MKVS for RequiredOnly {
+        fn get(&self, ctx: Context, key: &[u8]) -> Option<Vec<u8>> {
+            FallibleMKVS::get(&self.0, ctx, key).unwrap()
+        }
+
+        fn cache_contains_key(&self, ctx: Context, key: &[u8]) -> bool {
+            FallibleMKVS::cache_contains_key(&self.0, ctx, key)
+        }
+
+        fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+            FallibleMKVS::insert(&mut self.0, ctx, key, value).unwrap()
+        }
+
+        fn remove(&mut self, ctx: Context, key: &[u8]) -> Option<Vec<u8>> {
+            FallibleMKVS::remove(&mut self.0, ctx, key).unwrap()
+        }
+
+        fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<Prefix>, limit: u16) {
+            FallibleMKVS::prefetch_prefixes(&self.0, ctx, prefixes, limit).unwrap()
+        }
+
+        fn iter(&self, ctx: Context) -> Box<dyn Iterator + '_> {
+            FallibleMKVS::iter(&self.0, ctx)
+        }
+
+        fn commit(
+            &mut self,
+            _ctx: Context,
+            _namespace: Namespace,
+            _version: u64,
+        ) -> Result<(WriteLog, Hash)> {
+            unimplemented!();
+        }
+    }
 
     #[test]
     fn test_write_log_serialization() {
@@ -304,4 +577,25 @@ mod _tests {
 
         assert_eq!(write_log, deserialized);
     }
+
+    #[test]
+    fn test_default_remove() {
+        let mut mkvs = RequiredOnly(
+            Tree::make()
+                .with_root_type(RootType::State)
+                .new(Box::new(NoopReadSyncer)),
+        );
+        for key in &[&b"a"[..], b"b", b"ba", b"bb", b"c", b"ca", b"d"] {
+            mkvs.insert(Context::background(), key, b"value");
+        }
+
+        mkvs.remove_prefix(Context::background(), b"b");
+        mkvs.remove_range(Context::background(), b"c", b"d");
+        mkvs.prefetch_keys(Context::background(), &[b"a".to_vec()]);
+
+        let mut it = mkvs.iter(Context::background());
+        it.rewind();
+        let keys: Vec<Vec<u8>> = it.map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"a".to_vec(), b"d".to_vec()]);
+    }
 }
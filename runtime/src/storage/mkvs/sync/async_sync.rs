@@ -0,0 +1,29 @@
+use futures::Future;
+use io_context::Context;
+
+use super::{GetManyRequest, GetPrefixesRequest, GetRequest, IterateRequest, ProofResponse};
+
+/// Boxed future type returned by asynchronous read syncers.
+pub type BoxFuture<T> = Box<dyn Future<Item = T, Error = anyhow::Error> + Send>;
+
+/// AsyncReadSync is the asynchronous variant of the `ReadSync` interface.
+///
+/// Methods take `&self` so that multiple requests can be in flight at once.
+pub trait AsyncReadSync: Send + Sync {
+    /// Fetch a single key and returns the corresponding proof.
+    fn sync_get(&self, ctx: Context, request: GetRequest) -> BoxFuture<ProofResponse>;
+
+    /// Fetch multiple keys and returns a single proof covering all of them.
+    fn sync_get_many(&self, ctx: Context, request: GetManyRequest) -> BoxFuture<ProofResponse>;
+
+    /// Fetch all keys under the given prefixes and returns the corresponding proofs.
+    fn sync_get_prefixes(
+        &self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> BoxFuture<ProofResponse>;
+
+    /// Seek to a given key and then fetch the specified number of following (or
+    /// preceding in case of reverse iteration) items based on key iteration order.
+    fn sync_iterate(&self, ctx: Context, request: IterateRequest) -> BoxFuture<ProofResponse>;
+}
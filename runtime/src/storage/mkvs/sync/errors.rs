@@ -4,4 +4,10 @@ use thiserror::Error;
 pub enum SyncerError {
     #[error("mkvs: method not supported")]
     Unsupported,
+    #[error("mkvs: invalid root")]
+    InvalidRoot,
+    #[error("mkvs: root is dirty")]
+    DirtyRoot,
+    #[error("mkvs: reverse iteration not supported by the remote syncer")]
+    ReverseIterationUnsupported,
 }
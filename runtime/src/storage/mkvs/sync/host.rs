@@ -52,6 +52,10 @@ impl ReadSync for HostReadSyncer {
         self.make_request_with_proof(ctx, StorageSyncRequest::SyncGet(request))
     }
 
+    fn sync_get_many(&mut self, ctx: Context, request: GetManyRequest) -> Result<ProofResponse> {
+        self.make_request_with_proof(ctx, StorageSyncRequest::SyncGetMany(request))
+    }
+
     fn sync_get_prefixes(
         &mut self,
         ctx: Context,
@@ -61,6 +65,9 @@ impl ReadSync for HostReadSyncer {
     }
 
     fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        if request.reverse {
+            return Err(SyncerError::ReverseIterationUnsupported.into());
+        }
         self.make_request_with_proof(ctx, StorageSyncRequest::SyncIterate(request))
     }
 }
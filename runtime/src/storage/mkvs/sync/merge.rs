@@ -1,6 +1,8 @@
+use std::{collections::HashMap, rc::Rc};
+
 use anyhow::{anyhow, Result};
 
-use crate::storage::mkvs::tree::*;
+use crate::{common::crypto::hash::Hash, storage::mkvs::tree::*};
 
 /// Merges a previously verified subtree with an existing tree.
 pub fn merge_verified_subtree(
@@ -8,22 +10,24 @@ pub fn merge_verified_subtree(
     subtree: NodePtrRef,
     updater: &mut Vec<NodePtrRef>,
 ) -> Result<()> {
+    if subtree.borrow().is_null() || Rc::ptr_eq(&dst, &subtree) {
+        return Ok(());
+    }
+
+    let clean = dst.borrow().clean;
+    if !clean {
+        // The destination subtree has been modified, so its structure may
+        // differ from the structure of the verified subtree.
+        return merge_verified_subtree_by_hash(dst, subtree, updater);
+    }
+
     let dst_ref = dst;
     let mut dst = dst_ref.borrow_mut();
     let subtree = subtree.borrow();
-    if dst.is_null() || subtree.is_null() {
+    if dst.is_null() {
         return Ok(());
     }
 
-    if !dst.clean {
-        // TODO: Support merging into non-clean subtrees. If a subtree
-        //       is not clean, this means that the tree structure may
-        //       be changed.
-        return Err(anyhow!(
-            "merger: merging into non-clean subtree not yet supported"
-        ));
-    }
-
     // If the destination pointer is clean, sanity check that we are
     // merging correct nodes.
     if dst.hash != subtree.hash {
@@ -59,3 +63,63 @@ pub fn merge_verified_subtree(
 
     Ok(())
 }
+
+/// Merges a previously verified subtree with an existing tree whose structure
+/// may differ due to local modifications.
+///
+/// Verified nodes are attached to any unmodified parts of the destination
+/// with the same hash, so no hash mismatch is possible.
+pub fn merge_verified_subtree_by_hash(
+    dst: NodePtrRef,
+    subtree: NodePtrRef,
+    updater: &mut Vec<NodePtrRef>,
+) -> Result<()> {
+    let mut verified = HashMap::new();
+    collect_verified_nodes(&subtree, &mut verified);
+    merge_into_modified(dst, &verified, updater)
+}
+
+/// Index all nodes included in a verified subtree by their hash.
+fn collect_verified_nodes(ptr: &NodePtrRef, verified: &mut HashMap<Hash, NodePtrRef>) {
+    let node_ref = match ptr.borrow().node {
+        Some(ref node_ref) => node_ref.clone(),
+        None => return,
+    };
+    verified.insert(ptr.borrow().hash, ptr.clone());
+
+    let node = node_ref.borrow();
+    if let NodeBox::Internal(ref n) = *node {
+        collect_verified_nodes(&n.left, verified);
+        collect_verified_nodes(&n.right, verified);
+    }
+}
+
+/// Merges verified nodes into a subtree with uncommitted modifications.
+///
+/// Modified nodes are traversed and any clean pointers found below them are
+/// merged with the verified nodes of the same hash, if any.
+fn merge_into_modified(
+    dst: NodePtrRef,
+    verified: &HashMap<Hash, NodePtrRef>,
+    updater: &mut Vec<NodePtrRef>,
+) -> Result<()> {
+    let (clean, hash, node_ref) = {
+        let dst = dst.borrow();
+        (dst.clean, dst.hash, dst.node.clone())
+    };
+    if clean {
+        return match verified.get(&hash) {
+            Some(subtree) => merge_verified_subtree(dst, subtree.clone(), updater),
+            None => Ok(()),
+        };
+    }
+
+    if let Some(node_ref) = node_ref {
+        if let NodeBox::Internal(ref n) = *node_ref.borrow() {
+            merge_into_modified(n.left.clone(), verified, updater)?;
+            merge_into_modified(n.right.clone(), verified, updater)?;
+        }
+    }
+
+    Ok(())
+}
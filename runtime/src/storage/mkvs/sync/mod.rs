@@ -1,4 +1,5 @@
 //! The read-only tree sync interface.
+mod async_sync;
 mod errors;
 mod host;
 mod merge;
@@ -7,6 +8,7 @@ mod proof;
 mod stats;
 mod sync;
 
+pub use async_sync::*;
 pub use errors::*;
 pub use host::*;
 pub use merge::*;
@@ -17,6 +17,10 @@ impl ReadSync for NoopReadSyncer {
         Err(SyncerError::Unsupported.into())
     }
 
+    fn sync_get_many(&mut self, _ctx: Context, _request: GetManyRequest) -> Result<ProofResponse> {
+        Err(SyncerError::Unsupported.into())
+    }
+
     fn sync_get_prefixes(
         &mut self,
         _ctx: Context,
@@ -1,4 +1,7 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
 
 use anyhow::{anyhow, Result};
 use arbitrary::Arbitrary;
@@ -63,6 +66,132 @@ pub struct Proof {
     pub entries: Vec<Option<RawProofEntry>>,
 }
 
+struct ProofNode {
+    serialized: Vec<u8>,
+    children: Vec<Hash>,
+}
+
+/// A Merkle proof builder.
+pub struct ProofBuilder {
+    root: Hash,
+    subtree: Hash,
+    included: HashMap<Hash, ProofNode>,
+    size: u64,
+}
+
+impl ProofBuilder {
+    /// Create a new Merkle proof builder for the given root.
+    pub fn new(root: Hash, subtree: Hash) -> Self {
+        Self {
+            root,
+            subtree,
+            included: HashMap::new(),
+            size: 0,
+        }
+    }
+
+    /// Add a node to the set of included nodes.
+    ///
+    /// The node must be clean.
+    pub fn include(&mut self, node: &NodeBox) {
+        if !node.is_clean() {
+            panic!("proof: attempted to add a dirty node");
+        }
+
+        // If node is already included, skip it.
+        let nh = node.get_hash();
+        if self.included.contains_key(&nh) {
+            return;
+        }
+
+        // Node is available, serialize it.
+        let serialized = node
+            .compact_marshal_binary()
+            .expect("proof: failed to marshal node");
+
+        // For internal nodes, also add any children.
+        let mut children = Vec::new();
+        if let NodeBox::Internal(ref n) = node {
+            // NOTE: LeafNode is always included with the internal node.
+            children.push(n.left.borrow().hash);
+            children.push(n.right.borrow().hash);
+        }
+
+        self.size += 1 + serialized.len() as u64;
+        self.included.insert(
+            nh,
+            ProofNode {
+                serialized,
+                children,
+            },
+        );
+    }
+
+    /// Return true if the subtree root node has already been included.
+    pub fn has_subtree_root(&self) -> bool {
+        self.included.contains_key(&self.subtree)
+    }
+
+    /// Return the subtree root hash for this proof.
+    pub fn get_subtree_root(&self) -> Hash {
+        self.subtree
+    }
+
+    /// Return the current size of this proof.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Build the proof.
+    pub fn build(&self, _ctx: Context) -> Result<Proof> {
+        let untrusted_root = if self.has_subtree_root() {
+            // A partial proof for the subtree is available, include that.
+            self.subtree
+        } else {
+            // No partial proof available, we need to use the tree root.
+            self.root
+        };
+
+        let mut proof = Proof {
+            untrusted_root,
+            entries: Vec::new(),
+        };
+        self._build(&mut proof, &untrusted_root);
+
+        Ok(proof)
+    }
+
+    fn _build(&self, proof: &mut Proof, h: &Hash) {
+        if h.is_empty() {
+            // Append nil for empty nodes.
+            proof.entries.push(None);
+            return;
+        }
+
+        match self.included.get(h) {
+            None => {
+                // Node is not included in this proof, just add hash of subtree.
+                let mut entry = Vec::with_capacity(1 + Hash::len());
+                entry.push(PROOF_ENTRY_HASH);
+                entry.extend_from_slice(h.as_ref());
+                proof.entries.push(Some(entry.into()));
+            }
+            Some(pn) => {
+                // Pre-order traversal, add visited node.
+                let mut entry = Vec::with_capacity(1 + pn.serialized.len());
+                entry.push(PROOF_ENTRY_FULL);
+                entry.extend_from_slice(&pn.serialized);
+                proof.entries.push(Some(entry.into()));
+
+                // And then add any children.
+                for child in &pn.children {
+                    self._build(proof, child);
+                }
+            }
+        }
+    }
+}
+
 /// A proof verifier enables verifying proofs returned by the ReadSyncer API.
 pub struct ProofVerifier;
 
@@ -96,6 +225,67 @@ impl ProofVerifier {
         Ok(root_node)
     }
 
+    /// Verify a proof that the given key is not present in the tree with the
+    /// given root.
+    ///
+    /// Proofs of absence can be generated using `Tree::prove_absence`. The
+    /// proof must include all nodes on the path from the root towards the key
+    /// up to the point where the path diverges from the key.
+    pub fn verify_absence(
+        &self,
+        ctx: Context,
+        root: Hash,
+        key: &[u8],
+        proof: &Proof,
+    ) -> Result<()> {
+        let root_ptr = self.verify_proof(ctx, root, proof)?;
+        let key = key.to_vec();
+
+        let mut ptr = root_ptr;
+        let mut bit_depth: Depth = 0;
+        loop {
+            let node_ref = {
+                let ptr = ptr.borrow();
+                if ptr.is_null() {
+                    // Reached a nil node, there is nothing here.
+                    return Ok(());
+                }
+                match ptr.node {
+                    Some(ref node_ref) => node_ref.clone(),
+                    None => {
+                        return Err(anyhow!("verifier: proof is missing nodes on the key path"))
+                    }
+                }
+            };
+
+            let next = match *node_ref.borrow() {
+                NodeBox::Internal(ref n) => {
+                    // The key would be stored below this node only if it matches the label.
+                    if !n.label_matches(bit_depth, &key) {
+                        return Ok(());
+                    }
+
+                    let bit_length = bit_depth + n.label_bit_length;
+                    bit_depth = bit_length;
+                    if key.bit_length() == bit_length {
+                        n.leaf_node.clone()
+                    } else if key.get_bit(bit_length) {
+                        n.right.clone()
+                    } else {
+                        n.left.clone()
+                    }
+                }
+                NodeBox::Leaf(ref n) => {
+                    if n.key == key {
+                        return Err(anyhow!("verifier: key is present in the tree"));
+                    }
+                    return Ok(());
+                }
+            };
+            ptr = next;
+        }
+    }
+
     fn _verify_proof(&self, proof: &Proof, idx: usize) -> Result<(usize, NodePtrRef)> {
         if idx >= proof.entries.len() {
             return Err(anyhow!("verifier: malformed proof"));
@@ -154,10 +344,209 @@ mod test {
     use base64;
     use io_context::Context;
 
-    use crate::common::cbor;
+    use crate::{
+        common::cbor,
+        storage::mkvs::{cache::Cache, sync::NoopReadSyncer},
+    };
 
     use super::*;
 
+    #[test]
+    fn test_proof_builder() {
+        // Build a simple in-memory Merkle tree.
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..10 {
+            let key = format!("key {}", i);
+            let value = format!("value {}", i);
+            tree.insert(Context::background(), key.as_bytes(), value.as_bytes())
+                .expect("insert");
+        }
+        let root_hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+
+        // Create a Merkle proof, starting at the root node.
+        let mut builder = ProofBuilder::new(root_hash, root_hash);
+        assert!(
+            !builder.has_subtree_root(),
+            "has_subtree_root should return false"
+        );
+        assert_eq!(
+            builder.get_subtree_root(),
+            root_hash,
+            "get_subtree_root should return correct root"
+        );
+
+        let root_only_proof = builder
+            .build(Context::background())
+            .expect("build should not fail without a root present");
+
+        // Include root node.
+        let root_ptr = tree.cache.borrow().get_pending_root();
+        let root_node = root_ptr.borrow().get_node();
+        builder.include(&*root_node.borrow());
+        assert!(
+            builder.has_subtree_root(),
+            "has_subtree_root should return true after root included"
+        );
+
+        let proof = builder
+            .build(Context::background())
+            .expect("build should not fail");
+        assert_eq!(
+            proof.untrusted_root, root_hash,
+            "untrusted_root should be correct"
+        );
+        assert_eq!(
+            proof.entries.len(),
+            3,
+            "proof should only contain the root and two child hashes"
+        );
+
+        // Include root.left node.
+        let left_ptr = noderef_as!(root_node, Internal).left.clone();
+        let left_node = left_ptr.borrow().get_node();
+        builder.include(&*left_node.borrow());
+
+        let proof = builder
+            .build(Context::background())
+            .expect("build should not fail");
+        // Pre-order: root(full), root.left(full), root.left.left(hash), root.left.right(hash), root.right(hash)
+        assert_eq!(
+            proof.entries.len(),
+            5,
+            "proof should only contain the correct amount of nodes"
+        );
+        let entry_types: Vec<u8> = proof
+            .entries
+            .iter()
+            .map(|e| e.as_ref().unwrap()[0])
+            .collect();
+        assert_eq!(
+            entry_types,
+            vec![
+                PROOF_ENTRY_FULL,
+                PROOF_ENTRY_FULL,
+                PROOF_ENTRY_HASH,
+                PROOF_ENTRY_HASH,
+                PROOF_ENTRY_HASH
+            ],
+            "proof entries should have the correct types"
+        );
+
+        // Proof should be stable and match the Go test vector.
+        let test_vector_proof = base64::encode(&cbor::to_vec(&proof));
+        assert_eq!(
+            test_vector_proof,
+            "omdlbnRyaWVzhVIBAQAAAAAAAAAAJABrZXkgMAJOAQEAAAAAAAAAAAEAAAJYIQLfcbr2Zv0eZpMHlih4wq2kOBFhVcnJrZxX6NcwiYk7r1ghAt978txVc0EYdJJeTFslGslX071IXQB2AjNmdUK72P2IWCEC2cyKQF3sbrxpyUzrjSjTbzpWcb/S5InBJOw0IWchn9NudW50cnVzdGVkX3Jvb3RYIF655z+dXJ64QCdz4e69vE1azM6nxnpdzpH/jE6h1cys",
+        );
+        assert_eq!(
+            format!("{:?}", root_hash),
+            "5eb9e73f9d5c9eb8402773e1eebdbc4d5acccea7c67a5dce91ff8c4ea1d5ccac"
+        );
+
+        // Proof should verify.
+        let pv = ProofVerifier;
+        pv.verify_proof(Context::background(), root_hash, &proof)
+            .expect("verify proof should not fail with a valid proof");
+
+        // Proof with only the root node should verify.
+        pv.verify_proof(Context::background(), root_hash, &root_only_proof)
+            .expect("verify proof should not fail on a proof with only the root node");
+
+        // Empty root proof should verify.
+        let builder = ProofBuilder::new(Hash::empty_hash(), Hash::empty_hash());
+        let empty_root_proof = builder
+            .build(Context::background())
+            .expect("build should not fail for an empty root");
+        let empty_root_ptr = pv
+            .verify_proof(Context::background(), Hash::empty_hash(), &empty_root_proof)
+            .expect("verify proof should not fail with a valid proof for an empty root");
+        assert!(
+            empty_root_ptr.borrow().is_null(),
+            "verify proof should return a null pointer for an empty root"
+        );
+    }
+
+    #[test]
+    fn test_proof_absence() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..10 {
+            let key = format!("key {}", i);
+            let value = format!("value {}", i);
+            tree.insert(Context::background(), key.as_bytes(), value.as_bytes())
+                .expect("insert");
+        }
+        let root_hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+
+        let pv = ProofVerifier;
+        let absent_keys: Vec<&[u8]> = vec![b"", b"k", b"key", b"key 10", b"key 1\x00", b"zebra"];
+        for key in &absent_keys {
+            let proof = tree
+                .prove_absence(Context::background(), key)
+                .expect("prove_absence should not fail for absent keys");
+            pv.verify_absence(Context::background(), root_hash, key, &proof)
+                .expect("verify_absence should not fail with a valid proof");
+        }
+
+        // Proofs for present keys cannot be built and do not verify.
+        let result = tree.prove_absence(Context::background(), b"key 1");
+        assert!(
+            matches!(
+                result.unwrap_err().downcast_ref(),
+                Some(TreeError::KeyExists)
+            ),
+            "prove_absence should fail for present keys"
+        );
+        let proof = tree
+            .prove_absence(Context::background(), b"key 10")
+            .expect("prove_absence");
+        let result = pv.verify_absence(Context::background(), root_hash, b"key 1", &proof);
+        assert!(
+            result.is_err(),
+            "verify_absence should fail for present keys"
+        );
+
+        // Proofs which do not include the key path should not verify.
+        let mut builder = ProofBuilder::new(root_hash, root_hash);
+        let root_ptr = tree.cache.borrow().get_pending_root();
+        builder.include(&*root_ptr.borrow().get_node().borrow());
+        let partial = builder.build(Context::background()).expect("build");
+        let result = pv.verify_absence(Context::background(), root_hash, b"key 10", &partial);
+        assert!(
+            result.is_err(),
+            "verify_absence should fail with an incomplete proof"
+        );
+
+        // Proofs for a different root should not verify.
+        let bogus_hash = Hash::digest_bytes(b"i am a bogus hash");
+        let result = pv.verify_absence(Context::background(), bogus_hash, b"key 10", &proof);
+        assert!(
+            result.is_err(),
+            "verify_absence should fail with a proof for a different root"
+        );
+
+        // Any key is absent from an empty tree.
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        let root_hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+        assert_eq!(root_hash, Hash::empty_hash());
+        let proof = tree
+            .prove_absence(Context::background(), b"key 1")
+            .expect("prove_absence");
+        pv.verify_absence(Context::background(), Hash::empty_hash(), b"key 1", &proof)
+            .expect("verify_absence should not fail for an empty tree");
+    }
+
     #[test]
     fn test_proof() {
         // Test vector generated by Go.
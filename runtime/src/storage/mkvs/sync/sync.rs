@@ -3,7 +3,7 @@ use std::any::Any;
 use anyhow::Result;
 use io_context::Context;
 use serde::{Deserialize, Serialize};
-use serde_bytes;
+use serde_bytes::{self, ByteBuf};
 
 use crate::{
     common::crypto::hash::Hash,
@@ -32,6 +32,13 @@ pub struct GetRequest {
     pub include_siblings: bool,
 }
 
+/// Request for the SyncGetMany operation.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GetManyRequest {
+    pub tree: TreeID,
+    pub keys: Vec<ByteBuf>,
+}
+
 /// Request for the SyncGetPrefixes operation.
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct GetPrefixesRequest {
@@ -47,6 +54,15 @@ pub struct IterateRequest {
     #[serde(with = "serde_bytes")]
     pub key: Vec<u8>,
     pub prefetch: u16,
+    /// Whether to iterate in reverse key order. In this case an empty key
+    /// denotes the last key in the tree.
+    ///
+    /// This is not supported by the Go storage backends, so syncers which
+    /// forward requests to them reject reverse requests with
+    /// `SyncerError::ReverseIterationUnsupported`. The tree iterator then
+    /// falls back to fetching the nodes it needs without prefetching.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reverse: bool,
 }
 
 /// Response for requests that produce proofs.
@@ -64,6 +80,9 @@ pub trait ReadSync {
     /// Fetch a single key and returns the corresponding proof.
     fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse>;
 
+    /// Fetch multiple keys and returns a single proof covering all of them.
+    fn sync_get_many(&mut self, ctx: Context, request: GetManyRequest) -> Result<ProofResponse>;
+
     /// Fetch all keys under the given prefixes and returns the corresponding proofs.
     fn sync_get_prefixes(
         &mut self,
@@ -71,7 +90,7 @@ pub trait ReadSync {
         request: GetPrefixesRequest,
     ) -> Result<ProofResponse>;
 
-    /// Seek to a given key and then fetch the specified number of following items
-    /// based on key iteration order.
+    /// Seek to a given key and then fetch the specified number of following (or
+    /// preceding in case of reverse iteration) items based on key iteration order.
     fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse>;
 }
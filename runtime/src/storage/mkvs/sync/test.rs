@@ -1,10 +1,12 @@
+use std::iter;
+
 use io_context::Context;
 
 use crate::storage::mkvs::{
     interop::{Driver, ProtocolServer},
     sync::*,
     tree::*,
-    LogEntry,
+    Iterator, LogEntry,
 };
 
 #[test]
@@ -57,3 +59,96 @@ fn test_nil_pointers() {
         .insert(Context::background(), b"insert", b"key")
         .expect("insert");
 }
+
+#[test]
+fn test_tree_read_syncer() {
+    let mut tree = Tree::make()
+        .with_root_type(RootType::State)
+        .new(Box::new(NoopReadSyncer));
+
+    let items: Vec<(Vec<u8>, Vec<u8>)> = (0..100)
+        .map(|i| {
+            (
+                format!("key {}", i).into_bytes(),
+                format!("value {}", i).into_bytes(),
+            )
+        })
+        .collect();
+    for (key, value) in items.iter() {
+        tree.insert(Context::background(), key, value)
+            .expect("insert");
+    }
+    let hash =
+        Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    let root = Root {
+        root_type: RootType::State,
+        hash,
+        ..Default::default()
+    };
+
+    // Serve a remote tree directly from the committed local tree.
+    let stats = StatsCollector::new(Box::new(tree));
+    let remote = Tree::make()
+        .with_capacity(0, 0)
+        .with_root(root)
+        .new(Box::new(stats));
+
+    for (key, value) in items.iter() {
+        let result = remote.get(Context::background(), key).expect("get");
+        assert_eq!(
+            result.as_ref(),
+            Some(value),
+            "get should return the correct value"
+        );
+    }
+    let result = remote
+        .get(Context::background(), b"missing key")
+        .expect("get");
+    assert_eq!(result, None, "get should not return a missing key");
+
+    let mut it = remote.iter(Context::background());
+    it.set_prefetch(10);
+    it.rewind();
+    assert_eq!(
+        iter::Iterator::count(it),
+        items.len(),
+        "iterator should go over all items"
+    );
+
+    // Requests for a different root should be rejected.
+    let mut tree = Tree::make()
+        .with_root_type(RootType::State)
+        .new(Box::new(NoopReadSyncer));
+    Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+    let result = tree.sync_get(
+        Context::background(),
+        GetRequest {
+            tree: TreeID {
+                root,
+                position: hash,
+            },
+            key: b"key 1".to_vec(),
+            include_siblings: false,
+        },
+    );
+    assert!(result.is_err(), "sync_get should fail for an unknown root");
+}
+
+#[test]
+fn test_merge_hash_mismatch() {
+    let leaf = |key: &[u8]| {
+        let mut node = NodeBox::Leaf(LeafNode {
+            clean: true,
+            key: key.to_vec(),
+            value: b"value".to_vec(),
+            ..Default::default()
+        });
+        node.update_hash();
+        NodePointer::from_node(node)
+    };
+
+    // Merging a verified subtree into a clean pointer with a different hash must fail.
+    let mut updater = Vec::new();
+    assert!(merge_verified_subtree(leaf(b"foo"), leaf(b"bar"), &mut updater).is_err());
+    assert!(updater.is_empty());
+}
@@ -0,0 +1,228 @@
+//! Tree statistics.
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use io_context::Context;
+
+use crate::storage::mkvs::{cache::*, tree::*};
+
+/// Number of entries to prefetch when syncing nodes during analysis.
+const ANALYZE_PREFETCH: usize = 1000;
+/// Number of largest entries to report.
+const ANALYZE_LARGEST_ENTRIES: usize = 10;
+/// Length of key prefixes (in bytes) to report usage for.
+const ANALYZE_PREFIX_LENGTH: usize = 1;
+
+/// Statistics about the structure and contents of a whole tree.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TreeStats {
+    /// Number of keys in the tree.
+    pub key_count: u64,
+    /// Number of internal nodes in the tree.
+    pub internal_node_count: u64,
+    /// Total size of all keys, in bytes.
+    pub key_size: u64,
+    /// Total size of all values, in bytes.
+    pub value_size: u64,
+    /// Number of keys by their depth, which is the number of internal nodes
+    /// on the path from the root to the key.
+    pub depth_histogram: BTreeMap<usize, u64>,
+    /// Number of values by their size, bucketed by the next power of two.
+    pub value_size_histogram: BTreeMap<usize, u64>,
+    /// The largest entries as pairs of key and entry (key and value) size,
+    /// largest first.
+    pub largest_entries: Vec<(Vec<u8>, usize)>,
+    /// Total size of entries (keys and values) by key prefix.
+    pub prefix_usage: BTreeMap<Vec<u8>, u64>,
+}
+
+impl TreeStats {
+    fn add_entry(&mut self, depth: usize, key: &[u8], value: &[u8]) {
+        let size = key.len() + value.len();
+
+        self.key_count += 1;
+        self.key_size += key.len() as u64;
+        self.value_size += value.len() as u64;
+        *self.depth_histogram.entry(depth).or_default() += 1;
+        *self
+            .value_size_histogram
+            .entry(value.len().next_power_of_two())
+            .or_default() += 1;
+
+        let prefix = &key[..key.len().min(ANALYZE_PREFIX_LENGTH)];
+        *self.prefix_usage.entry(prefix.to_vec()).or_default() += size as u64;
+    }
+}
+
+impl Tree {
+    /// Walk the whole tree and collect statistics about its structure and
+    /// contents.
+    ///
+    /// Any nodes missing from the cache are fetched from the read syncer. Unlike
+    /// the cache statistics, the result covers the entire tree.
+    pub fn analyze(&self, ctx: Context) -> Result<TreeStats> {
+        let ctx = ctx.freeze();
+        let mut stats = TreeStats::default();
+        let mut largest = BinaryHeap::new();
+
+        let pending_root = self.cache.borrow().get_pending_root();
+        self._analyze(
+            &ctx,
+            pending_root,
+            0,
+            Key::new(),
+            0,
+            &mut stats,
+            &mut largest,
+        )?;
+
+        stats.largest_entries = largest
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((size, key))| (key, size))
+            .collect();
+
+        Ok(stats)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn _analyze(
+        &self,
+        ctx: &Arc<Context>,
+        ptr: NodePtrRef,
+        bit_depth: Depth,
+        path: Key,
+        depth: usize,
+        stats: &mut TreeStats,
+        largest: &mut BinaryHeap<Reverse<(usize, Vec<u8>)>>,
+    ) -> Result<()> {
+        let node_ref = self.cache.borrow_mut().deref_node_ptr(
+            ctx,
+            ptr,
+            Some(FetcherSyncIterate::new(&path, ANALYZE_PREFETCH, false)),
+        )?;
+
+        match classify_noderef!(?node_ref) {
+            NodeKind::None => Ok(()),
+            NodeKind::Internal => {
+                let node_ref = node_ref.unwrap();
+                if let NodeBox::Internal(ref n) = *node_ref.borrow() {
+                    stats.internal_node_count += 1;
+
+                    let bit_length = bit_depth + n.label_bit_length;
+                    let new_path = path.merge(bit_depth, &n.label, n.label_bit_length);
+
+                    self._analyze(
+                        ctx,
+                        n.leaf_node.clone(),
+                        bit_length,
+                        new_path.clone(),
+                        depth + 1,
+                        stats,
+                        largest,
+                    )?;
+                    self._analyze(
+                        ctx,
+                        n.left.clone(),
+                        bit_length,
+                        new_path.append_bit(bit_length, false),
+                        depth + 1,
+                        stats,
+                        largest,
+                    )?;
+                    return self._analyze(
+                        ctx,
+                        n.right.clone(),
+                        bit_length,
+                        new_path.append_bit(bit_length, true),
+                        depth + 1,
+                        stats,
+                        largest,
+                    );
+                }
+
+                unreachable!("node kind is internal node");
+            }
+            NodeKind::Leaf => {
+                let node_ref = node_ref.unwrap();
+                if let NodeBox::Leaf(ref n) = *node_ref.borrow() {
+                    stats.add_entry(depth, &n.key, &n.value);
+
+                    largest.push(Reverse((n.key.len() + n.value.len(), n.key.clone())));
+                    if largest.len() > ANALYZE_LARGEST_ENTRIES {
+                        largest.pop();
+                    }
+                    return Ok(());
+                }
+
+                unreachable!("node kind is leaf node");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::mkvs::sync::*;
+
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        assert_eq!(
+            tree.analyze(Context::background()).expect("analyze"),
+            TreeStats::default()
+        );
+
+        for i in 0..100u32 {
+            let key = format!("{}key {:03}", if i % 2 == 0 { 'a' } else { 'b' }, i);
+            let value = vec![0u8; i as usize];
+            tree.insert(Context::background(), key.as_bytes(), &value)
+                .expect("insert");
+        }
+
+        // Uncommitted trees can be analyzed as well.
+        let stats = tree.analyze(Context::background()).expect("analyze");
+        assert_eq!(stats.key_count, 100);
+        assert_eq!(stats.key_size, 100 * 8);
+        assert_eq!(stats.value_size, (0..100).sum::<u64>());
+        assert_eq!(stats.depth_histogram.values().sum::<u64>(), 100);
+        assert!(stats.internal_node_count >= 99);
+        assert_eq!(stats.value_size_histogram[&1], 2);
+        assert_eq!(stats.value_size_histogram[&128], 35);
+        assert_eq!(
+            stats.prefix_usage[&b"a".to_vec()],
+            50 * 8 + (0..100).step_by(2).sum::<u64>()
+        );
+        assert_eq!(
+            stats.prefix_usage[&b"b".to_vec()],
+            50 * 8 + (1..100).step_by(2).sum::<u64>()
+        );
+        assert_eq!(stats.largest_entries.len(), ANALYZE_LARGEST_ENTRIES);
+        assert_eq!(stats.largest_entries[0], (b"bkey 099".to_vec(), 8 + 99));
+        assert_eq!(stats.largest_entries[1], (b"akey 098".to_vec(), 8 + 98));
+
+        // Analyzing a remote tree should fetch all nodes and give the same results.
+        let hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+        let remote_tree = Tree::make()
+            .with_root(Root {
+                root_type: RootType::State,
+                hash,
+                ..Default::default()
+            })
+            .new(Box::new(tree));
+        assert_eq!(
+            remote_tree.analyze(Context::background()).expect("analyze"),
+            stats
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Applying write logs to trees.
+use anyhow::Result;
+use io_context::Context;
+
+use crate::{
+    common::{crypto::hash::Hash, namespace::Namespace},
+    storage::mkvs::{sync::*, tree::*, WriteLog},
+};
+
+impl Tree {
+    /// Apply the given write log to the tree and commit the result under the
+    /// given namespace and version, returning the new root hash.
+    ///
+    /// Entries are applied in order. Entries without a value remove the
+    /// corresponding key. The tree is left unmodified if any entry exceeds the
+    /// configured size limits.
+    ///
+    /// Application is not atomic otherwise. If an entry fails to apply (e.g.,
+    /// because a node cannot be fetched from the read syncer), the entries
+    /// before it remain applied and nothing is committed. The tree should be
+    /// discarded in this case.
+    pub fn apply_write_log(
+        &mut self,
+        ctx: Context,
+        write_log: &WriteLog,
+        namespace: Namespace,
+        version: u64,
+    ) -> Result<Hash> {
+        let ctx = ctx.freeze();
+        for entry in write_log {
+            if let Some(ref value) = entry.value {
+                self.check_limits(&entry.key, value)?;
+            }
+        }
+        for entry in write_log {
+            match entry.value {
+                Some(ref value) => {
+                    self.insert(Context::create_child(&ctx), &entry.key, value)?;
+                }
+                None => {
+                    self.remove(Context::create_child(&ctx), &entry.key)?;
+                }
+            }
+        }
+
+        self.commit(Context::create_child(&ctx), namespace, version)
+    }
+}
+
+/// Verify that applying the write log to the tree at `old_root` results in
+/// the tree at `expected_new_root`.
+///
+/// Any nodes of the old tree that are needed are fetched from the given read
+/// syncer. The resulting tree is committed under the namespace and version of
+/// the expected new root.
+pub fn verify_transition(
+    ctx: Context,
+    read_syncer: Box<dyn ReadSync>,
+    old_root: Root,
+    write_log: &WriteLog,
+    expected_new_root: Root,
+) -> Result<()> {
+    if old_root.root_type != expected_new_root.root_type {
+        return Err(TreeError::RootTypeMismatch.into());
+    }
+
+    let mut tree = Tree::make().with_root(old_root).new(read_syncer);
+    let new_hash = tree.apply_write_log(
+        ctx,
+        write_log,
+        expected_new_root.namespace,
+        expected_new_root.version,
+    )?;
+    if new_hash != expected_new_root.hash {
+        return Err(TreeError::RootMismatch {
+            expected: expected_new_root.hash,
+            got: new_hash,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::mkvs::{tree::tree_test::generate_key_value_pairs_ex, LogEntry};
+
+    use super::*;
+
+    fn build(keys: &[Vec<u8>], values: &[Vec<u8>]) -> (Tree, Root) {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for (key, value) in keys.iter().zip(values.iter()) {
+            tree.insert(Context::background(), key, value)
+                .expect("insert");
+        }
+        let hash = tree
+            .commit(Context::background(), Default::default(), 1)
+            .expect("commit");
+        let root = Root {
+            version: 1,
+            root_type: RootType::State,
+            hash,
+            ..Default::default()
+        };
+        (tree, root)
+    }
+
+    #[test]
+    fn test_verify_transition() {
+        let (keys, values) = generate_key_value_pairs_ex("apply".to_string(), 100);
+        let (_, old_root) = build(&keys, &values);
+
+        // Modify, remove and insert some keys.
+        let write_log = vec![
+            LogEntry::new(&keys[0], b"modified"),
+            LogEntry {
+                key: keys[1].clone(),
+                value: None,
+            },
+            LogEntry::new(b"new key", b"new value"),
+        ];
+        let (mut tree, _) = build(&keys, &values);
+        let new_hash = tree
+            .apply_write_log(Context::background(), &write_log, Default::default(), 2)
+            .expect("apply_write_log");
+        assert_eq!(
+            tree.get(Context::background(), &keys[0]).expect("get"),
+            Some(b"modified".to_vec())
+        );
+        assert_eq!(
+            tree.get(Context::background(), &keys[1]).expect("get"),
+            None
+        );
+        let new_root = Root {
+            version: 2,
+            hash: new_hash,
+            ..old_root
+        };
+
+        // Transitions should be verified against a remote old root.
+        let (remote, _) = build(&keys, &values);
+        verify_transition(
+            Context::background(),
+            Box::new(remote),
+            old_root,
+            &write_log,
+            new_root,
+        )
+        .expect("verify_transition");
+
+        // A different write log should not result in the same root.
+        let (remote, _) = build(&keys, &values);
+        let err = verify_transition(
+            Context::background(),
+            Box::new(remote),
+            old_root,
+            &write_log[..2].to_vec(),
+            new_root,
+        )
+        .expect_err("verify_transition should fail with a different write log");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(TreeError::RootMismatch { .. })
+        ));
+
+        // Neither should a different version.
+        let (remote, _) = build(&keys, &values);
+        let err = verify_transition(
+            Context::background(),
+            Box::new(remote),
+            old_root,
+            &write_log,
+            Root {
+                version: 3,
+                ..new_root
+            },
+        )
+        .expect_err("verify_transition should fail with a different version");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(TreeError::RootMismatch { .. })
+        ));
+
+        // Roots of different types should be rejected.
+        let err = verify_transition(
+            Context::background(),
+            Box::new(NoopReadSyncer),
+            old_root,
+            &write_log,
+            Root {
+                root_type: RootType::IO,
+                ..new_root
+            },
+        )
+        .expect_err("verify_transition should fail with different root types");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(TreeError::RootTypeMismatch)
+        ));
+    }
+}
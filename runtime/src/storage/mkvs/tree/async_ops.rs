@@ -0,0 +1,334 @@
+use std::{any::Any, cell::RefCell, rc::Rc, sync::Arc};
+
+use anyhow::{anyhow, Error, Result};
+use futures::{
+    future::{self, Either, Loop},
+    Future,
+};
+use io_context::Context;
+
+use crate::storage::mkvs::{self, sync::*, tree::*};
+
+/// Future returned by asynchronous tree operations.
+///
+/// Unlike `BoxFuture`, it borrows the tree and is not `Send`.
+pub type TreeFuture<'a, T> = Box<dyn Future<Item = T, Error = Error> + 'a>;
+
+/// A sync request issued by a tree operation.
+#[derive(Clone, Debug, PartialEq)]
+enum SyncRequest {
+    Get(GetRequest),
+    GetMany(GetManyRequest),
+    GetPrefixes(GetPrefixesRequest),
+    Iterate(IterateRequest),
+}
+
+impl SyncRequest {
+    fn send(self, ctx: Context, rs: &dyn AsyncReadSync) -> BoxFuture<ProofResponse> {
+        match self {
+            SyncRequest::Get(request) => rs.sync_get(ctx, request),
+            SyncRequest::GetMany(request) => rs.sync_get_many(ctx, request),
+            SyncRequest::GetPrefixes(request) => rs.sync_get_prefixes(ctx, request),
+            SyncRequest::Iterate(request) => rs.sync_iterate(ctx, request),
+        }
+    }
+}
+
+#[derive(Default)]
+struct DeferredState {
+    /// The first request which could not be served during an operation.
+    pending: Option<SyncRequest>,
+    /// Response to the request that was pending during the previous attempt.
+    response: Option<(SyncRequest, ProofResponse)>,
+}
+
+/// A read syncer which only serves responses that have already been fetched
+/// and records any other request so that it can be performed asynchronously.
+struct DeferredReadSync(Rc<RefCell<DeferredState>>);
+
+impl DeferredReadSync {
+    fn sync(&mut self, request: SyncRequest) -> Result<ProofResponse> {
+        let mut state = self.0.borrow_mut();
+        if let Some((answered, rsp)) = state.response.take() {
+            if answered == request {
+                return Ok(rsp);
+            }
+        }
+        if state.pending.is_none() {
+            state.pending = Some(request);
+        }
+        Err(anyhow!("mkvs: fetch deferred"))
+    }
+}
+
+impl ReadSync for DeferredReadSync {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, _ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        self.sync(SyncRequest::Get(request))
+    }
+
+    fn sync_get_many(&mut self, _ctx: Context, request: GetManyRequest) -> Result<ProofResponse> {
+        self.sync(SyncRequest::GetMany(request))
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        _ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        self.sync(SyncRequest::GetPrefixes(request))
+    }
+
+    fn sync_iterate(&mut self, _ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        self.sync(SyncRequest::Iterate(request))
+    }
+}
+
+impl Tree {
+    /// Get an existing key, fetching any missing nodes asynchronously.
+    ///
+    /// The tree must have been configured with an asynchronous read syncer
+    /// using `with_async_read_syncer`.
+    pub fn get_async<'a>(&'a self, ctx: Context, key: &[u8]) -> TreeFuture<'a, Option<Vec<u8>>> {
+        let key = key.to_vec();
+        self.run_async(ctx, move |tree, ctx| tree.get(ctx, &key))
+    }
+
+    /// Iterate over at most `limit` items, starting at the given key or the
+    /// next larger key, fetching any missing nodes asynchronously.
+    ///
+    /// The tree must have been configured with an asynchronous read syncer
+    /// using `with_async_read_syncer`.
+    pub fn iter_async<'a>(
+        &'a self,
+        ctx: Context,
+        start: &[u8],
+        limit: usize,
+    ) -> TreeFuture<'a, Vec<(Vec<u8>, Vec<u8>)>> {
+        use mkvs::Iterator;
+
+        let start = start.to_vec();
+        self.run_async(ctx, move |tree, ctx| {
+            let mut it = tree.iter(ctx);
+            it.set_prefetch(limit);
+            it.seek(&start);
+
+            let mut items = Vec::new();
+            while it.is_valid() && items.len() < limit {
+                let key = it.get_key().clone().expect("iterator is valid");
+                let value = it.get_value().clone().expect("iterator is valid");
+                items.push((key, value));
+                Iterator::next(&mut it);
+            }
+            match it.take_error() {
+                Some(error) => Err(error),
+                None => Ok(items),
+            }
+        })
+    }
+
+    /// Run a tree operation, performing any fetches it requires asynchronously.
+    ///
+    /// The operation is executed with a read syncer that defers all fetches. Each
+    /// time it requires a missing node, the corresponding request is sent to the
+    /// asynchronous read syncer and the operation is restarted once the response
+    /// is available. The response is then merged into the tree the same way as
+    /// if it was returned by the regular read syncer.
+    fn run_async<'a, T, F>(&'a self, ctx: Context, op: F) -> TreeFuture<'a, T>
+    where
+        T: 'a,
+        F: Fn(&Tree, Context) -> Result<T> + 'a,
+    {
+        let rs = match self.async_read_syncer {
+            Some(ref rs) => rs.clone(),
+            None => return Box::new(future::err(SyncerError::Unsupported.into())),
+        };
+        let ctx = ctx.freeze();
+        let state = Rc::new(RefCell::new(DeferredState::default()));
+
+        Box::new(future::loop_fn(
+            None,
+            move |answered: Option<SyncRequest>| {
+                let read_syncer = self
+                    .cache
+                    .borrow_mut()
+                    .replace_read_syncer(Box::new(DeferredReadSync(state.clone())));
+                let result = op(self, Context::create_child(&ctx));
+                self.cache.borrow_mut().replace_read_syncer(read_syncer);
+
+                let request = match state.borrow_mut().pending.take() {
+                    Some(request) => request,
+                    None => return Either::A(future::result(result.map(Loop::Break))),
+                };
+                if answered.as_ref() == Some(&request) {
+                    // Nodes fetched during the previous attempt are already gone.
+                    return Either::A(future::err(anyhow!(
+                        "mkvs: fetched nodes were evicted before use (cache too small)"
+                    )));
+                }
+
+                let state = state.clone();
+                Either::B(
+                    request
+                        .clone()
+                        .send(Context::create_child(&ctx), &*rs)
+                        .map(move |rsp| {
+                            state.borrow_mut().response = Some((request.clone(), rsp));
+                            Loop::Continue(Some(request))
+                        }),
+                )
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        thread,
+    };
+
+    use futures::sync::oneshot;
+
+    use super::*;
+
+    const NUM_KEYS: usize = 100;
+
+    /// Asynchronous read syncer serving requests from a tree on separate threads.
+    struct ThreadedReadSync {
+        tree: Arc<Mutex<Tree>>,
+        requests: AtomicUsize,
+    }
+
+    impl ThreadedReadSync {
+        fn spawn<F>(&self, f: F) -> BoxFuture<ProofResponse>
+        where
+            F: FnOnce(&mut Tree) -> Result<ProofResponse> + Send + 'static,
+        {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+
+            let tree = self.tree.clone();
+            let (tx, rx) = oneshot::channel();
+            thread::spawn(move || {
+                let mut tree = tree.lock().unwrap();
+                let _ = tx.send(f(&mut tree));
+            });
+            Box::new(
+                rx.map_err(|_| anyhow!("request canceled"))
+                    .and_then(|result| result),
+            )
+        }
+    }
+
+    impl AsyncReadSync for ThreadedReadSync {
+        fn sync_get(&self, ctx: Context, request: GetRequest) -> BoxFuture<ProofResponse> {
+            self.spawn(move |tree| ReadSync::sync_get(tree, ctx, request))
+        }
+
+        fn sync_get_many(&self, ctx: Context, request: GetManyRequest) -> BoxFuture<ProofResponse> {
+            self.spawn(move |tree| ReadSync::sync_get_many(tree, ctx, request))
+        }
+
+        fn sync_get_prefixes(
+            &self,
+            ctx: Context,
+            request: GetPrefixesRequest,
+        ) -> BoxFuture<ProofResponse> {
+            self.spawn(move |tree| ReadSync::sync_get_prefixes(tree, ctx, request))
+        }
+
+        fn sync_iterate(&self, ctx: Context, request: IterateRequest) -> BoxFuture<ProofResponse> {
+            self.spawn(move |tree| ReadSync::sync_iterate(tree, ctx, request))
+        }
+    }
+
+    #[test]
+    fn test_async_get_iter() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..NUM_KEYS {
+            let key = format!("key {:03}", i);
+            let value = format!("value {}", i);
+            tree.insert(Context::background(), key.as_bytes(), value.as_bytes())
+                .expect("insert");
+        }
+        let hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+        let root = Root {
+            root_type: RootType::State,
+            hash,
+            ..Default::default()
+        };
+
+        let rs = Arc::new(ThreadedReadSync {
+            tree: Arc::new(Mutex::new(tree)),
+            requests: AtomicUsize::new(0),
+        });
+
+        // Operations fail without an asynchronous read syncer.
+        let remote_tree = Tree::make().with_root(root).new(Box::new(NoopReadSyncer));
+        assert!(remote_tree
+            .get_async(Context::background(), b"key 000")
+            .wait()
+            .is_err());
+
+        let remote_tree = Tree::make()
+            .with_root(root)
+            .with_async_read_syncer(rs.clone())
+            .new(Box::new(NoopReadSyncer));
+
+        // Issue multiple lookups concurrently.
+        let lookups: Vec<_> = (0..NUM_KEYS)
+            .step_by(10)
+            .map(|i| {
+                let key = format!("key {:03}", i);
+                remote_tree
+                    .get_async(Context::background(), key.as_bytes())
+                    .map(move |value| (i, value))
+            })
+            .collect();
+        let results = future::join_all(lookups).wait().expect("get_async");
+        for (i, value) in results {
+            assert_eq!(value, Some(format!("value {}", i).into_bytes()));
+        }
+        assert_eq!(
+            remote_tree
+                .get_async(Context::background(), b"key 1000")
+                .wait()
+                .expect("get_async"),
+            None
+        );
+
+        // Fetched nodes should be available for synchronous lookups.
+        assert!(remote_tree.cache_contains_key(Context::background(), b"key 000"));
+
+        let items = remote_tree
+            .iter_async(Context::background(), b"key 050", 20)
+            .wait()
+            .expect("iter_async");
+        assert_eq!(items.len(), 20);
+        for (idx, (key, value)) in items.into_iter().enumerate() {
+            let i = 50 + idx;
+            assert_eq!(key, format!("key {:03}", i).into_bytes());
+            assert_eq!(value, format!("value {}", i).into_bytes());
+        }
+
+        // Everything has been fetched, so iterating again should not hit the remote.
+        let requests = rs.requests.load(Ordering::SeqCst);
+        let items = remote_tree
+            .iter_async(Context::background(), b"key 050", 20)
+            .wait()
+            .expect("iter_async");
+        assert_eq!(items.len(), 20);
+        assert_eq!(rs.requests.load(Ordering::SeqCst), requests);
+    }
+}
@@ -1,11 +1,11 @@
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use io_context::Context;
 
 use crate::{
     common::{crypto::hash::Hash, namespace::Namespace},
-    storage::mkvs::{cache::*, tree::*},
+    storage::mkvs::{cache::*, db::NodeDB, tree::*},
 };
 
 impl Tree {
@@ -16,20 +16,64 @@ impl Tree {
         let mut update_list: UpdateList<LRUCache> = UpdateList::new();
         let pending_root = self.cache.borrow().get_pending_root();
         let new_hash = _commit(&ctx, pending_root.clone(), &mut update_list, Some(version))?;
-
-        update_list.commit(&mut self.cache.borrow_mut());
-
-        self.cache.borrow_mut().set_sync_root(Root {
+        let root = Root {
             namespace,
             version,
             root_type: self.root_type,
             hash: new_hash,
-        });
+        };
+
+        let mut updated_nodes = Vec::new();
+        if let Some(ref node_db) = self.node_db {
+            collect_missing_nodes(node_db.as_ref(), &pending_root, &mut updated_nodes)?;
+        }
+
+        update_list.commit(&mut self.cache.borrow_mut());
+
+        if let Some(ref node_db) = self.node_db {
+            node_db.commit(root, &updated_nodes)?;
+        }
+
+        self.cache.borrow_mut().set_sync_root(root);
 
         Ok(new_hash)
     }
 }
 
+/// Collect all nodes reachable from the given pointer that are missing from
+/// the node database, which includes all nodes updated by the commit in
+/// progress.
+///
+/// Clean nodes already in the database are assumed to have their whole subtree
+/// persisted. Returns an error if a missing node is not available locally or
+/// if the node database fails.
+fn collect_missing_nodes(
+    node_db: &dyn NodeDB,
+    ptr: &NodePtrRef,
+    nodes: &mut Vec<NodeRef>,
+) -> Result<()> {
+    let ptr = ptr.borrow();
+    if ptr.is_null() || (ptr.clean && node_db.has_node(&ptr.hash)?) {
+        return Ok(());
+    }
+
+    match ptr.node {
+        Some(ref node_ref) => {
+            if let NodeBox::Internal(ref n) = *node_ref.borrow() {
+                collect_missing_nodes(node_db, &n.leaf_node, nodes)?;
+                collect_missing_nodes(node_db, &n.left, nodes)?;
+                collect_missing_nodes(node_db, &n.right, nodes)?;
+            }
+            nodes.push(node_ref.clone());
+            Ok(())
+        }
+        None => Err(anyhow!(
+            "mkvs: node {:?} is missing from the node database and not available locally",
+            ptr.hash
+        )),
+    }
+}
+
 pub fn _commit<C: Cache>(
     ctx: &Arc<Context>,
     ptr: NodePtrRef,
@@ -0,0 +1,457 @@
+//! Write log diff between two tree roots.
+use std::{any::Any, cell::RefCell, collections::BTreeMap, rc::Rc, sync::Arc};
+
+use anyhow::Result;
+use io_context::Context;
+
+use crate::storage::mkvs::{cache::*, sync::*, tree::*, LogEntry, WriteLog};
+
+/// Number of entries to prefetch when fetching nodes which are not yet
+/// available locally.
+const DIFF_PREFETCH: usize = 100;
+
+/// A read syncer shared between the trees being compared.
+struct SharedReadSyncer(Rc<RefCell<Box<dyn ReadSync>>>);
+
+impl ReadSync for SharedReadSyncer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        self.0.borrow_mut().sync_get(ctx, request)
+    }
+
+    fn sync_get_many(&mut self, ctx: Context, request: GetManyRequest) -> Result<ProofResponse> {
+        self.0.borrow_mut().sync_get_many(ctx, request)
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        self.0.borrow_mut().sync_get_prefixes(ctx, request)
+    }
+
+    fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        self.0.borrow_mut().sync_iterate(ctx, request)
+    }
+}
+
+/// A position within one of the trees being compared.
+#[derive(Clone)]
+struct Position {
+    ptr: NodePtrRef,
+    /// Depth at which the label of the node starts.
+    bit_depth: Depth,
+    /// Path from the root to the node.
+    path: Key,
+    /// Number of bits of the path which are known.
+    path_bits: Depth,
+}
+
+impl Position {
+    fn root(ptr: NodePtrRef) -> Self {
+        Self {
+            ptr,
+            bit_depth: 0,
+            path: Key::new(),
+            path_bits: 0,
+        }
+    }
+
+    fn child(path: &Key, bit_length: Depth, ptr: NodePtrRef, bit: Option<bool>) -> Self {
+        match bit {
+            Some(bit) => Self {
+                ptr,
+                bit_depth: bit_length,
+                path: path.append_bit(bit_length, bit),
+                path_bits: bit_length + 1,
+            },
+            None => Self {
+                ptr,
+                bit_depth: bit_length,
+                path: path.clone(),
+                path_bits: bit_length,
+            },
+        }
+    }
+}
+
+/// A resolved internal node.
+struct Internal {
+    bit_length: Depth,
+    path: Key,
+    leaf_node: NodePtrRef,
+    left: NodePtrRef,
+    right: NodePtrRef,
+}
+
+impl Internal {
+    fn leaf_node(&self) -> Position {
+        Position::child(&self.path, self.bit_length, self.leaf_node.clone(), None)
+    }
+
+    fn child(&self, bit: bool) -> Position {
+        let ptr = if bit { &self.right } else { &self.left };
+        Position::child(&self.path, self.bit_length, ptr.clone(), Some(bit))
+    }
+}
+
+struct Differ<'a> {
+    ctx: Arc<Context>,
+    old: &'a Tree,
+    new: &'a Tree,
+    changes: BTreeMap<Key, Option<Value>>,
+}
+
+impl<'a> Differ<'a> {
+    fn deref(&self, tree: &Tree, pos: &Position) -> Result<Option<NodeRef>> {
+        // Smallest key in the subtree, used to fetch the node in case it is not available.
+        let key = pos.path[..pos.path_bits.to_bytes()].to_vec();
+        tree.cache.borrow_mut().deref_node_ptr(
+            &self.ctx,
+            pos.ptr.clone(),
+            Some(FetcherSyncIterate::new(&key, DIFF_PREFETCH, false)),
+        )
+    }
+
+    fn internal(node_ref: &NodeRef, pos: &Position) -> Option<Internal> {
+        match *node_ref.borrow() {
+            NodeBox::Internal(ref n) => Some(Internal {
+                bit_length: pos.bit_depth + n.label_bit_length,
+                path: pos.path.merge(pos.bit_depth, &n.label, n.label_bit_length),
+                leaf_node: n.leaf_node.clone(),
+                left: n.left.clone(),
+                right: n.right.clone(),
+            }),
+            NodeBox::Leaf(_) => None,
+        }
+    }
+
+    /// Collect all entries in the given subtree.
+    fn collect(
+        &self,
+        tree: &Tree,
+        pos: &Position,
+        entries: &mut BTreeMap<Key, Value>,
+    ) -> Result<()> {
+        let node_ref = match self.deref(tree, pos)? {
+            Some(node_ref) => node_ref,
+            None => return Ok(()),
+        };
+        match Self::internal(&node_ref, pos) {
+            Some(n) => {
+                self.collect(tree, &n.leaf_node(), entries)?;
+                self.collect(tree, &n.child(false), entries)?;
+                self.collect(tree, &n.child(true), entries)?;
+            }
+            None => {
+                entries.insert(
+                    noderef_as!(node_ref, Leaf).key.clone(),
+                    noderef_as!(node_ref, Leaf).value.clone(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Compare two subtrees by enumerating all of their entries.
+    fn diff_entries(&mut self, old: &Position, new: &Position) -> Result<()> {
+        let mut old_entries = BTreeMap::new();
+        let mut new_entries = BTreeMap::new();
+        self.collect(self.old, old, &mut old_entries)?;
+        self.collect(self.new, new, &mut new_entries)?;
+
+        for key in old_entries.keys() {
+            if !new_entries.contains_key(key) {
+                self.changes.insert(key.clone(), None);
+            }
+        }
+        for (key, value) in new_entries {
+            if old_entries.get(&key) != Some(&value) {
+                self.changes.insert(key, Some(value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Record all entries of a subtree as removed.
+    fn removed(&mut self, old: &Position) -> Result<()> {
+        self.diff_entries(old, &Position::root(NodePointer::null_ptr()))
+    }
+
+    /// Record all entries of a subtree as inserted.
+    fn inserted(&mut self, new: &Position) -> Result<()> {
+        self.diff_entries(&Position::root(NodePointer::null_ptr()), new)
+    }
+
+    fn diff(&mut self, old: Position, new: Position) -> Result<()> {
+        // Subtrees at the same depth with equal hashes are identical.
+        if old.bit_depth == new.bit_depth && old.ptr.borrow().hash == new.ptr.borrow().hash {
+            return Ok(());
+        }
+
+        let old_node = self.deref(self.old, &old)?;
+        let new_node = self.deref(self.new, &new)?;
+        let (a, b) = match (old_node, new_node) {
+            (Some(old_node), Some(new_node)) => match (
+                Self::internal(&old_node, &old),
+                Self::internal(&new_node, &new),
+            ) {
+                (Some(a), Some(b)) => (a, b),
+                // At least one of the subtrees is a single leaf.
+                _ => return self.diff_entries(&old, &new),
+            },
+            // At least one of the subtrees is empty.
+            _ => return self.diff_entries(&old, &new),
+        };
+
+        // If the paths diverge, the subtrees contain disjoint sets of keys.
+        let min_length = a.bit_length.min(b.bit_length);
+        if a.path
+            .common_prefix_len(a.bit_length, &b.path, b.bit_length)
+            < min_length
+        {
+            self.removed(&old)?;
+            return self.inserted(&new);
+        }
+
+        if a.bit_length == b.bit_length {
+            // Both nodes branch at the same depth, compare the children.
+            self.diff(a.leaf_node(), b.leaf_node())?;
+            self.diff(a.child(false), b.child(false))?;
+            self.diff(a.child(true), b.child(true))
+        } else if a.bit_length < b.bit_length {
+            // The old node branches before the new one, so all new keys are
+            // contained in one of its children.
+            let bit = b.path.get_bit(a.bit_length);
+            self.removed(&a.leaf_node())?;
+            self.removed(&a.child(!bit))?;
+            self.diff(a.child(bit), new)
+        } else {
+            // The new node branches before the old one, so all old keys are
+            // contained in one of its children.
+            let bit = a.path.get_bit(b.bit_length);
+            self.inserted(&b.leaf_node())?;
+            self.inserted(&b.child(!bit))?;
+            self.diff(old, b.child(bit))
+        }
+    }
+}
+
+/// Compute the write log which transforms the tree at `old_root` into the
+/// tree at `new_root`.
+///
+/// Both trees are walked in parallel and subtrees that are equal in both of
+/// them are skipped. Any nodes that are needed are fetched from the given
+/// read syncer. Entries in the resulting write log are sorted by key.
+pub fn diff(
+    ctx: Context,
+    read_syncer: Box<dyn ReadSync>,
+    old_root: Root,
+    new_root: Root,
+) -> Result<WriteLog> {
+    if old_root.root_type != new_root.root_type {
+        return Err(TreeError::RootTypeMismatch.into());
+    }
+
+    let read_syncer = Rc::new(RefCell::new(read_syncer));
+    let old = Tree::make()
+        .with_root(old_root)
+        .new(Box::new(SharedReadSyncer(read_syncer.clone())));
+    let new = Tree::make()
+        .with_root(new_root)
+        .new(Box::new(SharedReadSyncer(read_syncer)));
+
+    let old_root = Position::root(old.cache.borrow().get_pending_root());
+    let new_root = Position::root(new.cache.borrow().get_pending_root());
+    let mut differ = Differ {
+        ctx: ctx.freeze(),
+        old: &old,
+        new: &new,
+        changes: BTreeMap::new(),
+    };
+    differ.diff(old_root, new_root)?;
+
+    Ok(differ
+        .changes
+        .into_iter()
+        .map(|(key, value)| LogEntry { key, value })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use io_context::Context;
+
+    use super::*;
+    use crate::{
+        common::crypto::hash::Hash, storage::mkvs::tree::tree_test::generate_key_value_pairs_ex,
+    };
+
+    /// A read syncer dispatching requests to trees based on the requested root.
+    struct MultiRootSyncer {
+        trees: Vec<Tree>,
+    }
+
+    impl MultiRootSyncer {
+        fn tree(&mut self, root: &Root) -> Result<&mut Tree> {
+            self.trees
+                .iter_mut()
+                .find(|tree| tree.cache.borrow().get_sync_root() == *root)
+                .ok_or_else(|| SyncerError::InvalidRoot.into())
+        }
+    }
+
+    impl ReadSync for MultiRootSyncer {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+            self.tree(&request.tree.root)?.sync_get(ctx, request)
+        }
+
+        fn sync_get_many(
+            &mut self,
+            ctx: Context,
+            request: GetManyRequest,
+        ) -> Result<ProofResponse> {
+            self.tree(&request.tree.root)?.sync_get_many(ctx, request)
+        }
+
+        fn sync_get_prefixes(
+            &mut self,
+            ctx: Context,
+            request: GetPrefixesRequest,
+        ) -> Result<ProofResponse> {
+            self.tree(&request.tree.root)?
+                .sync_get_prefixes(ctx, request)
+        }
+
+        fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+            self.tree(&request.tree.root)?.sync_iterate(ctx, request)
+        }
+    }
+
+    fn build(entries: &[(Vec<u8>, Vec<u8>)], version: u64) -> (Tree, Root) {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for (key, value) in entries {
+            tree.insert(Context::background(), key, value)
+                .expect("insert");
+        }
+        let hash = tree
+            .commit(Context::background(), Default::default(), version)
+            .expect("commit");
+        let root = Root {
+            version,
+            root_type: RootType::State,
+            hash,
+            ..Default::default()
+        };
+        (tree, root)
+    }
+
+    #[test]
+    fn test_diff() {
+        let (keys, values) = generate_key_value_pairs_ex("diff".to_string(), 200);
+
+        // Build the new version by modifying, inserting and removing some keys.
+        let mut old_entries = Vec::new();
+        let mut expected = Vec::new();
+        for i in 0..keys.len() {
+            let old_value = if i % 2 == 0 {
+                Some(values[i].clone())
+            } else {
+                None
+            };
+            let new_value = match i % 7 {
+                0 | 1 => None,
+                2 => Some(b"modified".to_vec()),
+                _ => old_value.clone(),
+            };
+            if let Some(ref value) = old_value {
+                old_entries.push((keys[i].clone(), value.clone()));
+            }
+            if old_value != new_value {
+                expected.push(LogEntry {
+                    key: keys[i].clone(),
+                    value: new_value,
+                });
+            }
+        }
+        expected.sort_by(|a, b| a.key.cmp(&b.key));
+
+        // Derive the new version from the old one, so that unmodified
+        // subtrees are shared between both roots.
+        let (old_tree, old_root) = build(&old_entries, 0);
+        let (mut new_tree, _) = build(&old_entries, 0);
+        for entry in &expected {
+            match entry.value {
+                Some(ref value) => new_tree
+                    .insert(Context::background(), &entry.key, value)
+                    .map(|_| ()),
+                None => new_tree
+                    .remove(Context::background(), &entry.key)
+                    .map(|_| ()),
+            }
+            .expect("update");
+        }
+        let hash = new_tree
+            .commit(Context::background(), Default::default(), 1)
+            .expect("commit");
+        let new_root = Root {
+            version: 1,
+            hash,
+            ..old_root
+        };
+        let rs = MultiRootSyncer {
+            trees: vec![old_tree, new_tree],
+        };
+        let write_log =
+            diff(Context::background(), Box::new(rs), old_root, new_root).expect("diff");
+        assert_eq!(write_log, expected);
+
+        // Diffing a root against itself should not require any nodes.
+        let write_log = diff(
+            Context::background(),
+            Box::new(NoopReadSyncer),
+            new_root,
+            new_root,
+        )
+        .expect("diff");
+        assert!(write_log.is_empty());
+
+        // Diffing against an empty root should insert or remove everything.
+        let (old_tree, old_root) = build(&old_entries, 0);
+        let empty_root = Root {
+            root_type: RootType::State,
+            hash: Hash::empty_hash(),
+            ..Default::default()
+        };
+        let rs = MultiRootSyncer {
+            trees: vec![old_tree],
+        };
+        let write_log =
+            diff(Context::background(), Box::new(rs), old_root, empty_root).expect("diff");
+        assert_eq!(write_log.len(), old_entries.len());
+        assert!(write_log.iter().all(|entry| entry.value.is_none()));
+
+        // Diffing roots of different types should fail.
+        let io_root = Root {
+            root_type: RootType::IO,
+            ..Default::default()
+        };
+        assert!(diff(
+            Context::background(),
+            Box::new(NoopReadSyncer),
+            empty_root,
+            io_root
+        )
+        .is_err());
+    }
+}
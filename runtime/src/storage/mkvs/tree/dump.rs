@@ -0,0 +1,176 @@
+//! Exporting and importing trees to and from portable dumps.
+//!
+//! A dump is a stream of CBOR items. The first item is a header containing
+//! the dump format version and the root the dump was taken at, followed by
+//! one item for each key/value pair in key order.
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use io_context::Context;
+use serde::{Deserialize, Serialize};
+use serde_cbor;
+
+use crate::{
+    common::cbor,
+    storage::mkvs::{self, cache::Cache, sync::*, tree::*},
+};
+
+/// Version of the dump format.
+const DUMP_VERSION: u16 = 1;
+
+/// Dump header.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct DumpHeader {
+    version: u16,
+    root: Root,
+}
+
+/// A single key/value pair in a dump.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct DumpEntry {
+    #[serde(with = "serde_bytes")]
+    key: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    value: Vec<u8>,
+}
+
+/// Export all entries of the tree at its current root to the given writer.
+///
+/// The tree must be committed. Any nodes missing from the tree's cache are
+/// fetched from its read syncer.
+pub fn export<W: Write>(ctx: Context, tree: &Tree, mut w: W) -> Result<()> {
+    use mkvs::Iterator;
+
+    let root = tree.cache.borrow().get_sync_root();
+    tree.check_sync_root(&root)?;
+
+    // Use to_value first to force serialization into canonical format.
+    serde_cbor::to_writer(
+        &mut w,
+        &cbor::to_value(DumpHeader {
+            version: DUMP_VERSION,
+            root,
+        }),
+    )?;
+
+    let mut it = tree.iter(ctx);
+    it.rewind();
+    while it.is_valid() {
+        let entry = DumpEntry {
+            key: it.get_key().clone().expect("iterator is valid"),
+            value: it.get_value().clone().expect("iterator is valid"),
+        };
+        serde_cbor::to_writer(&mut w, &cbor::to_value(entry))?;
+        mkvs::Iterator::next(&mut it);
+    }
+    if let Some(error) = it.take_error() {
+        return Err(error);
+    }
+    w.flush()?;
+
+    Ok(())
+}
+
+/// Import a dump created by `export` from the given reader.
+///
+/// The entries are inserted into a new in-memory tree which is committed under
+/// the namespace and version of the recorded root. Returns an error if the
+/// resulting root hash differs from the recorded one.
+pub fn import<R: Read>(ctx: Context, r: R) -> Result<Tree> {
+    let ctx = ctx.freeze();
+    let mut de = serde_cbor::Deserializer::from_reader(r);
+    let header = DumpHeader::deserialize(&mut de)?;
+    if header.version != DUMP_VERSION {
+        return Err(TreeError::UnsupportedDumpVersion(header.version).into());
+    }
+
+    let mut tree = Tree::make()
+        .with_root_type(header.root.root_type)
+        .with_capacity(0, 0)
+        .new(Box::new(NoopReadSyncer));
+    for entry in de.into_iter::<DumpEntry>() {
+        let entry = entry?;
+        tree.insert(Context::create_child(&ctx), &entry.key, &entry.value)?;
+    }
+
+    let hash = tree.commit(
+        Context::create_child(&ctx),
+        header.root.namespace,
+        header.root.version,
+    )?;
+    if hash != header.root.hash {
+        return Err(TreeError::RootMismatch {
+            expected: header.root.hash,
+            got: hash,
+        }
+        .into());
+    }
+
+    Ok(tree)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::mkvs::tree::tree_test::generate_key_value_pairs_ex;
+
+    use super::*;
+
+    #[test]
+    fn test_export_import() {
+        let (keys, values) = generate_key_value_pairs_ex("dump".to_string(), 100);
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for (key, value) in keys.iter().zip(values.iter()) {
+            tree.insert(Context::background(), key, value)
+                .expect("insert");
+        }
+
+        // Uncommitted trees cannot be exported.
+        assert!(export(Context::background(), &tree, Vec::new()).is_err());
+
+        let hash = tree
+            .commit(Context::background(), Default::default(), 3)
+            .expect("commit");
+        let mut dump = Vec::new();
+        export(Context::background(), &tree, &mut dump).expect("export");
+
+        // Write errors should be returned instead of panicking.
+        let mut short = [0u8; 16];
+        assert!(export(Context::background(), &tree, &mut short[..]).is_err());
+
+        let imported = import(Context::background(), &dump[..]).expect("import");
+        let root = imported.cache.borrow().get_sync_root();
+        assert_eq!(root.hash, hash);
+        assert_eq!(root.version, 3);
+        assert_eq!(root.root_type, RootType::State);
+        for (key, value) in keys.iter().zip(values.iter()) {
+            assert_eq!(
+                imported.get(Context::background(), key).expect("get"),
+                Some(value.clone())
+            );
+        }
+
+        // Tampering with the dump should be detected.
+        let mut tampered = Vec::new();
+        let mut de = serde_cbor::Deserializer::from_slice(&dump);
+        let header = DumpHeader::deserialize(&mut de).expect("header");
+        cbor::to_writer(&mut tampered, &header);
+        for (idx, entry) in de.into_iter::<DumpEntry>().enumerate() {
+            let mut entry = entry.expect("entry");
+            if idx == 10 {
+                entry.value = b"tampered".to_vec();
+            }
+            cbor::to_writer(&mut tampered, &entry);
+        }
+        let err = import(Context::background(), &tampered[..])
+            .expect_err("import of a tampered dump should fail");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(TreeError::RootMismatch { .. })
+        ));
+
+        // Truncated dumps should fail to import.
+        assert!(import(Context::background(), &dump[..dump.len() - 1]).is_err());
+    }
+}
@@ -1,9 +1,23 @@
 use thiserror::Error;
 
+use crate::common::crypto::hash::Hash;
+
 #[derive(Error, Debug)]
 pub enum TreeError {
     #[error("mkvs: malformed node")]
     MalformedNode,
     #[error("mkvs: malformed key")]
     MalformedKey,
+    #[error("mkvs: root type mismatch")]
+    RootTypeMismatch,
+    #[error("mkvs: key exists")]
+    KeyExists,
+    #[error("mkvs: root mismatch (expected: {expected:?} got: {got:?})")]
+    RootMismatch { expected: Hash, got: Hash },
+    #[error("mkvs: unsupported dump version {0}")]
+    UnsupportedDumpVersion(u16),
+    #[error("mkvs: key too large ({size} > {max} bytes)")]
+    KeyTooLarge { size: usize, max: usize },
+    #[error("mkvs: value too large ({size} > {max} bytes)")]
+    ValueTooLarge { size: usize, max: usize },
 }
@@ -9,7 +9,12 @@ use super::lookup::FetcherSyncGet;
 
 impl Tree {
     /// Insert a key/value pair into the tree.
+    ///
+    /// Returns an error if the key or value exceed the configured size limits.
     pub fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.check_limits(key, value)?;
+        self.cache.borrow_mut().forget_absent(key);
+
         let ctx = ctx.freeze();
         let pending_root = self.cache.borrow().get_pending_root();
         let boxed_key = key.to_vec();
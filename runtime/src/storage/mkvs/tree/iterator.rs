@@ -4,16 +4,24 @@ use std::{collections::VecDeque, fmt, iter::Iterator, mem::replace, sync::Arc};
 use anyhow::{Error, Result};
 use io_context::Context;
 
-use crate::storage::mkvs::{self, cache::*, sync::*, tree::*};
+use crate::{
+    common::crypto::hash::Hash,
+    storage::mkvs::{self, cache::*, sync::*, tree::*},
+};
 
 pub(super) struct FetcherSyncIterate<'a> {
     key: &'a Key,
     prefetch: usize,
+    reverse: bool,
 }
 
 impl<'a> FetcherSyncIterate<'a> {
-    pub(super) fn new(key: &'a Key, prefetch: usize) -> Self {
-        Self { key, prefetch }
+    pub(super) fn new(key: &'a Key, prefetch: usize, reverse: bool) -> Self {
+        Self {
+            key,
+            prefetch,
+            reverse,
+        }
     }
 }
 
@@ -25,27 +33,56 @@ impl<'a> ReadSyncFetcher for FetcherSyncIterate<'a> {
         ptr: NodePtrRef,
         rs: &mut Box<dyn ReadSync>,
     ) -> Result<Proof> {
-        let rsp = rs.sync_iterate(
-            ctx,
+        let ctx = ctx.freeze();
+        let tree = TreeID {
+            root,
+            position: ptr.borrow().hash,
+        };
+        let rsp = match rs.sync_iterate(
+            Context::create_child(&ctx),
             IterateRequest {
-                tree: TreeID {
-                    root,
-                    position: ptr.borrow().hash,
-                },
+                tree: tree.clone(),
                 key: self.key.clone(),
                 prefetch: self.prefetch as u16,
+                reverse: self.reverse,
             },
-        )?;
+        ) {
+            Ok(rsp) => rsp,
+            Err(err)
+                if self.reverse
+                    && matches!(
+                        err.downcast_ref(),
+                        Some(SyncerError::ReverseIterationUnsupported)
+                    ) =>
+            {
+                // The remote syncer cannot prefetch in reverse order, so only fetch the path
+                // towards the key (together with siblings) and let the iterator resolve any
+                // remaining nodes on demand.
+                rs.sync_get(
+                    Context::create_child(&ctx),
+                    GetRequest {
+                        tree,
+                        key: self.key.clone(),
+                        include_siblings: true,
+                    },
+                )?
+            }
+            Err(err) => return Err(err),
+        };
         Ok(rsp.proof)
     }
 }
 
+/// Number of items to prefetch when iterating over a prefix.
+const PREFIX_PREFETCH: usize = 1000;
+
 /// Visit state of a node.
 #[derive(Debug, PartialEq)]
 enum VisitState {
     Before,
     At,
     AtLeft,
+    AtRight,
     After,
 }
 
@@ -77,6 +114,11 @@ pub struct TreeIterator<'tree> {
     key: Option<Key>,
     value: Option<Vec<u8>>,
     error: Option<Error>,
+    reverse: bool,
+    /// Prefix to prefetch on the first remote sync, if any.
+    prefetch_prefix: Option<Vec<u8>>,
+
+    proof_builder: Option<ProofBuilder>,
 }
 
 impl<'tree> TreeIterator<'tree> {
@@ -90,9 +132,66 @@ impl<'tree> TreeIterator<'tree> {
             key: None,
             value: None,
             error: None,
+            reverse: false,
+            prefetch_prefix: None,
+            proof_builder: None,
         }
     }
 
+    /// Configure the iterator to build a proof for all items iterated over,
+    /// anchored at the given root.
+    pub fn with_proof(mut self, root: Hash) -> Self {
+        self.proof_builder = Some(ProofBuilder::new(root, root));
+        self
+    }
+
+    /// Return the proof builder used by the iterator, if it has been configured
+    /// using `with_proof`.
+    pub fn get_proof_builder(&self) -> Option<&ProofBuilder> {
+        self.proof_builder.as_ref()
+    }
+
+    /// Build a proof for all items iterated over by the iterator.
+    ///
+    /// The iterator must have been configured using `with_proof`, otherwise
+    /// calling this method will panic.
+    pub fn get_proof(&self) -> Result<Proof> {
+        match self.proof_builder {
+            Some(ref pb) => pb.build(Context::create_child(&self.ctx)),
+            None => panic!("iterator: called get_proof on an iterator without proof builder"),
+        }
+    }
+
+    /// Take the error that occurred during iteration if any.
+    pub(super) fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    /// Prefetch all entries under the configured prefix in case the given node
+    /// must be fetched from the remote read syncer. This is only done once.
+    fn maybe_prefetch_prefix(&mut self, ptr: &NodePtrRef) -> Result<()> {
+        {
+            let ptr = ptr.borrow();
+            if self.prefetch_prefix.is_none() || ptr.is_null() || ptr.node.is_some() {
+                return Ok(());
+            }
+        }
+        let prefix = self.prefetch_prefix.take().expect("prefetch prefix is set");
+
+        // Prefetching is anchored at the root so it is only possible when the root
+        // has not been modified locally.
+        let pending_root = self.tree.cache.borrow().get_pending_root();
+        if !pending_root.borrow().clean || !self.tree.has_remote_read_syncer() {
+            return Ok(());
+        }
+        let limit = self.prefetch.min(u16::max_value() as usize) as u16;
+        self.tree.prefetch_prefixes(
+            Context::create_child(&self.ctx),
+            &vec![prefix.into()],
+            limit,
+        )
+    }
+
     fn reset(&mut self) {
         self.pos.clear();
         self.key = None;
@@ -104,6 +203,15 @@ impl<'tree> TreeIterator<'tree> {
             return;
         }
 
+        if self.reverse {
+            // The current path was built while iterating in reverse, reposition the
+            // iterator at the current key before moving forward.
+            match self.key.clone() {
+                Some(key) => mkvs::Iterator::seek(self, &key),
+                None => return,
+            }
+        }
+
         while !self.pos.is_empty() {
             // Start where we left off.
             let atom = self.pos.pop_front().expect("not empty");
@@ -151,12 +259,18 @@ impl<'tree> TreeIterator<'tree> {
         mut key: Key,
         mut state: VisitState,
     ) -> Result<()> {
+        self.maybe_prefetch_prefix(&ptr)?;
         let node_ref = self.tree.cache.borrow_mut().deref_node_ptr(
             &self.ctx,
             ptr.clone(),
-            Some(FetcherSyncIterate::new(&key, self.prefetch)),
+            Some(FetcherSyncIterate::new(&key, self.prefetch, false)),
         )?;
 
+        // Include nodes in proof if we have a proof builder.
+        if let (Some(pb), Some(node_ref)) = (self.proof_builder.as_mut(), node_ref.as_ref()) {
+            pb.include(&*node_ref.borrow());
+        }
+
         match classify_noderef!(?node_ref) {
             NodeKind::None => {
                 // Reached a nil node, there is nothing here.
@@ -282,6 +396,211 @@ impl<'tree> TreeIterator<'tree> {
             }
         }
     }
+
+    fn prev(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if !self.reverse {
+            // The current path was built while iterating forward, reposition the
+            // iterator at the current key before moving backward.
+            match self.key.clone() {
+                Some(key) => mkvs::Iterator::seek_for_prev(self, &key),
+                None => return,
+            }
+        }
+
+        while !self.pos.is_empty() {
+            // Start where we left off.
+            let atom = self.pos.pop_front().expect("not empty");
+            let mut remainder = replace(&mut self.pos, VecDeque::new());
+
+            // Remember where the path from root to target node ends (will end).
+            let mut cache = self.tree.cache.borrow_mut();
+            cache.mark_position();
+            for atom in &remainder {
+                cache.use_node(atom.ptr.clone());
+            }
+            drop(cache);
+
+            // Try to proceed with the current node. Everything that remains to be
+            // visited is smaller than the current key so no bound is needed.
+            let key = self.key.take().expect("iterator is valid");
+            self.reset();
+            if let Err(error) = self._prev(atom.ptr, atom.bit_depth, atom.path, None, atom.state) {
+                self.error = Some(error);
+                self.reset();
+                return;
+            }
+            if self.key.is_some() {
+                // Key has been found.
+                self.pos.append(&mut remainder);
+                return;
+            }
+
+            self.key = Some(key);
+            self.pos = remainder;
+        }
+
+        // We have reached the start of the tree, make sure everything is reset.
+        self.key = None;
+        self.value = None;
+    }
+
+    /// Find the largest key not greater than `bound` (or the largest key if there
+    /// is no bound) in the given subtree.
+    ///
+    /// Within a subtree, the key stored in the internal node's leaf is the smallest,
+    /// followed by keys in the left subtree and then keys in the right subtree.
+    fn _prev(
+        &mut self,
+        ptr: NodePtrRef,
+        bit_depth: Depth,
+        path: Key,
+        bound: Option<&Key>,
+        state: VisitState,
+    ) -> Result<()> {
+        // In case the node needs to be fetched, the remote iterator must be positioned
+        // so that it traverses this node. An empty key seeks to the last key in the
+        // tree, which is what we need for the root.
+        let fetch_key = match bound {
+            Some(bound) => bound.clone(),
+            None if path.is_empty() => Key::new(),
+            None => {
+                // Use the largest key of the same length that has the node's path as
+                // its prefix.
+                let bit_length = bit_depth + 1;
+                let mut key = path[..bit_length.to_bytes()].to_vec();
+                if bit_length % 8 != 0 {
+                    *key.last_mut().unwrap() |= 0xff >> (bit_length % 8);
+                }
+                key
+            }
+        };
+        self.maybe_prefetch_prefix(&ptr)?;
+        let node_ref = self.tree.cache.borrow_mut().deref_node_ptr(
+            &self.ctx,
+            ptr.clone(),
+            Some(FetcherSyncIterate::new(&fetch_key, self.prefetch, true)),
+        )?;
+
+        // Include nodes in proof if we have a proof builder.
+        if let (Some(pb), Some(node_ref)) = (self.proof_builder.as_mut(), node_ref.as_ref()) {
+            pb.include(&*node_ref.borrow());
+        }
+
+        match classify_noderef!(?node_ref) {
+            NodeKind::None => {
+                // Reached a nil node, there is nothing here.
+                Ok(())
+            }
+            NodeKind::Internal => {
+                let node_ref = node_ref.unwrap();
+                if let NodeBox::Internal(ref n) = *node_ref.borrow() {
+                    // Internal node.
+                    let bit_length = bit_depth + n.label_bit_length;
+                    let new_path = path.merge(bit_depth, &n.label, n.label_bit_length);
+
+                    // Determine which children may contain keys not larger than the bound.
+                    let mut visit_right = true;
+                    let mut visit_left = true;
+                    let mut right_bound = None;
+                    let mut left_bound = None;
+                    if let Some(bound) = bound {
+                        let prefix_len =
+                            bound.common_prefix_len(bound.bit_length(), &new_path, bit_length);
+                        if prefix_len < bit_length {
+                            // The bound diverges from the path to this node. If the bound is
+                            // smaller, so is everything in this subtree. Otherwise all keys in
+                            // this subtree are smaller than the bound.
+                            if prefix_len == bound.bit_length() || !bound.get_bit(prefix_len) {
+                                return Ok(());
+                            }
+                        } else if bound.bit_length() == bit_length {
+                            // The bound ends here, only the leaf can be smaller or equal.
+                            visit_right = false;
+                            visit_left = false;
+                        } else if bound.get_bit(bit_length) {
+                            // Bound is in the right subtree, everything on the left is smaller.
+                            right_bound = Some(bound);
+                        } else {
+                            // Bound is in the left subtree, everything on the right is larger.
+                            visit_right = false;
+                            left_bound = Some(bound);
+                        }
+                    }
+
+                    if state == VisitState::Before && visit_right {
+                        self._prev(
+                            n.right.clone(),
+                            bit_length,
+                            new_path.append_bit(bit_length, true),
+                            right_bound,
+                            VisitState::Before,
+                        )?;
+                        if self.key.is_some() {
+                            // Key has been found.
+                            self.pos.push_back(PathAtom {
+                                ptr,
+                                bit_depth,
+                                path,
+                                state: VisitState::AtRight,
+                            });
+                            return Ok(());
+                        }
+                    }
+
+                    if (state == VisitState::Before || state == VisitState::AtRight) && visit_left {
+                        self._prev(
+                            n.left.clone(),
+                            bit_length,
+                            new_path.append_bit(bit_length, false),
+                            left_bound,
+                            VisitState::Before,
+                        )?;
+                        if self.key.is_some() {
+                            // Key has been found.
+                            self.pos.push_back(PathAtom {
+                                ptr,
+                                bit_depth,
+                                path,
+                                state: VisitState::AtLeft,
+                            });
+                            return Ok(());
+                        }
+                    }
+
+                    // The leaf is a prefix of all other keys in the subtree so it is never
+                    // larger than the bound at this point. Once it has been visited there
+                    // is nothing left to visit in this subtree.
+                    return self._prev(
+                        n.leaf_node.clone(),
+                        bit_length,
+                        path,
+                        Some(&new_path),
+                        VisitState::Before,
+                    );
+                }
+
+                unreachable!("node kind is internal node");
+            }
+            NodeKind::Leaf => {
+                // Reached a leaf node.
+                let node_ref = node_ref.unwrap();
+                if let NodeBox::Leaf(ref n) = *node_ref.borrow() {
+                    if bound.map_or(true, |bound| n.key <= *bound) {
+                        self.key = Some(n.key.clone());
+                        self.value = Some(n.value.clone());
+                    }
+                } else {
+                    unreachable!("node kind is leaf node");
+                }
+
+                Ok(())
+            }
+        }
+    }
 }
 
 impl<'tree> Iterator for TreeIterator<'tree> {
@@ -325,6 +644,7 @@ impl<'tree> mkvs::Iterator for TreeIterator<'tree> {
         }
 
         self.reset();
+        self.reverse = false;
         let pending_root = self.tree.cache.borrow().get_pending_root();
         if let Err(error) = self._next(
             pending_root,
@@ -338,6 +658,50 @@ impl<'tree> mkvs::Iterator for TreeIterator<'tree> {
         }
     }
 
+    fn seek_last(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+
+        self.reset();
+        self.reverse = true;
+        let pending_root = self.tree.cache.borrow().get_pending_root();
+        if let Err(error) = self._prev(pending_root, 0, Key::new(), None, VisitState::Before) {
+            self.error = Some(error);
+            self.reset();
+        }
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if key.is_empty() {
+            // The empty key is the smallest key so it can only match exactly. This case
+            // is handled separately as an empty key denotes the last key in reverse
+            // remote iteration requests.
+            self.seek(key);
+            if self.key.as_ref().map_or(false, |k| !k.is_empty()) {
+                self.key = None;
+                self.value = None;
+            }
+            self.pos.clear();
+            self.reverse = true;
+            return;
+        }
+
+        self.reset();
+        self.reverse = true;
+        let key = key.to_vec();
+        let pending_root = self.tree.cache.borrow().get_pending_root();
+        if let Err(error) = self._prev(pending_root, 0, Key::new(), Some(&key), VisitState::Before)
+        {
+            self.error = Some(error);
+            self.reset();
+        }
+    }
+
     fn get_key(&self) -> &Option<Key> {
         &self.key
     }
@@ -349,6 +713,139 @@ impl<'tree> mkvs::Iterator for TreeIterator<'tree> {
     fn next(&mut self) {
         TreeIterator::next(self)
     }
+
+    fn prev(&mut self) {
+        TreeIterator::prev(self)
+    }
+}
+
+/// An iterator over entries with keys starting with a given prefix.
+///
+/// The iterator becomes invalid once it moves past the prefix boundary in
+/// either direction.
+pub struct PrefixIterator<I: mkvs::Iterator> {
+    inner: I,
+    prefix: Vec<u8>,
+    /// Smallest key larger than all keys with the prefix, if any.
+    end: Option<Vec<u8>>,
+}
+
+impl<I: mkvs::Iterator> PrefixIterator<I> {
+    /// Create a new prefix iterator wrapping the given iterator and position it
+    /// at the first key with the prefix.
+    pub fn new(inner: I, prefix: &[u8]) -> Self {
+        // Compute the end of the prefix range by incrementing the last byte that
+        // is not 0xff. Prefixes containing only 0xff bytes have no end.
+        let mut end = prefix.to_vec();
+        while end.last() == Some(&0xff) {
+            end.pop();
+        }
+        let end = end.last_mut().map(|b| *b += 1).map(|_| end);
+
+        let mut it = Self {
+            inner,
+            prefix: prefix.to_vec(),
+            end,
+        };
+        mkvs::Iterator::rewind(&mut it);
+        it
+    }
+
+    fn in_bounds(&self) -> bool {
+        self.inner
+            .get_key()
+            .as_ref()
+            .map_or(false, |key| key.starts_with(&self.prefix))
+    }
+}
+
+impl<I: mkvs::Iterator> Iterator for PrefixIterator<I> {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        use mkvs::Iterator;
+
+        if !self.is_valid() {
+            return None;
+        }
+
+        let key = self.get_key().clone().expect("iterator is valid");
+        let value = self.get_value().clone().expect("iterator is valid");
+        mkvs::Iterator::next(self);
+
+        Some((key, value))
+    }
+}
+
+impl<I: mkvs::Iterator> mkvs::Iterator for PrefixIterator<I> {
+    fn set_prefetch(&mut self, prefetch: usize) {
+        self.inner.set_prefetch(prefetch)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid() && self.in_bounds()
+    }
+
+    fn error(&self) -> &Option<Error> {
+        self.inner.error()
+    }
+
+    fn rewind(&mut self) {
+        self.inner.seek(&self.prefix)
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        if key < &self.prefix[..] {
+            self.inner.seek(&self.prefix)
+        } else {
+            self.inner.seek(key)
+        }
+    }
+
+    fn seek_last(&mut self) {
+        match self.end.clone() {
+            Some(end) => {
+                self.inner.seek_for_prev(&end);
+                if self.inner.get_key().as_ref() == Some(&end) {
+                    self.inner.prev();
+                }
+            }
+            None => self.inner.seek_last(),
+        }
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        match self.end {
+            Some(ref end) if key >= &end[..] => self.seek_last(),
+            _ => self.inner.seek_for_prev(key),
+        }
+    }
+
+    fn get_key(&self) -> &Option<Key> {
+        if !self.in_bounds() {
+            return &None;
+        }
+        self.inner.get_key()
+    }
+
+    fn get_value(&self) -> &Option<Vec<u8>> {
+        if !self.in_bounds() {
+            return &None;
+        }
+        self.inner.get_value()
+    }
+
+    fn next(&mut self) {
+        if self.is_valid() {
+            mkvs::Iterator::next(&mut self.inner)
+        }
+    }
+
+    fn prev(&mut self) {
+        if self.is_valid() {
+            self.inner.prev()
+        }
+    }
 }
 
 impl Tree {
@@ -356,6 +853,62 @@ impl Tree {
     pub fn iter(&self, ctx: Context) -> TreeIterator {
         TreeIterator::new(ctx, self)
     }
+
+    /// Return an iterator over all entries with keys starting with the given prefix.
+    ///
+    /// When nodes first need to be fetched from the remote read syncer, all entries
+    /// under the prefix are prefetched using a single request.
+    pub fn iter_prefix(&self, ctx: Context, prefix: &[u8]) -> PrefixIterator<TreeIterator> {
+        let mut it = TreeIterator::new(ctx, self);
+        it.prefetch = PREFIX_PREFETCH;
+        it.prefetch_prefix = Some(prefix.to_vec());
+        PrefixIterator::new(it, prefix)
+    }
+
+    /// Seek to a given key and then fetch the specified number of following (or
+    /// preceding in case of reverse iteration) items based on key iteration order,
+    /// returning the corresponding proof.
+    pub fn sync_iterate(&self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        use mkvs::Iterator;
+
+        let ctx = ctx.freeze();
+        self.check_sync_root(&request.tree.root)?;
+
+        // Create an iterator which generates proofs. Always anchor the proof at the
+        // root as an iterator may encompass many subtrees. Make sure to propagate
+        // prefetching to any upstream remote syncers.
+        let mut it = self
+            .iter(Context::create_child(&ctx))
+            .with_proof(request.tree.root.hash);
+        it.set_prefetch(request.prefetch as usize);
+
+        let mut i = 0;
+        if request.reverse {
+            if request.key.is_empty() {
+                it.seek_last();
+            } else {
+                it.seek_for_prev(&request.key);
+            }
+            while it.is_valid() && i < request.prefetch {
+                TreeIterator::prev(&mut it);
+                i += 1;
+            }
+        } else {
+            it.seek(&request.key);
+            while it.is_valid() && i < request.prefetch {
+                TreeIterator::next(&mut it);
+                i += 1;
+            }
+        }
+        if let Some(error) = it.take_error() {
+            return Err(error);
+        }
+
+        // Retrieve the proof for the items iterated over.
+        let proof = it.get_proof()?;
+
+        Ok(ProofResponse { proof })
+    }
 }
 
 #[cfg(test)]
@@ -369,6 +922,7 @@ pub(super) mod test {
     use crate::storage::mkvs::{
         self,
         interop::{Driver, ProtocolServer},
+        sync::StatsCollector,
         Iterator,
     };
 
@@ -505,6 +1059,180 @@ pub(super) mod test {
         assert_eq!(2, stats.sync_iterate_count, "sync_iterate_count");
     }
 
+    #[test]
+    fn test_iterator_reverse() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+
+        // Test with an empty tree.
+        let mut it = tree.iter(Context::background());
+        it.seek_last();
+        assert!(
+            !it.is_valid(),
+            "iterator should be invalid on an empty tree"
+        );
+
+        let items = vec![
+            (b"key".to_vec(), b"first".to_vec()),
+            (b"key 1".to_vec(), b"one".to_vec()),
+            (b"key 2".to_vec(), b"two".to_vec()),
+            (b"key 5".to_vec(), b"five".to_vec()),
+            (b"key 8".to_vec(), b"eight".to_vec()),
+            (b"key 9".to_vec(), b"nine".to_vec()),
+        ];
+        for (key, value) in items.iter() {
+            tree.insert(Context::background(), key, value).unwrap();
+        }
+
+        let tests = vec![
+            (b"".to_vec(), -1),
+            (b"k".to_vec(), -1),
+            (b"key".to_vec(), 0),
+            (b"key 0".to_vec(), 0),
+            (b"key 1".to_vec(), 1),
+            (b"key 3".to_vec(), 2),
+            (b"key 5".to_vec(), 3),
+            (b"key 7".to_vec(), 3),
+            (b"key 9".to_vec(), 5),
+            (b"key A".to_vec(), 5),
+        ];
+
+        // Direct.
+        let it = tree.iter(Context::background());
+        test_reverse_iterator_with(&items, it, &tests);
+
+        // Changing direction.
+        let mut it = tree.iter(Context::background());
+        it.seek(b"key 5");
+        it.next();
+        assert_eq!(it.get_key(), &Some(b"key 8".to_vec()));
+        it.prev();
+        assert_eq!(it.get_key(), &Some(b"key 5".to_vec()));
+        it.prev();
+        assert_eq!(it.get_key(), &Some(b"key 2".to_vec()));
+        it.next();
+        assert_eq!(it.get_key(), &Some(b"key 5".to_vec()));
+
+        // Remote with prefetch (10).
+        let hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+        let stats = StatsCollector::new(Box::new(tree));
+        let remote_tree = Tree::make()
+            .with_capacity(0, 0)
+            .with_root(Root {
+                root_type: RootType::State,
+                hash,
+                ..Default::default()
+            })
+            .new(Box::new(stats));
+
+        let mut it = remote_tree.iter(Context::background());
+        it.set_prefetch(10);
+        test_reverse_iterator_with(&items, it, &tests);
+
+        let cache = remote_tree.cache.borrow();
+        let stats = cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<StatsCollector>()
+            .expect("stats");
+        assert_eq!(0, stats.sync_get_count, "sync_get_count");
+        assert_eq!(0, stats.sync_get_prefixes_count, "sync_get_prefixes_count");
+        assert_eq!(1, stats.sync_iterate_count, "sync_iterate_count");
+    }
+
+    /// Read syncer which rejects reverse iteration requests like syncers backed by the Go
+    /// storage backends do.
+    struct ForwardOnlyReadSyncer(Box<dyn ReadSync>);
+
+    impl ReadSync for ForwardOnlyReadSyncer {
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+
+        fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+            self.0.sync_get(ctx, request)
+        }
+
+        fn sync_get_many(
+            &mut self,
+            ctx: Context,
+            request: GetManyRequest,
+        ) -> Result<ProofResponse> {
+            self.0.sync_get_many(ctx, request)
+        }
+
+        fn sync_get_prefixes(
+            &mut self,
+            ctx: Context,
+            request: GetPrefixesRequest,
+        ) -> Result<ProofResponse> {
+            self.0.sync_get_prefixes(ctx, request)
+        }
+
+        fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+            if request.reverse {
+                return Err(SyncerError::ReverseIterationUnsupported.into());
+            }
+            self.0.sync_iterate(ctx, request)
+        }
+    }
+
+    #[test]
+    fn test_iterator_reverse_unsupported() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+
+        let items = vec![
+            (b"key".to_vec(), b"first".to_vec()),
+            (b"key 1".to_vec(), b"one".to_vec()),
+            (b"key 2".to_vec(), b"two".to_vec()),
+            (b"key 5".to_vec(), b"five".to_vec()),
+            (b"key 8".to_vec(), b"eight".to_vec()),
+            (b"key 9".to_vec(), b"nine".to_vec()),
+        ];
+        for (key, value) in items.iter() {
+            tree.insert(Context::background(), key, value).unwrap();
+        }
+        let hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+
+        let tests = vec![
+            (b"".to_vec(), -1),
+            (b"key".to_vec(), 0),
+            (b"key 3".to_vec(), 2),
+            (b"key 9".to_vec(), 5),
+            (b"key A".to_vec(), 5),
+        ];
+
+        // Reverse iteration should still work when the remote only supports forward iteration.
+        let stats = StatsCollector::new(Box::new(ForwardOnlyReadSyncer(Box::new(tree))));
+        let remote_tree = Tree::make()
+            .with_capacity(0, 0)
+            .with_root(Root {
+                root_type: RootType::State,
+                hash,
+                ..Default::default()
+            })
+            .new(Box::new(stats));
+
+        let mut it = remote_tree.iter(Context::background());
+        it.set_prefetch(10);
+        test_reverse_iterator_with(&items, it, &tests);
+
+        let cache = remote_tree.cache.borrow();
+        let stats = cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<StatsCollector>()
+            .expect("stats");
+        assert!(stats.sync_get_count > 0, "sync_get_count");
+    }
+
     #[test]
     fn test_iterator_case1() {
         let mut tree = Tree::make()
@@ -615,6 +1343,151 @@ pub(super) mod test {
         assert_eq!(2, stats.sync_iterate_count, "sync_iterate_count");
     }
 
+    #[test]
+    fn test_iterator_prefix() {
+        let server = ProtocolServer::new();
+
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        let mut write_log = Vec::new();
+        for prefix in &["a", "b", "c"] {
+            for i in 0..10 {
+                let key = format!("{}{:02}", prefix, i).into_bytes();
+                let value = format!("value {}", i).into_bytes();
+                tree.insert(Context::background(), &key, &value)
+                    .expect("insert");
+                write_log.push(mkvs::LogEntry::new(&key, &value));
+            }
+        }
+        let hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+        server.apply(&write_log, hash, Default::default(), 0);
+
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = write_log[10..20]
+            .iter()
+            .map(|entry| (entry.key.clone(), entry.value.clone().unwrap()))
+            .collect();
+
+        // Local iteration.
+        let items: Vec<_> = tree.iter_prefix(Context::background(), b"b").collect();
+        assert_eq!(items, expected);
+        let mut it = tree.iter_prefix(Context::background(), b"b");
+        it.seek_last();
+        assert_eq!(it.get_key().as_ref(), Some(&b"b09".to_vec()));
+        it.seek(b"a");
+        assert_eq!(it.get_key().as_ref(), Some(&b"b00".to_vec()));
+        it.prev();
+        assert!(!it.is_valid());
+        it.seek_for_prev(b"d");
+        assert_eq!(it.get_key().as_ref(), Some(&b"b09".to_vec()));
+        it.seek(b"c");
+        assert!(!it.is_valid());
+        assert_eq!(
+            tree.iter_prefix(Context::background(), b"d").count(),
+            0,
+            "iterator over a missing prefix should be empty"
+        );
+
+        // Remote iteration should prefetch the prefix using a single request.
+        let root = Root {
+            root_type: RootType::State,
+            hash,
+            ..Default::default()
+        };
+        let stats = StatsCollector::new(server.read_sync());
+        let remote_tree = Tree::make().with_root(root).new(Box::new(stats));
+        let items: Vec<_> = remote_tree
+            .iter_prefix(Context::background(), b"b")
+            .collect();
+        assert_eq!(items, expected);
+        {
+            let cache = remote_tree.cache.borrow();
+            let stats = cache
+                .get_read_syncer()
+                .as_any()
+                .downcast_ref::<StatsCollector>()
+                .expect("stats");
+            assert_eq!(0, stats.sync_get_count, "sync_get_count");
+            assert_eq!(1, stats.sync_get_prefixes_count, "sync_get_prefixes_count");
+            assert_eq!(0, stats.sync_iterate_count, "sync_iterate_count");
+        }
+
+        // Overlay entries should be merged.
+        let mut overlay = OverlayTree::new(Tree::make().with_root(root).new(server.read_sync()));
+        overlay
+            .insert(Context::background(), b"b05", b"updated")
+            .expect("insert");
+        overlay
+            .insert(Context::background(), b"b10", b"new")
+            .expect("insert");
+        overlay
+            .insert(Context::background(), b"c10", b"new")
+            .expect("insert");
+        overlay
+            .remove(Context::background(), b"b00")
+            .expect("remove");
+        let mut expected: Vec<(Vec<u8>, Vec<u8>)> = expected[1..].to_vec();
+        expected[4].1 = b"updated".to_vec();
+        expected.push((b"b10".to_vec(), b"new".to_vec()));
+        let items: Vec<_> = overlay.iter_prefix(Context::background(), b"b").collect();
+        assert_eq!(items, expected);
+        let items: Vec<_> =
+            mkvs::MKVS::iter_prefix(&overlay, Context::background(), b"c").collect();
+        assert_eq!(items.len(), 11);
+    }
+
+    pub(in super::super) fn test_reverse_iterator_with<I: mkvs::Iterator>(
+        items: &Vec<(Vec<u8>, Vec<u8>)>,
+        mut it: I,
+        tests: &Vec<(Vec<u8>, isize)>,
+    ) {
+        // Iterate through the whole tree in reverse.
+        let mut iterations = 0;
+        it.seek_last();
+        for (key, value) in items.iter().rev() {
+            assert_eq!(
+                &Some(key.clone()),
+                it.get_key(),
+                "iterator should have the correct key"
+            );
+            assert_eq!(
+                &Some(value.clone()),
+                it.get_value(),
+                "iterator should have the correct value"
+            );
+            it.prev();
+            iterations += 1;
+        }
+        assert!(!it.is_valid(), "iterator should be invalid at the start");
+        assert!(it.error().is_none(), "iterator should not error");
+        assert_eq!(iterations, items.len(), "iterator should go over all items");
+
+        for (seek, pos) in tests {
+            it.seek_for_prev(&seek);
+            if *pos == -1 {
+                assert!(!it.is_valid(), "iterator should not be valid after seek");
+                continue;
+            }
+
+            for (key, value) in items[..=*pos as usize].iter().rev() {
+                assert_eq!(
+                    &Some(key.clone()),
+                    it.get_key(),
+                    "iterator should have the correct key"
+                );
+                assert_eq!(
+                    &Some(value.clone()),
+                    it.get_value(),
+                    "iterator should have the correct value"
+                );
+                it.prev();
+            }
+            assert!(!it.is_valid(), "iterator should be invalid at the start");
+        }
+    }
+
     pub(in super::super) fn test_iterator_with<I: mkvs::Iterator>(
         items: &Vec<(Vec<u8>, Vec<u8>)>,
         mut it: I,
@@ -2,6 +2,7 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use io_context::Context;
+use serde_bytes::ByteBuf;
 
 use crate::storage::mkvs::{cache::*, sync::*, tree::*};
 
@@ -42,6 +43,48 @@ impl<'a> ReadSyncFetcher for FetcherSyncGet<'a> {
     }
 }
 
+pub(super) struct FetcherSyncGetMany<'a> {
+    keys: &'a Vec<ByteBuf>,
+}
+
+impl<'a> FetcherSyncGetMany<'a> {
+    pub(super) fn new(keys: &'a Vec<ByteBuf>) -> Self {
+        Self { keys }
+    }
+}
+
+impl<'a> ReadSyncFetcher for FetcherSyncGetMany<'a> {
+    fn fetch(
+        &self,
+        ctx: Context,
+        root: Root,
+        ptr: NodePtrRef,
+        rs: &mut Box<dyn ReadSync>,
+    ) -> Result<Proof> {
+        let rsp = rs.sync_get_many(
+            ctx,
+            GetManyRequest {
+                tree: TreeID {
+                    root,
+                    position: ptr.borrow().hash,
+                },
+                keys: self.keys.clone(),
+            },
+        )?;
+        Ok(rsp.proof)
+    }
+}
+
+/// Options for the internal lookup operation.
+struct GetOptions<'a> {
+    /// Proof builder to include visited nodes into, if any.
+    proof_builder: Option<&'a mut ProofBuilder>,
+    /// Whether siblings of the nodes on the path should also be fetched.
+    include_siblings: bool,
+    /// Whether to only check the local cache without invoking the read syncer.
+    check_only: bool,
+}
+
 impl Tree {
     /// Get an existing key.
     pub fn get(&self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
@@ -57,7 +100,129 @@ impl Tree {
         }
     }
 
+    /// Fetch a single key and return the corresponding proof.
+    pub fn sync_get(&self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        let ctx = ctx.freeze();
+        self.check_sync_root(&request.tree.root)?;
+        let pending_root = self.cache.borrow().get_pending_root();
+
+        // Remember where the path from root to target node ends (will end).
+        self.cache.borrow_mut().mark_position();
+
+        let mut pb = ProofBuilder::new(request.tree.root.hash, request.tree.position);
+        let mut opts = GetOptions {
+            proof_builder: Some(&mut pb),
+            include_siblings: request.include_siblings,
+            check_only: false,
+        };
+        self._get(&ctx, pending_root, 0, &request.key, &mut opts, false)?;
+        let proof = pb.build(Context::create_child(&ctx))?;
+
+        Ok(ProofResponse { proof })
+    }
+
+    /// Build a proof that the given key is not present in the tree.
+    ///
+    /// The tree must not have any uncommitted modifications and the proof is
+    /// generated against its last synced root. The proof can be verified using
+    /// `ProofVerifier::verify_absence`.
+    pub fn prove_absence(&self, ctx: Context, key: &[u8]) -> Result<Proof> {
+        let ctx = ctx.freeze();
+        let root = self.cache.borrow().get_sync_root();
+        self.check_sync_root(&root)?;
+        let pending_root = self.cache.borrow().get_pending_root();
+
+        // Remember where the path from root to target node ends (will end).
+        self.cache.borrow_mut().mark_position();
+
+        let mut pb = ProofBuilder::new(root.hash, root.hash);
+        let mut opts = GetOptions {
+            proof_builder: Some(&mut pb),
+            include_siblings: false,
+            check_only: false,
+        };
+        if self
+            ._get(&ctx, pending_root, 0, &key.to_vec(), &mut opts, false)?
+            .is_some()
+        {
+            return Err(TreeError::KeyExists.into());
+        }
+
+        pb.build(Context::create_child(&ctx))
+    }
+
+    /// Populate the in-memory tree with nodes for the given keys.
+    ///
+    /// All keys which cannot be resolved from the local cache are fetched
+    /// using a single request to the read syncer. In case the read syncer does
+    /// not support batched fetches, the keys are fetched one by one instead.
+    pub fn prefetch_keys<K: AsRef<[u8]>>(&self, ctx: Context, keys: &[K]) -> Result<()> {
+        let ctx = ctx.freeze();
+        let missing: Vec<ByteBuf> = keys
+            .iter()
+            .filter(|key| {
+                self._get_top(Context::create_child(&ctx), key.as_ref(), true)
+                    .is_err()
+            })
+            .map(|key| ByteBuf::from(key.as_ref()))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let pending_root = self.cache.borrow().get_pending_root();
+        let result = self.cache.borrow_mut().remote_sync(
+            &ctx,
+            pending_root,
+            FetcherSyncGetMany::new(&missing),
+        );
+        match result {
+            Err(err) if matches!(err.downcast_ref(), Some(SyncerError::Unsupported)) => {
+                for key in &missing {
+                    self._get_top(Context::create_child(&ctx), key, false)?;
+                }
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Fetch multiple keys and return a single proof covering all of them.
+    pub fn sync_get_many(&self, ctx: Context, request: GetManyRequest) -> Result<ProofResponse> {
+        let ctx = ctx.freeze();
+        self.check_sync_root(&request.tree.root)?;
+
+        // First, trigger same prefetching locally if a remote read syncer
+        // is available. This is needed to ensure that the same optimization
+        // carries on to the next layer.
+        if self.has_remote_read_syncer() {
+            self.prefetch_keys(Context::create_child(&ctx), &request.keys)?;
+        }
+
+        let pending_root = self.cache.borrow().get_pending_root();
+        let mut pb = ProofBuilder::new(request.tree.root.hash, request.tree.position);
+        for key in &request.keys {
+            // Remember where the path from root to target node ends (will end).
+            self.cache.borrow_mut().mark_position();
+
+            let mut opts = GetOptions {
+                proof_builder: Some(&mut pb),
+                include_siblings: false,
+                check_only: false,
+            };
+            self._get(&ctx, pending_root.clone(), 0, key, &mut opts, false)?;
+        }
+        let proof = pb.build(Context::create_child(&ctx))?;
+
+        Ok(ProofResponse { proof })
+    }
+
     fn _get_top(&self, ctx: Context, key: &[u8], check_only: bool) -> Result<Option<Vec<u8>>> {
+        // Keys which were recently found to be absent do not need another lookup.
+        if self.cache.borrow().is_absent(key) {
+            return Ok(None);
+        }
+
         let ctx = ctx.freeze();
         let boxed_key = key.to_vec();
         let pending_root = self.cache.borrow().get_pending_root();
@@ -65,7 +230,16 @@ impl Tree {
         // Remember where the path from root to target node ends (will end).
         self.cache.borrow_mut().mark_position();
 
-        Ok(self._get(&ctx, pending_root, 0, &boxed_key, 0, check_only)?)
+        let mut opts = GetOptions {
+            proof_builder: None,
+            include_siblings: false,
+            check_only,
+        };
+        let value = self._get(&ctx, pending_root, 0, &boxed_key, &mut opts, false)?;
+        if value.is_none() {
+            self.cache.borrow_mut().record_absent(key);
+        }
+        Ok(value)
     }
 
     fn _get(
@@ -74,19 +248,30 @@ impl Tree {
         ptr: NodePtrRef,
         bit_depth: Depth,
         key: &Key,
-        depth: Depth,
-        check_only: bool,
+        opts: &mut GetOptions,
+        stop: bool,
     ) -> Result<Option<Value>> {
         let node_ref = self.cache.borrow_mut().deref_node_ptr(
             ctx,
             ptr,
-            if check_only {
+            if opts.check_only {
                 None
             } else {
-                Some(FetcherSyncGet::new(key, false))
+                Some(FetcherSyncGet::new(key, opts.include_siblings))
             },
         )?;
 
+        // Include nodes in proof if we have a proof builder.
+        if let (Some(pb), Some(node_ref)) = (opts.proof_builder.as_mut(), node_ref.as_ref()) {
+            pb.include(&*node_ref.borrow());
+        }
+
+        // This may be used to only include the given node in a proof and not
+        // traverse the tree further (e.g., to fetch a sibling).
+        if stop {
+            return Ok(None);
+        }
+
         match classify_noderef!(?node_ref) {
             NodeKind::None => {
                 // Reached a nil node, there is nothing here.
@@ -94,45 +279,62 @@ impl Tree {
             }
             NodeKind::Internal => {
                 let node_ref = node_ref.unwrap();
-                if let NodeBox::Internal(ref mut n) = *node_ref.borrow_mut() {
+                if let NodeBox::Internal(ref n) = *node_ref.borrow() {
                     // Internal node.
+                    let bit_length = bit_depth + n.label_bit_length;
+
+                    // Modified nodes may have labels merged from collapsed paths, so
+                    // blindly following the key bits could lead into subtrees which
+                    // are not on the key's path in the synced root.
+                    if !n.clean && !n.label_matches(bit_depth, key) {
+                        return Ok(None);
+                    }
+
                     // Does lookup key end here? Look into LeafNode.
-                    if key.bit_length() == bit_depth + n.label_bit_length {
+                    if key.bit_length() == bit_length {
+                        // Include siblings before disabling the proof builder for the leaf node.
+                        if opts.include_siblings {
+                            // Also fetch the left and right siblings.
+                            self._get(ctx, n.left.clone(), bit_length, key, opts, true)?;
+                            self._get(ctx, n.right.clone(), bit_length, key, opts, true)?;
+                        }
+
+                        // Omit the proof builder as the leaf node is always included with
+                        // the internal node itself.
+                        let mut leaf_opts = GetOptions {
+                            proof_builder: None,
+                            include_siblings: opts.include_siblings,
+                            check_only: opts.check_only,
+                        };
                         return self._get(
                             ctx,
                             n.leaf_node.clone(),
-                            bit_depth + n.label_bit_length,
+                            bit_length,
                             key,
-                            depth,
-                            check_only,
+                            &mut leaf_opts,
+                            false,
                         );
                     }
 
                     // Lookup key is too short for the current n.Label. It's not stored.
-                    if key.bit_length() < bit_depth + n.label_bit_length {
+                    if key.bit_length() < bit_length {
                         return Ok(None);
                     }
 
                     // Continue recursively based on a bit value.
-                    if key.get_bit(bit_depth + n.label_bit_length) {
-                        return self._get(
-                            ctx,
-                            n.right.clone(),
-                            bit_depth + n.label_bit_length,
-                            key,
-                            depth + 1,
-                            check_only,
-                        );
+                    let (next, sibling) = if key.get_bit(bit_length) {
+                        (n.right.clone(), n.left.clone())
                     } else {
-                        return self._get(
-                            ctx,
-                            n.left.clone(),
-                            bit_depth + n.label_bit_length,
-                            key,
-                            depth + 1,
-                            check_only,
-                        );
+                        (n.left.clone(), n.right.clone())
+                    };
+                    let value = self._get(ctx, next, bit_length, key, opts, false)?;
+
+                    if opts.include_siblings {
+                        // Also fetch the sibling.
+                        self._get(ctx, sibling, bit_length, key, opts, true)?;
                     }
+
+                    return Ok(value);
                 }
 
                 unreachable!("node kind is internal node");
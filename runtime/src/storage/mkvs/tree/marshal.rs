@@ -72,8 +72,21 @@ impl Marshal for NodeKind {
     }
 }
 
-impl Marshal for InternalNode {
-    fn marshal_binary(&self) -> Result<Vec<u8>> {
+impl NodeBox {
+    /// Marshal the node into a binary form without any hash pointers
+    /// (e.g., for proofs).
+    pub fn compact_marshal_binary(&self) -> Result<Vec<u8>> {
+        match self {
+            NodeBox::Internal(ref n) => n.compact_marshal_binary(),
+            NodeBox::Leaf(ref n) => n.marshal_binary(),
+        }
+    }
+}
+
+impl InternalNode {
+    /// Marshal the internal node into a binary form without any hash
+    /// pointers (e.g., for proofs).
+    pub fn compact_marshal_binary(&self) -> Result<Vec<u8>> {
         let leaf_node_binary: Vec<u8>;
         if self.leaf_node.borrow().is_null() {
             leaf_node_binary = vec![NodeKind::None as u8];
@@ -89,6 +102,14 @@ impl Marshal for InternalNode {
         result.append(&mut self.label_bit_length.marshal_binary()?);
         result.extend_from_slice(&self.label);
         result.extend_from_slice(leaf_node_binary.as_ref());
+
+        Ok(result)
+    }
+}
+
+impl Marshal for InternalNode {
+    fn marshal_binary(&self) -> Result<Vec<u8>> {
+        let mut result = self.compact_marshal_binary()?;
         result.extend_from_slice(self.left.borrow().hash.as_ref());
         result.extend_from_slice(self.right.borrow().hash.as_ref());
 
@@ -1,7 +1,12 @@
 #[macro_use]
 mod macros;
 
+mod analyze;
+mod apply;
+mod async_ops;
 mod commit;
+mod diff;
+mod dump;
 mod errors;
 mod insert;
 mod iterator;
@@ -11,15 +16,22 @@ mod node;
 mod overlay;
 mod prefetch;
 mod remove;
+mod shared;
 mod tree;
 
+pub use analyze::*;
+pub use apply::*;
+pub use async_ops::*;
 pub use commit::*;
+pub use diff::*;
+pub use dump::*;
 pub use errors::*;
 pub use insert::*;
 pub use iterator::*;
 pub use node::*;
 pub use overlay::*;
 pub use remove::*;
+pub use shared::*;
 pub use tree::*;
 
 #[cfg(test)]
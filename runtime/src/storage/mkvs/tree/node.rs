@@ -248,6 +248,22 @@ pub struct InternalNode {
     pub right: NodePtrRef,
 }
 
+impl InternalNode {
+    /// Check whether the node's label matches the given key, assuming the node
+    /// is located at the given bit depth.
+    pub fn label_matches(&self, bit_depth: Depth, key: &Key) -> bool {
+        if key.bit_length() < bit_depth + self.label_bit_length {
+            return false;
+        }
+        let (_, key_remainder) = key.split(bit_depth, key.bit_length());
+        self.label.common_prefix_len(
+            self.label_bit_length,
+            &key_remainder,
+            key.bit_length() - bit_depth,
+        ) == self.label_bit_length
+    }
+}
+
 impl Node for InternalNode {
     fn is_clean(&self) -> bool {
         self.clean
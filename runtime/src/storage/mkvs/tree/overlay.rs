@@ -1,16 +1,73 @@
 use std::{
-    collections::{btree_map, BTreeMap, HashSet},
-    iter::{Iterator, Peekable},
+    cell::RefCell,
+    cmp,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    iter::Iterator,
+    mem,
+    ops::Bound::{Excluded, Included, Unbounded},
 };
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use io_context::Context;
 
 use crate::{
     common::{crypto::hash::Hash, namespace::Namespace},
     storage::mkvs::{self, tree::*},
+    transaction::rwset::ReadWriteSet,
 };
 
+/// Number of items to prefetch when enumerating keys for range removals.
+const REMOVE_PREFETCH: usize = 1000;
+
+/// A savepoint in an overlay tree, which can be used to roll back any updates
+/// made after the savepoint was created.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Savepoint {
+    id: u64,
+}
+
+/// State of a key in the overlay before it was updated.
+struct UndoEntry {
+    key: Vec<u8>,
+    value: Option<Vec<u8>>,
+    dirty: bool,
+}
+
+/// Recorder of the (coarsened) keys read and written through an overlay.
+struct ReadWriteSetRecorder {
+    granularity: u16,
+    read_set: BTreeSet<Vec<u8>>,
+    write_set: BTreeSet<Vec<u8>>,
+}
+
+impl ReadWriteSetRecorder {
+    fn new(granularity: u16) -> Self {
+        Self {
+            granularity,
+            read_set: BTreeSet::new(),
+            write_set: BTreeSet::new(),
+        }
+    }
+
+    fn coarsen(&self, key: &[u8]) -> Vec<u8> {
+        key[..cmp::min(key.len(), self.granularity as usize)].to_vec()
+    }
+
+    fn take(&mut self) -> ReadWriteSet {
+        ReadWriteSet {
+            granularity: self.granularity,
+            read_set: mem::take(&mut self.read_set)
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            write_set: mem::take(&mut self.write_set)
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
+
 /// A key-value tree overlay that holds all updates in memory and only commits them if requested.
 /// This can be used to create snapshots that can be discarded.
 ///
@@ -20,6 +77,17 @@ pub struct OverlayTree<T: mkvs::FallibleMKVS> {
     inner: T,
     overlay: BTreeMap<Vec<u8>, Vec<u8>>,
     dirty: HashSet<Vec<u8>>,
+
+    /// Active savepoints together with the length of the undo log at the time
+    /// each savepoint was created.
+    savepoints: Vec<(Savepoint, usize)>,
+    /// Log of overlay states to restore on rollback, only kept while there are
+    /// active savepoints.
+    undo_log: Vec<UndoEntry>,
+    next_savepoint_id: u64,
+
+    /// Read/write set recorder, if recording has been enabled.
+    rw_set: RefCell<Option<ReadWriteSetRecorder>>,
 }
 
 impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
@@ -29,11 +97,122 @@ impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
             inner,
             overlay: BTreeMap::new(),
             dirty: HashSet::new(),
+            savepoints: Vec::new(),
+            undo_log: Vec::new(),
+            next_savepoint_id: 0,
+            rw_set: RefCell::new(None),
         }
     }
 
+    /// Start recording all keys read and written through the overlay, coarsened to
+    /// prefixes of the given size (in bytes).
+    ///
+    /// Any previously recorded keys are discarded.
+    pub fn record_rw_set(&mut self, granularity: u16) {
+        *self.rw_set.borrow_mut() = Some(ReadWriteSetRecorder::new(granularity));
+    }
+
+    /// Return the read/write set recorded since recording was started or since the
+    /// last call to this method. Recording continues with an empty set.
+    ///
+    /// Returns `None` in case recording has not been enabled.
+    pub fn take_rw_set(&mut self) -> Option<ReadWriteSet> {
+        self.rw_set
+            .borrow_mut()
+            .as_mut()
+            .map(|rw_set| rw_set.take())
+    }
+
+    fn record_read(&self, key: &[u8]) {
+        if let Some(ref mut rw_set) = *self.rw_set.borrow_mut() {
+            let key = rw_set.coarsen(key);
+            rw_set.read_set.insert(key);
+        }
+    }
+
+    fn record_write(&self, key: &[u8]) {
+        if let Some(ref mut rw_set) = *self.rw_set.borrow_mut() {
+            let key = rw_set.coarsen(key);
+            rw_set.write_set.insert(key);
+        }
+    }
+
+    /// Create a new savepoint. Savepoints can be nested.
+    pub fn savepoint(&mut self) -> Savepoint {
+        let savepoint = Savepoint {
+            id: self.next_savepoint_id,
+        };
+        self.next_savepoint_id += 1;
+        self.savepoints.push((savepoint, self.undo_log.len()));
+
+        savepoint
+    }
+
+    /// Roll back all updates made after the given savepoint was created.
+    ///
+    /// Any savepoints created after the given savepoint are released, while the
+    /// given savepoint remains active.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) -> Result<()> {
+        let index = self.savepoint_index(savepoint)?;
+        let undo_len = self.savepoints[index].1;
+        self.savepoints.truncate(index + 1);
+
+        while self.undo_log.len() > undo_len {
+            let entry = self.undo_log.pop().unwrap();
+            match entry.value {
+                Some(value) => self.overlay.insert(entry.key.clone(), value),
+                None => self.overlay.remove(&entry.key),
+            };
+            if entry.dirty {
+                self.dirty.insert(entry.key);
+            } else {
+                self.dirty.remove(&entry.key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Release the given savepoint and any savepoints created after it, keeping
+    /// all updates.
+    pub fn release(&mut self, savepoint: Savepoint) -> Result<()> {
+        let index = self.savepoint_index(savepoint)?;
+        self.savepoints.truncate(index);
+        if self.savepoints.is_empty() {
+            self.undo_log.clear();
+        }
+
+        Ok(())
+    }
+
+    fn savepoint_index(&self, savepoint: Savepoint) -> Result<usize> {
+        self.savepoints
+            .iter()
+            .position(|(sp, _)| *sp == savepoint)
+            .ok_or_else(|| anyhow!("overlay: savepoint is not active"))
+    }
+
+    /// Record the current state of the given key so it can be restored on rollback.
+    fn record_undo(&mut self, key: &[u8]) {
+        if self.savepoints.is_empty() {
+            return;
+        }
+
+        self.undo_log.push(UndoEntry {
+            key: key.to_owned(),
+            value: self.overlay.get(key).cloned(),
+            dirty: self.dirty.contains(key),
+        });
+    }
+
     /// Get an existing key.
     pub fn get(&self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.record_read(key);
+        self._get(ctx, key)
+    }
+
+    /// Get an existing key without recording the read.
+    fn _get(&self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
         // For dirty values, check the overlay.
         if self.dirty.contains(key) {
             return Ok(self.overlay.get(key).map(|v| v.clone()));
@@ -44,9 +223,15 @@ impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
     }
 
     /// Insert a key/value pair into the tree.
+    ///
+    /// Returns an error if the key or value exceed the size limits of the inner tree.
     pub fn insert(&mut self, ctx: Context, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
-        let previous = self.get(ctx, key)?;
+        self.inner.check_limits(key, value)?;
+
+        let previous = self._get(ctx, key)?;
 
+        self.record_write(key);
+        self.record_undo(key);
         self.overlay.insert(key.to_owned(), value.to_owned());
         self.dirty.insert(key.to_owned());
 
@@ -56,8 +241,11 @@ impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
     /// Remove entry with given key, returning the value at the key if the key was previously
     /// in the database.
     pub fn remove(&mut self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.record_write(key);
+
         // For dirty values, remove from the overlay.
         if self.dirty.contains(key) {
+            self.record_undo(key);
             return Ok(self.overlay.remove(key).map(|v| v.clone()));
         }
 
@@ -65,19 +253,106 @@ impl<T: mkvs::FallibleMKVS> OverlayTree<T> {
 
         // Do not treat a value as dirty if it was not dirty before and did not exist in the inner tree.
         if value.is_some() {
+            self.record_undo(key);
             self.dirty.insert(key.to_owned());
         }
         Ok(value)
     }
 
+    /// Remove all entries with keys starting with the given prefix.
+    pub fn remove_prefix(&mut self, ctx: Context, prefix: &[u8]) -> Result<()> {
+        self.remove_matching(ctx, prefix, |key| key.starts_with(prefix))
+    }
+
+    /// Remove all entries with keys in the range [`start`, `end`).
+    pub fn remove_range(&mut self, ctx: Context, start: &[u8], end: &[u8]) -> Result<()> {
+        if start >= end {
+            return Ok(());
+        }
+        self.remove_matching(ctx, start, |key| key < end)
+    }
+
+    /// Remove all entries starting at the given key for as long as keys match the
+    /// given predicate.
+    fn remove_matching<F>(&mut self, ctx: Context, start: &[u8], matches: F) -> Result<()>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        // Mark all matching keys in the inner tree as dirty. As each removed key must
+        // be recorded in the write log, the keys need to be enumerated. Prefetch to
+        // avoid a remote sync for each removed key.
+        let mut removed = Vec::new();
+        {
+            let mut it = self.inner.iter(ctx);
+            it.set_prefetch(REMOVE_PREFETCH);
+            it.seek(start);
+            while let Some(key) = it.get_key() {
+                if !matches(key) {
+                    break;
+                }
+                removed.push(key.clone());
+                mkvs::Iterator::next(&mut *it);
+            }
+            if let Some(error) = it.error() {
+                return Err(anyhow!("{}", error));
+            }
+        }
+        for key in removed {
+            self.record_write(&key);
+            self.record_undo(&key);
+            self.dirty.insert(key);
+        }
+
+        // Remove all matching keys from the overlay. They remain dirty so that
+        // the removals are propagated to the inner tree on commit.
+        let removed: Vec<Vec<u8>> = self
+            .overlay
+            .range::<[u8], _>((Included(start), Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| matches(key))
+            .cloned()
+            .collect();
+        for key in removed {
+            self.record_write(&key);
+            self.record_undo(&key);
+            self.overlay.remove(&key);
+            self.dirty.insert(key);
+        }
+
+        Ok(())
+    }
+
     /// Return an iterator over the tree.
     pub fn iter(&self, ctx: Context) -> OverlayTreeIterator<T> {
-        OverlayTreeIterator::new(ctx, self)
+        OverlayTreeIterator::new(self, self.inner.iter(ctx))
+    }
+
+    /// Return an iterator over all entries with keys starting with the given prefix.
+    ///
+    /// Entries in the overlay are merged with entries under the prefix in the
+    /// inner tree.
+    pub fn iter_prefix(
+        &self,
+        ctx: Context,
+        prefix: &[u8],
+    ) -> PrefixIterator<OverlayTreeIterator<T>> {
+        PrefixIterator::new(
+            OverlayTreeIterator::new(self, self.inner.iter_prefix(ctx, prefix)),
+            prefix,
+        )
     }
 
     /// Commit any modifications to the underlying tree.
+    ///
+    /// Returns an error if any savepoints are active, as committed updates cannot be rolled back.
     pub fn commit(&mut self, ctx: Context) -> Result<mkvs::WriteLog> {
+        if !self.savepoints.is_empty() {
+            return Err(anyhow!(
+                "overlay: cannot commit while savepoints are active"
+            ));
+        }
         let ctx = ctx.freeze();
+
         let mut log: mkvs::WriteLog = Vec::new();
 
         // Insert all items present in the overlay.
@@ -132,20 +407,20 @@ pub struct OverlayTreeIterator<'tree, T: mkvs::FallibleMKVS> {
     tree: &'tree OverlayTree<T>,
 
     inner: Box<dyn mkvs::Iterator + 'tree>,
-    overlay: Peekable<btree_map::Range<'tree, Vec<u8>, Vec<u8>>>,
-    overlay_valid: bool,
+    overlay: Option<(&'tree Vec<u8>, &'tree Vec<u8>)>,
+    reverse: bool,
 
     key: Option<Vec<u8>>,
     value: Option<Vec<u8>>,
 }
 
 impl<'tree, T: mkvs::FallibleMKVS> OverlayTreeIterator<'tree, T> {
-    fn new(ctx: Context, tree: &'tree OverlayTree<T>) -> Self {
+    fn new(tree: &'tree OverlayTree<T>, inner: Box<dyn mkvs::Iterator + 'tree>) -> Self {
         Self {
             tree,
-            inner: tree.inner.iter(ctx),
-            overlay: tree.overlay.range(vec![]..).peekable(),
-            overlay_valid: true,
+            inner,
+            overlay: None,
+            reverse: false,
             key: None,
             value: None,
         }
@@ -162,23 +437,24 @@ impl<'tree, T: mkvs::FallibleMKVS> OverlayTreeIterator<'tree, T> {
             {
                 break;
             }
-            self.inner.next();
+            if self.reverse {
+                self.inner.prev();
+            } else {
+                self.inner.next();
+            }
         }
 
         let i_key = self.inner.get_key();
-        let o_item = self.overlay.peek();
-        self.overlay_valid = o_item.is_some();
+        let o_item = self.overlay;
 
         if self.inner.is_valid()
-            && (!self.overlay_valid
-                || i_key.as_ref().expect("inner.is_valid") < o_item.expect("overlay_valid").0)
+            && (o_item.is_none() || self.inner_first(i_key.as_ref().expect("inner.is_valid")))
         {
-            // Key of inner iterator is smaller than the key of the overlay iterator.
+            // Key of inner iterator comes before the key of the overlay iterator.
             self.key = i_key.clone();
             self.value = self.inner.get_value().clone();
-        } else if self.overlay_valid {
-            // Key of overlay iterator is smaller than or equal to the key of the inner iterator.
-            let (o_key, o_value) = o_item.expect("overlay_valid");
+        } else if let Some((o_key, o_value)) = o_item {
+            // Key of overlay iterator comes before or is equal to the key of the inner iterator.
             self.key = Some(o_key.to_vec());
             self.value = Some(o_value.to_vec());
         } else {
@@ -186,19 +462,72 @@ impl<'tree, T: mkvs::FallibleMKVS> OverlayTreeIterator<'tree, T> {
             self.key = None;
             self.value = None;
         }
+
+        if let Some(ref key) = self.key {
+            self.tree.record_read(key);
+        }
+    }
+
+    /// Check whether the given key of the inner iterator comes before the key of the
+    /// overlay iterator in the current iteration order.
+    fn inner_first(&self, i_key: &Vec<u8>) -> bool {
+        let o_key = self.overlay.expect("overlay is valid").0;
+        if self.reverse {
+            i_key > o_key
+        } else {
+            i_key < o_key
+        }
     }
 
     fn next(&mut self) {
-        if !self.overlay_valid
+        if self.reverse {
+            // Reposition both iterators at the current key before moving forward.
+            match self.key.clone() {
+                Some(key) => mkvs::Iterator::seek(self, &key),
+                None => return,
+            }
+        }
+
+        if self.overlay.is_none()
             || (self.inner.is_valid()
                 && self.inner.get_key().as_ref().expect("inner.is_valid")
-                    <= self.overlay.peek().expect("overlay_valid").0)
+                    <= self.overlay.expect("overlay is valid").0)
         {
             // Key of inner iterator is smaller or equal than the key of the overlay iterator.
             self.inner.next();
         } else {
             // Key of inner iterator is greater than the key of the overlay iterator.
-            self.overlay.next();
+            let o_key = self.overlay.expect("overlay is valid").0;
+            self.overlay = self
+                .tree
+                .overlay
+                .range::<Vec<u8>, _>((Excluded(o_key), Unbounded))
+                .next();
+        }
+
+        self.update_iterator_position();
+    }
+
+    fn prev(&mut self) {
+        if !self.reverse {
+            // Reposition both iterators at the current key before moving backward.
+            match self.key.clone() {
+                Some(key) => mkvs::Iterator::seek_for_prev(self, &key),
+                None => return,
+            }
+        }
+
+        if self.overlay.is_none()
+            || (self.inner.is_valid()
+                && self.inner.get_key().as_ref().expect("inner.is_valid")
+                    >= self.overlay.expect("overlay is valid").0)
+        {
+            // Key of inner iterator is greater or equal than the key of the overlay iterator.
+            self.inner.prev();
+        } else {
+            // Key of inner iterator is smaller than the key of the overlay iterator.
+            let o_key = self.overlay.expect("overlay is valid").0;
+            self.overlay = self.tree.overlay.range::<Vec<u8>, _>(..o_key).next_back();
         }
 
         self.update_iterator_position();
@@ -230,7 +559,7 @@ impl<'tree, T: mkvs::FallibleMKVS> mkvs::Iterator for OverlayTreeIterator<'tree,
 
     fn is_valid(&self) -> bool {
         // If either iterator is valid, the merged iterator is valid.
-        self.inner.is_valid() || self.overlay_valid
+        self.inner.is_valid() || self.overlay.is_some()
     }
 
     fn error(&self) -> &Option<Error> {
@@ -242,8 +571,29 @@ impl<'tree, T: mkvs::FallibleMKVS> mkvs::Iterator for OverlayTreeIterator<'tree,
     }
 
     fn seek(&mut self, key: &[u8]) {
+        self.reverse = false;
         self.inner.seek(key);
-        self.overlay = self.tree.overlay.range(key.to_vec()..).peekable();
+        self.overlay = self.tree.overlay.range(key.to_vec()..).next();
+
+        self.update_iterator_position();
+    }
+
+    fn seek_last(&mut self) {
+        self.reverse = true;
+        self.inner.seek_last();
+        self.overlay = self.tree.overlay.iter().next_back();
+
+        self.update_iterator_position();
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) {
+        self.reverse = true;
+        self.inner.seek_for_prev(key);
+        self.overlay = self
+            .tree
+            .overlay
+            .range::<[u8], _>((Unbounded, Included(key)))
+            .next_back();
 
         self.update_iterator_position();
     }
@@ -259,6 +609,10 @@ impl<'tree, T: mkvs::FallibleMKVS> mkvs::Iterator for OverlayTreeIterator<'tree,
     fn next(&mut self) {
         OverlayTreeIterator::next(self)
     }
+
+    fn prev(&mut self) {
+        OverlayTreeIterator::prev(self)
+    }
 }
 
 impl<T: mkvs::FallibleMKVS> mkvs::MKVS for OverlayTree<T> {
@@ -278,18 +632,46 @@ impl<T: mkvs::FallibleMKVS> mkvs::MKVS for OverlayTree<T> {
         self.insert(ctx, key, value).unwrap()
     }
 
+    fn check_limits(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.check_limits(key, value)
+    }
+
     fn remove(&mut self, ctx: Context, key: &[u8]) -> Option<Vec<u8>> {
         self.remove(ctx, key).unwrap()
     }
 
+    fn remove_prefix(&mut self, ctx: Context, prefix: &[u8]) {
+        self.remove_prefix(ctx, prefix).unwrap()
+    }
+
+    fn remove_range(&mut self, ctx: Context, start: &[u8], end: &[u8]) {
+        self.remove_range(ctx, start, end).unwrap()
+    }
+
     fn prefetch_prefixes(&self, ctx: Context, prefixes: &Vec<mkvs::Prefix>, limit: u16) {
         self.inner.prefetch_prefixes(ctx, prefixes, limit).unwrap()
     }
 
+    fn prefetch_keys(&self, ctx: Context, keys: &[Vec<u8>]) {
+        // Dirty keys are served from the overlay, no need to fetch them.
+        let keys: Vec<Vec<u8>> = keys
+            .iter()
+            .filter(|key| !self.dirty.contains(*key))
+            .cloned()
+            .collect();
+        // Prefetching is only an optimization and keys are fetched again when
+        // accessed, so ignore failures.
+        let _ = self.inner.prefetch_keys(ctx, &keys);
+    }
+
     fn iter(&self, ctx: Context) -> Box<dyn mkvs::Iterator + '_> {
         Box::new(self.iter(ctx))
     }
 
+    fn iter_prefix(&self, ctx: Context, prefix: &[u8]) -> Box<dyn mkvs::Iterator + '_> {
+        Box::new(self.iter_prefix(ctx, prefix))
+    }
+
     fn commit(
         &mut self,
         ctx: Context,
@@ -303,7 +685,11 @@ impl<T: mkvs::FallibleMKVS> mkvs::MKVS for OverlayTree<T> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::storage::mkvs::{sync::NoopReadSyncer, tree::iterator::test::test_iterator_with};
+    use crate::storage::mkvs::{
+        sync::NoopReadSyncer,
+        tree::iterator::test::{test_iterator_with, test_reverse_iterator_with},
+        Iterator as _,
+    };
 
     #[test]
     fn test_overlay() {
@@ -424,6 +810,21 @@ mod test {
         let it = overlay.iter(Context::background());
         test_iterator_with(&items, it, &tests);
 
+        // Make sure that merged overlay iterator also works in reverse.
+        let reverse_tests = vec![
+            (b"".to_vec(), -1),
+            (b"k".to_vec(), -1),
+            (b"key 1".to_vec(), 1),
+            (b"key 3".to_vec(), 1),
+            (b"key 5".to_vec(), 2),
+            (b"key 6".to_vec(), 2),
+            (b"key 7".to_vec(), 3),
+            (b"key 9".to_vec(), 5),
+            (b"key A".to_vec(), 5),
+        ];
+        let it = overlay.iter(Context::background());
+        test_reverse_iterator_with(&items, it, &reverse_tests);
+
         // Commit the overlay.
         overlay.commit(Context::background()).unwrap();
 
@@ -437,4 +838,197 @@ mod test {
         let it = tree.iter(Context::background());
         test_iterator_with(&items, it, &tests);
     }
+
+    #[test]
+    fn test_overlay_savepoints() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for key in &[b"a 1", b"a 2", b"b 1"] {
+            tree.insert(Context::background(), *key, b"inner").unwrap();
+        }
+
+        let mut overlay = OverlayTree::new(&mut tree);
+        overlay
+            .insert(Context::background(), b"a 1", b"first")
+            .unwrap();
+
+        let outer = overlay.savepoint();
+        overlay
+            .insert(Context::background(), b"a 1", b"second")
+            .unwrap();
+        overlay.remove(Context::background(), b"b 1").unwrap();
+
+        let inner = overlay.savepoint();
+        overlay.remove_prefix(Context::background(), b"a").unwrap();
+        overlay
+            .insert(Context::background(), b"c 1", b"inner savepoint")
+            .unwrap();
+
+        // Rolling back the inner savepoint should only revert its own updates.
+        overlay.rollback_to(inner).unwrap();
+        let mut it = overlay.iter(Context::background());
+        it.rewind();
+        let items: Vec<(Vec<u8>, Vec<u8>)> = it.collect();
+        assert_eq!(
+            items,
+            vec![
+                (b"a 1".to_vec(), b"second".to_vec()),
+                (b"a 2".to_vec(), b"inner".to_vec()),
+            ]
+        );
+
+        // The savepoint remains active after a rollback.
+        overlay
+            .insert(Context::background(), b"c 2", b"inner savepoint")
+            .unwrap();
+        overlay.release(inner).unwrap();
+        assert!(overlay.rollback_to(inner).is_err());
+        assert!(overlay.release(inner).is_err());
+
+        // Rolling back the outer savepoint should also revert released inner savepoints.
+        overlay.rollback_to(outer).unwrap();
+        overlay.release(outer).unwrap();
+        let mut it = overlay.iter(Context::background());
+        it.rewind();
+        let items: Vec<(Vec<u8>, Vec<u8>)> = it.collect();
+        assert_eq!(
+            items,
+            vec![
+                (b"a 1".to_vec(), b"first".to_vec()),
+                (b"a 2".to_vec(), b"inner".to_vec()),
+                (b"b 1".to_vec(), b"inner".to_vec()),
+            ]
+        );
+
+        let write_log = overlay.commit(Context::background()).unwrap();
+        assert_eq!(write_log, vec![mkvs::LogEntry::new(b"a 1", b"first")]);
+    }
+
+    #[test]
+    fn test_overlay_rw_set() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for key in &[b"aa 1", b"aa 2", b"bb 1", b"cc 1"] {
+            tree.insert(Context::background(), *key, b"inner").unwrap();
+        }
+
+        let mut overlay = OverlayTree::new(&mut tree);
+        assert_eq!(overlay.take_rw_set(), None);
+
+        overlay.record_rw_set(2);
+        overlay.get(Context::background(), b"bb 1").unwrap();
+        overlay.get(Context::background(), b"x").unwrap();
+        overlay
+            .insert(Context::background(), b"dd 1", b"overlay")
+            .unwrap();
+        overlay.remove(Context::background(), b"cc 1").unwrap();
+        overlay.remove_prefix(Context::background(), b"aa").unwrap();
+        assert_eq!(
+            overlay.take_rw_set(),
+            Some(ReadWriteSet {
+                granularity: 2,
+                read_set: vec![b"bb".to_vec().into(), b"x".to_vec().into()],
+                write_set: vec![
+                    b"aa".to_vec().into(),
+                    b"cc".to_vec().into(),
+                    b"dd".to_vec().into(),
+                ],
+            })
+        );
+
+        // Recording continues with an empty set and also covers iteration.
+        {
+            let mut it = overlay.iter(Context::background());
+            it.seek(b"c");
+            assert!(it.is_valid());
+        }
+        assert_eq!(
+            overlay.take_rw_set(),
+            Some(ReadWriteSet {
+                granularity: 2,
+                read_set: vec![b"dd".to_vec().into()],
+                write_set: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_overlay_remove_prefix_range() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for key in &[b"a 1", b"a 2", b"b 1", b"b 2", b"c 1"] {
+            tree.insert(Context::background(), *key, b"inner").unwrap();
+        }
+
+        let mut overlay = OverlayTree::new(&mut tree);
+        overlay
+            .insert(Context::background(), b"a 3", b"overlay")
+            .unwrap();
+        overlay
+            .insert(Context::background(), b"b 1", b"overlay")
+            .unwrap();
+        overlay
+            .insert(Context::background(), b"c 2", b"overlay")
+            .unwrap();
+
+        overlay.remove_prefix(Context::background(), b"a").unwrap();
+        overlay
+            .remove_range(Context::background(), b"b 2", b"c 2")
+            .unwrap();
+
+        let mut it = overlay.iter(Context::background());
+        it.rewind();
+        let items: Vec<(Vec<u8>, Vec<u8>)> = it.collect();
+        assert_eq!(
+            items,
+            vec![
+                (b"b 1".to_vec(), b"overlay".to_vec()),
+                (b"c 2".to_vec(), b"overlay".to_vec()),
+            ]
+        );
+
+        let mut write_log = overlay.commit(Context::background()).unwrap();
+        write_log.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(
+            write_log,
+            vec![
+                mkvs::LogEntry {
+                    key: b"a 1".to_vec(),
+                    value: None,
+                },
+                mkvs::LogEntry {
+                    key: b"a 2".to_vec(),
+                    value: None,
+                },
+                mkvs::LogEntry {
+                    key: b"a 3".to_vec(),
+                    value: None,
+                },
+                mkvs::LogEntry::new(b"b 1", b"overlay"),
+                mkvs::LogEntry {
+                    key: b"b 2".to_vec(),
+                    value: None,
+                },
+                mkvs::LogEntry {
+                    key: b"c 1".to_vec(),
+                    value: None,
+                },
+                mkvs::LogEntry::new(b"c 2", b"overlay"),
+            ]
+        );
+
+        let mut it = tree.iter(Context::background());
+        it.rewind();
+        let items: Vec<(Vec<u8>, Vec<u8>)> = it.collect();
+        assert_eq!(
+            items,
+            vec![
+                (b"b 1".to_vec(), b"overlay".to_vec()),
+                (b"c 2".to_vec(), b"overlay".to_vec()),
+            ]
+        );
+    }
 }
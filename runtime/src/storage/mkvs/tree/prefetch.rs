@@ -1,7 +1,7 @@
 use anyhow::Result;
 use io_context::Context;
 
-use crate::storage::mkvs::{cache::*, sync::*, tree::*, Prefix};
+use crate::storage::mkvs::{self, cache::*, sync::*, tree::*, Prefix};
 
 pub(super) struct FetcherSyncGetPrefixes<'a> {
     prefixes: &'a Vec<Prefix>,
@@ -53,4 +53,58 @@ impl Tree {
             FetcherSyncGetPrefixes::new(prefixes, limit),
         )
     }
+
+    /// Fetch all keys under the given prefixes and return the corresponding proof.
+    pub fn sync_get_prefixes(
+        &self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        use mkvs::Iterator;
+
+        let ctx = ctx.freeze();
+        self.check_sync_root(&request.tree.root)?;
+
+        // First, trigger same prefetching locally if a remote read syncer
+        // is available. This is needed to ensure that the same optimization
+        // carries on to the next layer.
+        if self.has_remote_read_syncer() {
+            self.prefetch_prefixes(
+                Context::create_child(&ctx),
+                &request.prefixes,
+                request.limit,
+            )?;
+        }
+
+        let mut it = self
+            .iter(Context::create_child(&ctx))
+            .with_proof(request.tree.root.hash);
+
+        let mut total = 0;
+        'prefixes: for prefix in &request.prefixes {
+            it.seek(prefix);
+            while it.is_valid() {
+                if total >= request.limit {
+                    break 'prefixes;
+                }
+                if !it
+                    .get_key()
+                    .as_ref()
+                    .expect("iterator is valid")
+                    .starts_with(prefix)
+                {
+                    break;
+                }
+                mkvs::Iterator::next(&mut it);
+                total += 1;
+            }
+        }
+        if let Some(error) = it.take_error() {
+            return Err(error);
+        }
+
+        let proof = it.get_proof()?;
+
+        Ok(ProofResponse { proof })
+    }
 }
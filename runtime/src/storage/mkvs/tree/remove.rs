@@ -24,6 +24,37 @@ impl Tree {
         Ok(old_val)
     }
 
+    /// Remove all entries with keys starting with the given prefix.
+    pub fn remove_prefix(&mut self, ctx: Context, prefix: &[u8]) -> Result<()> {
+        let range = KeyRange::prefix(prefix);
+        self.remove_key_range(ctx, &range)
+    }
+
+    /// Remove all entries with keys in the range [`start`, `end`).
+    pub fn remove_range(&mut self, ctx: Context, start: &[u8], end: &[u8]) -> Result<()> {
+        if start >= end {
+            return Ok(());
+        }
+        let range = KeyRange {
+            start: start.to_vec(),
+            end: Some(end.to_vec()),
+        };
+        self.remove_key_range(ctx, &range)
+    }
+
+    fn remove_key_range(&mut self, ctx: Context, range: &KeyRange) -> Result<()> {
+        let ctx = ctx.freeze();
+        let pending_root = self.cache.borrow().get_pending_root();
+
+        // Remember where the path from root to target node ends (will end).
+        self.cache.borrow_mut().mark_position();
+
+        let (new_root, _) = self._remove_range(&ctx, pending_root, 0, Key::new(), 0, range)?;
+        self.cache.borrow_mut().set_pending_root(new_root);
+
+        Ok(())
+    }
+
     fn _remove(
         &mut self,
         ctx: &Arc<Context>,
@@ -47,18 +78,14 @@ impl Tree {
                 // Remove from internal node and recursively collapse the path, if needed.
                 let node_ref = node_ref.unwrap();
                 let (changed, old_val): (bool, Option<Value>);
-                let (remaining_leaf, remaining_left, remaining_right): (
-                    Option<NodeRef>,
-                    Option<NodeRef>,
-                    Option<NodeRef>,
-                );
                 if let NodeBox::Internal(ref mut n) = *node_ref.borrow_mut() {
                     // Remove from internal node and recursively collapse the branch, if
                     // needed.
                     let bit_length = bit_depth + n.label_bit_length;
 
-                    if key.bit_length() < bit_length {
-                        // Lookup key is too short for the current n.Label, so it doesn't exist.
+                    if !n.label_matches(bit_depth, key) {
+                        // Lookup key is too short for or doesn't match the current n.Label,
+                        // so it doesn't exist.
                         return Ok((ptr.clone(), false, None));
                     }
 
@@ -80,109 +107,12 @@ impl Tree {
                     } else {
                         n.left = new_child;
                     }
-
-                    // Fetch and check the remaining children.
-                    // NOTE: The leaf node is always included with the internal node.
-                    remaining_leaf = n.leaf_node.borrow().node.clone();
-                    remaining_left = self.cache.borrow_mut().deref_node_ptr(
-                        ctx,
-                        n.left.clone(),
-                        Some(FetcherSyncGet::new(key, true)),
-                    )?;
-                    remaining_right = self.cache.borrow_mut().deref_node_ptr(
-                        ctx,
-                        n.right.clone(),
-                        Some(FetcherSyncGet::new(key, true)),
-                    )?;
                 } else {
                     unreachable!("node kind is Internal");
                 }
 
-                // If exactly one child including LeafNode remains, collapse it.
-                match remaining_leaf {
-                    Some(_) => match remaining_left {
-                        Some(_) => (),
-                        None => match remaining_right {
-                            None => {
-                                let nd_leaf = noderef_as!(node_ref, Internal).leaf_node.clone();
-                                noderef_as_mut!(node_ref, Internal).leaf_node =
-                                    NodePointer::null_ptr();
-                                self.cache.borrow_mut().remove_node(ptr.clone());
-                                return Ok((nd_leaf, true, old_val));
-                            }
-                            Some(_) => (),
-                        },
-                    },
-                    None => {
-                        let mut nd_child: Option<NodeRef> = None;
-                        let mut node_ptr: NodePtrRef = NodePointer::null_ptr();
-                        let mut both_children = true;
-                        match remaining_left {
-                            Some(_) => match remaining_right {
-                                None => {
-                                    node_ptr = noderef_as!(node_ref, Internal).left.clone();
-                                    noderef_as_mut!(node_ref, Internal).left =
-                                        NodePointer::null_ptr();
-                                    nd_child = remaining_left;
-                                    both_children = false;
-                                }
-                                Some(_) => (),
-                            },
-                            None => match remaining_right {
-                                None => (),
-                                Some(_) => {
-                                    node_ptr = noderef_as!(node_ref, Internal).right.clone();
-                                    noderef_as_mut!(node_ref, Internal).right =
-                                        NodePointer::null_ptr();
-                                    nd_child = remaining_right;
-                                    both_children = false;
-                                }
-                            },
-                        }
-
-                        if !both_children {
-                            // If child is an internal node, also fix the label.
-                            match nd_child {
-                                Some(_) => match classify_noderef!(?nd_child) {
-                                    NodeKind::Internal => {
-                                        if let NodeBox::Internal(ref mut inode) =
-                                            *nd_child.unwrap().borrow_mut()
-                                        {
-                                            inode.label =
-                                                noderef_as!(node_ref, Internal).label.merge(
-                                                    noderef_as!(node_ref, Internal)
-                                                        .label_bit_length,
-                                                    &inode.label,
-                                                    inode.label_bit_length,
-                                                );
-                                            inode.label_bit_length +=
-                                                noderef_as!(node_ref, Internal).label_bit_length;
-                                            inode.clean = false;
-                                            node_ptr.borrow_mut().clean = false;
-                                        }
-                                    }
-                                    _ => (),
-                                },
-                                _ => (),
-                            }
-
-                            self.cache.borrow_mut().remove_node(ptr.clone());
-                            return Ok((node_ptr, true, old_val));
-                        }
-                    }
-                };
-
-                // Two or more children including leaf_node remain, just mark dirty bit.
-                if changed {
-                    noderef_as_mut!(node_ref, Internal).clean = false;
-                    ptr.borrow_mut().clean = false;
-                    // No longer eligible for eviction as it is dirty.
-                    self.cache
-                        .borrow_mut()
-                        .rollback_node(ptr.clone(), NodeKind::Internal);
-                }
-
-                return Ok((ptr.clone(), changed, old_val));
+                let (new_ptr, changed) = self._collapse(ctx, ptr, node_ref, key, changed)?;
+                return Ok((new_ptr, changed, old_val));
             }
             NodeKind::Leaf => {
                 // Remove from leaf node.
@@ -197,4 +127,306 @@ impl Tree {
             }
         };
     }
+
+    /// Remove all keys in the given range from the subtree under `ptr`, where all keys
+    /// in the subtree have the first `path_bits` bits of `path` as a prefix.
+    ///
+    /// Subtrees which are completely contained in the range are dropped without being
+    /// fetched.
+    fn _remove_range(
+        &mut self,
+        ctx: &Arc<Context>,
+        ptr: NodePtrRef,
+        bit_depth: Depth,
+        path: Key,
+        path_bits: Depth,
+        range: &KeyRange,
+    ) -> Result<(NodePtrRef, bool)> {
+        if ptr.borrow().is_null() {
+            return Ok((ptr, false));
+        }
+        match range.overlap(&path, path_bits) {
+            Overlap::None => return Ok((ptr, false)),
+            Overlap::Full => {
+                self.cache.borrow_mut().remove_node(ptr);
+                return Ok((NodePointer::null_ptr(), true));
+            }
+            Overlap::Partial => {}
+        }
+
+        // Smallest key in the subtree, used to fetch the node in case it is not available.
+        let key = path[..path_bits.to_bytes()].to_vec();
+        let node_ref = self.cache.borrow_mut().deref_node_ptr(
+            ctx,
+            ptr.clone(),
+            Some(FetcherSyncGet::new(&key, true)),
+        )?;
+
+        match classify_noderef!(?node_ref) {
+            NodeKind::None => Ok((NodePointer::null_ptr(), false)),
+            NodeKind::Internal => {
+                let node_ref = node_ref.unwrap();
+                let (bit_length, new_path, leaf_node, left, right) = {
+                    let node = node_ref.borrow();
+                    let n = match *node {
+                        NodeBox::Internal(ref n) => n,
+                        _ => unreachable!("node kind is Internal"),
+                    };
+                    let bit_length = bit_depth + n.label_bit_length;
+                    let new_path = path.merge(bit_depth, &n.label, n.label_bit_length);
+                    (
+                        bit_length,
+                        new_path,
+                        n.leaf_node.clone(),
+                        n.left.clone(),
+                        n.right.clone(),
+                    )
+                };
+
+                // The node's label may further restrict the keys in this subtree.
+                match range.overlap(&new_path, bit_length) {
+                    Overlap::None => return Ok((ptr, false)),
+                    Overlap::Full => {
+                        self.cache.borrow_mut().remove_node(ptr);
+                        return Ok((NodePointer::null_ptr(), true));
+                    }
+                    Overlap::Partial => {}
+                }
+
+                let (new_leaf_node, leaf_changed) = self._remove_range(
+                    ctx,
+                    leaf_node,
+                    bit_length,
+                    new_path.clone(),
+                    bit_length,
+                    range,
+                )?;
+                let (new_left, left_changed) = self._remove_range(
+                    ctx,
+                    left,
+                    bit_length,
+                    new_path.append_bit(bit_length, false),
+                    bit_length + 1,
+                    range,
+                )?;
+                let (new_right, right_changed) = self._remove_range(
+                    ctx,
+                    right,
+                    bit_length,
+                    new_path.append_bit(bit_length, true),
+                    bit_length + 1,
+                    range,
+                )?;
+
+                if let NodeBox::Internal(ref mut n) = *node_ref.borrow_mut() {
+                    n.leaf_node = new_leaf_node;
+                    n.left = new_left;
+                    n.right = new_right;
+                }
+
+                let changed = leaf_changed || left_changed || right_changed;
+                let key = new_path[..bit_length.to_bytes()].to_vec();
+                self._collapse(ctx, ptr, node_ref, &key, changed)
+            }
+            NodeKind::Leaf => {
+                let node_ref = node_ref.unwrap();
+                if range.contains(&noderef_as!(node_ref, Leaf).key) {
+                    self.cache.borrow_mut().remove_node(ptr);
+                    return Ok((NodePointer::null_ptr(), true));
+                }
+
+                Ok((ptr, false))
+            }
+        }
+    }
+
+    /// Collapse the given internal node if exactly one child (including the leaf node)
+    /// remains after removal. Otherwise, mark the node as dirty in case it was changed.
+    ///
+    /// The `key` is used to fetch any remaining children from the remote read syncer.
+    fn _collapse(
+        &mut self,
+        ctx: &Arc<Context>,
+        ptr: NodePtrRef,
+        node_ref: NodeRef,
+        key: &Key,
+        changed: bool,
+    ) -> Result<(NodePtrRef, bool)> {
+        // Fetch and check the remaining children.
+        // NOTE: The leaf node is always included with the internal node.
+        let (leaf_node, left, right) = match *node_ref.borrow() {
+            NodeBox::Internal(ref n) => (n.leaf_node.clone(), n.left.clone(), n.right.clone()),
+            _ => unreachable!("node kind is Internal"),
+        };
+        let remaining_leaf = leaf_node.borrow().node.clone();
+        let remaining_left = self.cache.borrow_mut().deref_node_ptr(
+            ctx,
+            left,
+            Some(FetcherSyncGet::new(key, true)),
+        )?;
+        let remaining_right = self.cache.borrow_mut().deref_node_ptr(
+            ctx,
+            right,
+            Some(FetcherSyncGet::new(key, true)),
+        )?;
+
+        // If no children remain, remove the node.
+        if remaining_leaf.is_none() && remaining_left.is_none() && remaining_right.is_none() {
+            self.cache.borrow_mut().remove_node(ptr.clone());
+            return Ok((NodePointer::null_ptr(), true));
+        }
+
+        // If exactly one child including LeafNode remains, collapse it.
+        match remaining_leaf {
+            Some(_) => match remaining_left {
+                Some(_) => (),
+                None => match remaining_right {
+                    None => {
+                        let nd_leaf = noderef_as!(node_ref, Internal).leaf_node.clone();
+                        noderef_as_mut!(node_ref, Internal).leaf_node = NodePointer::null_ptr();
+                        self.cache.borrow_mut().remove_node(ptr.clone());
+                        return Ok((nd_leaf, true));
+                    }
+                    Some(_) => (),
+                },
+            },
+            None => {
+                let mut nd_child: Option<NodeRef> = None;
+                let mut node_ptr: NodePtrRef = NodePointer::null_ptr();
+                let mut both_children = true;
+                match remaining_left {
+                    Some(_) => match remaining_right {
+                        None => {
+                            node_ptr = noderef_as!(node_ref, Internal).left.clone();
+                            noderef_as_mut!(node_ref, Internal).left = NodePointer::null_ptr();
+                            nd_child = remaining_left;
+                            both_children = false;
+                        }
+                        Some(_) => (),
+                    },
+                    None => match remaining_right {
+                        None => (),
+                        Some(_) => {
+                            node_ptr = noderef_as!(node_ref, Internal).right.clone();
+                            noderef_as_mut!(node_ref, Internal).right = NodePointer::null_ptr();
+                            nd_child = remaining_right;
+                            both_children = false;
+                        }
+                    },
+                }
+
+                if !both_children {
+                    // If child is an internal node, also fix the label.
+                    match nd_child {
+                        Some(_) => match classify_noderef!(?nd_child) {
+                            NodeKind::Internal => {
+                                if let NodeBox::Internal(ref mut inode) =
+                                    *nd_child.unwrap().borrow_mut()
+                                {
+                                    inode.label = noderef_as!(node_ref, Internal).label.merge(
+                                        noderef_as!(node_ref, Internal).label_bit_length,
+                                        &inode.label,
+                                        inode.label_bit_length,
+                                    );
+                                    inode.label_bit_length +=
+                                        noderef_as!(node_ref, Internal).label_bit_length;
+                                    inode.clean = false;
+                                    node_ptr.borrow_mut().clean = false;
+                                }
+                            }
+                            _ => (),
+                        },
+                        _ => (),
+                    }
+
+                    self.cache.borrow_mut().remove_node(ptr.clone());
+                    return Ok((node_ptr, true));
+                }
+            }
+        };
+
+        // Two or more children including leaf_node remain, just mark dirty bit.
+        if changed {
+            noderef_as_mut!(node_ref, Internal).clean = false;
+            ptr.borrow_mut().clean = false;
+            // No longer eligible for eviction as it is dirty.
+            self.cache
+                .borrow_mut()
+                .rollback_node(ptr.clone(), NodeKind::Internal);
+        }
+
+        Ok((ptr.clone(), changed))
+    }
+}
+
+/// Overlap between a key range and the set of keys sharing a given prefix.
+#[derive(Debug, PartialEq)]
+enum Overlap {
+    None,
+    Partial,
+    Full,
+}
+
+/// A range of keys [`start`, `end`), where a missing end means that the range
+/// is unbounded.
+struct KeyRange {
+    start: Key,
+    end: Option<Key>,
+}
+
+impl KeyRange {
+    /// Create a range containing all keys with the given prefix.
+    fn prefix(prefix: &[u8]) -> Self {
+        // The end of the range is the smallest key that is larger than all keys
+        // with the given prefix (if any).
+        let mut end = prefix.to_vec();
+        while let Some(last) = end.pop() {
+            if last < 0xff {
+                end.push(last + 1);
+                return Self {
+                    start: prefix.to_vec(),
+                    end: Some(end),
+                };
+            }
+        }
+
+        Self {
+            start: prefix.to_vec(),
+            end: None,
+        }
+    }
+
+    /// Check whether the range contains the given key.
+    fn contains(&self, key: &Key) -> bool {
+        *key >= self.start && self.end.as_ref().map_or(true, |end| key < end)
+    }
+
+    /// Determine how the range overlaps the set of keys having the first
+    /// `path_bits` bits of `path` as a prefix.
+    fn overlap(&self, path: &Key, path_bits: Depth) -> Overlap {
+        // Smallest key with the given prefix.
+        let min_key = &path[..path_bits.to_bytes()];
+
+        if self.end.as_ref().map_or(false, |end| &end[..] <= min_key)
+            || Self::above_prefix(&self.start, path, path_bits)
+        {
+            return Overlap::None;
+        }
+        if &self.start[..] <= min_key
+            && self
+                .end
+                .as_ref()
+                .map_or(true, |end| Self::above_prefix(end, path, path_bits))
+        {
+            return Overlap::Full;
+        }
+        Overlap::Partial
+    }
+
+    /// Check whether the given key is larger than all keys having the first
+    /// `path_bits` bits of `path` as a prefix.
+    fn above_prefix(key: &Key, path: &Key, path_bits: Depth) -> bool {
+        let prefix_len = key.common_prefix_len(key.bit_length(), path, path_bits);
+        prefix_len < path_bits && prefix_len < key.bit_length() && key.get_bit(prefix_len)
+    }
 }
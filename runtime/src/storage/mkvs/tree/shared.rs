@@ -0,0 +1,288 @@
+use std::{
+    any::Any,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use io_context::Context;
+
+use crate::storage::mkvs::{cache::NodeCache, sync::*, tree::*};
+
+/// Default maximum number of nodes kept in the shared node cache.
+const DEFAULT_NODE_CAPACITY: usize = 100_000;
+/// Default maximum total size, in bytes, of values kept in the shared node cache.
+const DEFAULT_VALUE_CAPACITY: usize = 10_000_000;
+
+/// A read syncer used by all views of a shared tree.
+///
+/// Requests are forwarded to the shared underlying read syncer, which is the
+/// only point where readers contend.
+struct SharedTreeReadSyncer {
+    root: Root,
+    read_syncer: Arc<Mutex<Box<dyn ReadSync + Send>>>,
+}
+
+impl SharedTreeReadSyncer {
+    fn sync<F>(&self, ctx: Context, tree: &TreeID, fetch: F) -> Result<ProofResponse>
+    where
+        F: FnOnce(Context, &mut Box<dyn ReadSync + Send>) -> Result<ProofResponse>,
+    {
+        if tree.root != self.root {
+            return Err(SyncerError::InvalidRoot.into());
+        }
+
+        let mut read_syncer = self.read_syncer.lock().unwrap();
+        fetch(ctx, &mut read_syncer)
+    }
+}
+
+impl ReadSync for SharedTreeReadSyncer {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        let tree = request.tree.clone();
+        self.sync(ctx, &tree, |ctx, rs| rs.sync_get(ctx, request))
+    }
+
+    fn sync_get_many(&mut self, ctx: Context, request: GetManyRequest) -> Result<ProofResponse> {
+        let tree = request.tree.clone();
+        self.sync(ctx, &tree, |ctx, rs| rs.sync_get_many(ctx, request))
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        let tree = request.tree.clone();
+        self.sync(ctx, &tree, |ctx, rs| rs.sync_get_prefixes(ctx, request))
+    }
+
+    fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        let tree = request.tree.clone();
+        self.sync(ctx, &tree, |ctx, rs| rs.sync_iterate(ctx, request))
+    }
+}
+
+/// A thread-safe handle to a committed tree root.
+///
+/// Multiple threads can read the same root at once, each using its own `Tree`
+/// obtained via `tree`. All trees share a node cache, so nodes fetched by one
+/// reader are available to all others and only nodes missing from the node
+/// cache are fetched from the underlying read syncer.
+#[derive(Clone)]
+pub struct SharedTree {
+    root: Root,
+    node_cache: NodeCache,
+    read_syncer: Arc<Mutex<Box<dyn ReadSync + Send>>>,
+}
+
+impl SharedTree {
+    /// Create a new shared tree for the given committed root.
+    pub fn new(read_syncer: Box<dyn ReadSync + Send>, root: Root) -> Self {
+        Self::with_capacity(
+            read_syncer,
+            root,
+            DEFAULT_NODE_CAPACITY,
+            DEFAULT_VALUE_CAPACITY,
+        )
+    }
+
+    /// Create a new shared tree for the given committed root, keeping at most
+    /// the given number of nodes and total size of values in the shared node
+    /// cache.
+    pub fn with_capacity(
+        read_syncer: Box<dyn ReadSync + Send>,
+        root: Root,
+        node_capacity: usize,
+        value_capacity: usize,
+    ) -> Self {
+        Self::with_node_cache(
+            read_syncer,
+            root,
+            NodeCache::new(node_capacity, value_capacity),
+        )
+    }
+
+    /// Create a new shared tree for the given committed root, using the given
+    /// node cache which may also be shared with other trees.
+    pub fn with_node_cache(
+        read_syncer: Box<dyn ReadSync + Send>,
+        root: Root,
+        node_cache: NodeCache,
+    ) -> Self {
+        Self {
+            root,
+            node_cache,
+            read_syncer: Arc::new(Mutex::new(read_syncer)),
+        }
+    }
+
+    /// Return the root of the shared tree.
+    pub fn root(&self) -> Root {
+        self.root
+    }
+
+    /// Return the number of nodes in the shared node cache.
+    pub fn cached_nodes(&self) -> usize {
+        self.node_cache.len()
+    }
+
+    /// Create a view of the tree for use on the current thread.
+    ///
+    /// The returned tree consults the shared node cache before fetching nodes
+    /// from the underlying read syncer. Any modifications to it are local and
+    /// cannot be shared.
+    pub fn tree(&self) -> Tree {
+        Tree::make()
+            .with_root(self.root)
+            .with_node_cache(self.node_cache.clone())
+            .new(Box::new(SharedTreeReadSyncer {
+                root: self.root,
+                read_syncer: self.read_syncer.clone(),
+            }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    use crate::{
+        common::namespace::Namespace,
+        storage::mkvs::{sync::NoopReadSyncer, Iterator},
+    };
+
+    use super::*;
+
+    const NUM_KEYS: usize = 500;
+    const NUM_THREADS: usize = 4;
+
+    /// Read syncer counting the number of requests forwarded to a tree.
+    struct CountingReadSyncer {
+        tree: Tree,
+        requests: Arc<AtomicUsize>,
+    }
+
+    impl ReadSync for CountingReadSyncer {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            self.tree.sync_get(ctx, request)
+        }
+
+        fn sync_get_many(
+            &mut self,
+            ctx: Context,
+            request: GetManyRequest,
+        ) -> Result<ProofResponse> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            self.tree.sync_get_many(ctx, request)
+        }
+
+        fn sync_get_prefixes(
+            &mut self,
+            ctx: Context,
+            request: GetPrefixesRequest,
+        ) -> Result<ProofResponse> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            self.tree.sync_get_prefixes(ctx, request)
+        }
+
+        fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            self.tree.sync_iterate(ctx, request)
+        }
+    }
+
+    fn read_all(shared: &SharedTree) {
+        let tree = shared.tree();
+        for i in 0..NUM_KEYS {
+            let key = format!("key {}", i);
+            let value = format!("value {}", i);
+            assert_eq!(
+                tree.get(Context::background(), key.as_bytes())
+                    .expect("get"),
+                Some(value.into_bytes())
+            );
+        }
+
+        let mut it = tree.iter(Context::background());
+        it.rewind();
+        let mut count = 0;
+        while it.is_valid() {
+            count += 1;
+            Iterator::next(&mut it);
+        }
+        assert!(it.error().is_none());
+        assert_eq!(count, NUM_KEYS);
+    }
+
+    #[test]
+    fn test_shared_tree() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..NUM_KEYS {
+            let key = format!("key {}", i);
+            let value = format!("value {}", i);
+            tree.insert(Context::background(), key.as_bytes(), value.as_bytes())
+                .expect("insert");
+        }
+        let hash = tree
+            .commit(Context::background(), Namespace::default(), 1)
+            .expect("commit");
+        let root = Root {
+            namespace: Namespace::default(),
+            version: 1,
+            root_type: RootType::State,
+            hash,
+        };
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let shared = SharedTree::new(
+            Box::new(CountingReadSyncer {
+                tree,
+                requests: requests.clone(),
+            }),
+            root,
+        );
+        assert_eq!(shared.root(), root);
+
+        let threads: Vec<_> = (0..NUM_THREADS)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || read_all(&shared))
+            })
+            .collect();
+        for t in threads {
+            t.join().expect("reader thread should not panic");
+        }
+        assert!(requests.load(Ordering::SeqCst) > 0);
+        assert!(shared.cached_nodes() > 0);
+
+        // All nodes are now cached, so further reads should not hit the remote.
+        let remote_requests = requests.load(Ordering::SeqCst);
+        read_all(&shared);
+        assert_eq!(requests.load(Ordering::SeqCst), remote_requests);
+
+        // Requests for other roots must be rejected.
+        let mut other_root = root;
+        other_root.version = 2;
+        let other = Tree::make()
+            .with_root(other_root)
+            .new(Box::new(SharedTreeReadSyncer {
+                root,
+                read_syncer: shared.read_syncer.clone(),
+            }));
+        assert!(other.get(Context::background(), b"key 0").is_err());
+    }
+}
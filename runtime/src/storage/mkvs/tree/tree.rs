@@ -1,19 +1,26 @@
-use std::{cell::RefCell, fmt, rc::Rc};
+use std::{any::Any, cell::RefCell, fmt, rc::Rc, sync::Arc};
 
 use anyhow::Result;
 use io_context::Context;
 
 use crate::{
     common::{crypto::hash::Hash, namespace::Namespace},
-    storage::mkvs::{self, cache::*, sync::*, tree::*},
+    storage::mkvs::{self, cache::*, db::NodeDB, sync::*, tree::*},
 };
 
 /// A container for the parameters used to construct a new MKVS tree instance.
 pub struct Options {
     node_capacity: usize,
     value_capacity: usize,
+    negative_capacity: usize,
+    eviction_policy: Option<Box<dyn Fn(usize) -> Box<dyn EvictionPolicy>>>,
     root: Option<Root>,
     root_type: Option<RootType>,
+    node_db: Option<Arc<dyn NodeDB>>,
+    node_cache: Option<NodeCache>,
+    async_read_syncer: Option<Arc<dyn AsyncReadSync>>,
+    max_key_size: Option<usize>,
+    max_value_size: Option<usize>,
 }
 
 impl Options {
@@ -32,6 +39,29 @@ impl Options {
         self
     }
 
+    /// Set the eviction policy of the underlying in-memory cache.
+    ///
+    /// The given function is called with the node and value capacities to
+    /// construct the policies for internal and leaf nodes respectively. If left
+    /// unspecified, nodes are evicted using an `LRUPolicy`.
+    pub fn with_eviction_policy<F>(mut self, new_policy: F) -> Self
+    where
+        F: Fn(usize) -> Box<dyn EvictionPolicy> + 'static,
+    {
+        self.eviction_policy = Some(Box::new(new_policy));
+        self
+    }
+
+    /// Set the capacity of the cache of keys known to be absent from the tree.
+    ///
+    /// Lookups of keys in this cache return immediately without traversing the
+    /// tree or invoking the read syncer. If set to 0, absent keys are not cached.
+    /// If left unspecified, the cache will default to 10_000 keys.
+    pub fn with_negative_cache_capacity(mut self, negative_capacity: usize) -> Self {
+        self.negative_capacity = negative_capacity;
+        self
+    }
+
     /// Set an existing root as the root for the new tree.
     ///
     /// Either this or a root type must be specified to construct a new
@@ -52,6 +82,52 @@ impl Options {
         self
     }
 
+    /// Set the node database that committed nodes are persisted into.
+    ///
+    /// Roots committed to the node database can later be opened by trees
+    /// using a `NodeDBReadSyncer` as their read syncer.
+    pub fn with_node_db(mut self, node_db: Arc<dyn NodeDB>) -> Self {
+        self.node_db = Some(node_db);
+        self
+    }
+
+    /// Set a shared node cache to use in addition to the tree's own cache.
+    ///
+    /// Nodes are looked up in the node cache before being fetched from the
+    /// read syncer, so trees at different roots sharing a node cache only
+    /// need to fetch the nodes that differ between them.
+    pub fn with_node_cache(mut self, node_cache: NodeCache) -> Self {
+        self.node_cache = Some(node_cache);
+        self
+    }
+
+    /// Set the read syncer used by asynchronous operations like `get_async`.
+    ///
+    /// Asynchronous operations perform all fetches using this read syncer
+    /// instead of the one the tree is constructed with.
+    pub fn with_async_read_syncer(mut self, read_syncer: Arc<dyn AsyncReadSync>) -> Self {
+        self.async_read_syncer = Some(read_syncer);
+        self
+    }
+
+    /// Set the maximum size of keys, in bytes.
+    ///
+    /// Inserting larger keys fails with `TreeError::KeyTooLarge`. If left
+    /// unspecified, key sizes are not limited.
+    pub fn with_max_key_size(mut self, max_key_size: usize) -> Self {
+        self.max_key_size = Some(max_key_size);
+        self
+    }
+
+    /// Set the maximum size of values, in bytes.
+    ///
+    /// Inserting larger values fails with `TreeError::ValueTooLarge`. If left
+    /// unspecified, value sizes are not limited.
+    pub fn with_max_value_size(mut self, max_value_size: usize) -> Self {
+        self.max_value_size = Some(max_value_size);
+        self
+    }
+
     /// Commit the options set so far into a newly constructed tree instance.
     pub fn new(self, read_syncer: Box<dyn ReadSync>) -> Tree {
         if self.root_type.is_none() && self.root.is_none() {
@@ -72,6 +148,10 @@ impl Options {
 pub struct Tree {
     pub(crate) cache: RefCell<Box<LRUCache>>,
     pub(crate) root_type: RootType,
+    pub(crate) node_db: Option<Arc<dyn NodeDB>>,
+    pub(crate) async_read_syncer: Option<Arc<dyn AsyncReadSync>>,
+    pub(crate) max_key_size: Option<usize>,
+    pub(crate) max_value_size: Option<usize>,
 }
 
 // Tree is Send as long as ownership of internal Rcs cannot leak out via any of its methods.
@@ -85,14 +165,26 @@ impl Tree {
         } else {
             opts.root.unwrap().root_type
         };
+        let new_policy = |capacity| -> Box<dyn EvictionPolicy> {
+            match opts.eviction_policy {
+                Some(ref new_policy) => new_policy(capacity),
+                None => Box::new(LRUPolicy::new(capacity)),
+            }
+        };
         let tree = Tree {
             cache: RefCell::new(LRUCache::new(
-                opts.node_capacity,
-                opts.value_capacity,
+                new_policy(opts.node_capacity),
+                new_policy(opts.value_capacity),
+                opts.negative_capacity,
                 read_syncer,
+                opts.node_cache.clone(),
                 root_type,
             )),
             root_type: root_type,
+            node_db: opts.node_db.clone(),
+            async_read_syncer: opts.async_read_syncer.clone(),
+            max_key_size: opts.max_key_size,
+            max_value_size: opts.max_value_size,
         };
 
         if let Some(root) = opts.root {
@@ -114,10 +206,64 @@ impl Tree {
         Options {
             node_capacity: 50_000,
             value_capacity: 16 * 1024 * 1024,
+            negative_capacity: 10_000,
+            eviction_policy: None,
             root: None,
             root_type: None,
+            node_db: None,
+            node_cache: None,
+            async_read_syncer: None,
+            max_key_size: None,
+            max_value_size: None,
         }
     }
+
+    /// Check that the given key and value do not exceed the configured size
+    /// limits.
+    pub fn check_limits(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        if let Some(max) = self.max_key_size {
+            if key.len() > max {
+                return Err(TreeError::KeyTooLarge {
+                    size: key.len(),
+                    max,
+                }
+                .into());
+            }
+        }
+        if let Some(max) = self.max_value_size {
+            if value.len() > max {
+                return Err(TreeError::ValueTooLarge {
+                    size: value.len(),
+                    max,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure that sync requests for the given root can be served from
+    /// this tree.
+    pub(super) fn check_sync_root(&self, root: &Root) -> Result<()> {
+        let cache = self.cache.borrow();
+        if *root != cache.get_sync_root() {
+            return Err(SyncerError::InvalidRoot.into());
+        }
+        if !cache.get_pending_root().borrow().clean {
+            return Err(SyncerError::DirtyRoot.into());
+        }
+        Ok(())
+    }
+
+    /// Whether the tree is backed by a remote read syncer.
+    pub(super) fn has_remote_read_syncer(&self) -> bool {
+        !self
+            .cache
+            .borrow()
+            .get_read_syncer()
+            .as_any()
+            .is::<NoopReadSyncer>()
+    }
 }
 
 impl fmt::Debug for Tree {
@@ -139,10 +285,22 @@ impl mkvs::FallibleMKVS for Tree {
         Tree::insert(self, ctx, key, value)
     }
 
+    fn check_limits(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        Tree::check_limits(self, key, value)
+    }
+
     fn remove(&mut self, ctx: Context, key: &[u8]) -> Result<Option<Vec<u8>>> {
         Tree::remove(self, ctx, key)
     }
 
+    fn remove_prefix(&mut self, ctx: Context, prefix: &[u8]) -> Result<()> {
+        Tree::remove_prefix(self, ctx, prefix)
+    }
+
+    fn remove_range(&mut self, ctx: Context, start: &[u8], end: &[u8]) -> Result<()> {
+        Tree::remove_range(self, ctx, start, end)
+    }
+
     fn prefetch_prefixes(
         &self,
         ctx: Context,
@@ -152,11 +310,45 @@ impl mkvs::FallibleMKVS for Tree {
         Tree::prefetch_prefixes(self, ctx, prefixes, limit)
     }
 
+    fn prefetch_keys(&self, ctx: Context, keys: &[Vec<u8>]) -> Result<()> {
+        Tree::prefetch_keys(self, ctx, keys)
+    }
+
     fn iter(&self, ctx: Context) -> Box<dyn mkvs::Iterator + '_> {
         Box::new(Tree::iter(self, ctx))
     }
 
+    fn iter_prefix(&self, ctx: Context, prefix: &[u8]) -> Box<dyn mkvs::Iterator + '_> {
+        Box::new(Tree::iter_prefix(self, ctx, prefix))
+    }
+
     fn commit(&mut self, ctx: Context, namespace: Namespace, version: u64) -> Result<Hash> {
         Tree::commit(self, ctx, namespace, version)
     }
 }
+
+impl ReadSync for Tree {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn sync_get(&mut self, ctx: Context, request: GetRequest) -> Result<ProofResponse> {
+        Tree::sync_get(self, ctx, request)
+    }
+
+    fn sync_get_many(&mut self, ctx: Context, request: GetManyRequest) -> Result<ProofResponse> {
+        Tree::sync_get_many(self, ctx, request)
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> Result<ProofResponse> {
+        Tree::sync_get_prefixes(self, ctx, request)
+    }
+
+    fn sync_iterate(&mut self, ctx: Context, request: IterateRequest) -> Result<ProofResponse> {
+        Tree::sync_iterate(self, ctx, request)
+    }
+}
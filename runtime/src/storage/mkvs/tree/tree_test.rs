@@ -668,6 +668,110 @@ fn test_remove() {
     assert_eq!(hash, Hash::empty_hash());
 }
 
+#[test]
+fn test_remove_prefix_range() {
+    let mut items: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    for prefix in &["a", "ab", "b", "c"] {
+        let (keys, values) = generate_key_value_pairs_ex(prefix.to_string(), 100);
+        items.extend(keys.into_iter().zip(values.into_iter()));
+    }
+
+    let build_tree = |items: &Vec<(Vec<u8>, Vec<u8>)>| -> (Tree, Hash) {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for (key, value) in items {
+            tree.insert(Context::background(), key, value)
+                .expect("insert");
+        }
+        let hash =
+            Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+        (tree, hash)
+    };
+    let (_, root) = build_tree(&items);
+
+    let tests: Vec<(&[u8], Option<&[u8]>)> = vec![
+        (b"a", None),
+        (b"abkey 1", None),
+        (b"b", None),
+        (b"", None),
+        (b"zz", None),
+        (b"akey 3", Some(b"bkey 5")),
+        (b"abkey 99", Some(b"c")),
+        (b"", Some(b"b")),
+        (b"c", Some(b"a")),
+    ];
+    for (start, end) in tests {
+        let remaining: Vec<(Vec<u8>, Vec<u8>)> = items
+            .iter()
+            .filter(|(key, _)| match end {
+                None => !key.starts_with(start),
+                Some(end) => key.as_slice() < start || key.as_slice() >= end,
+            })
+            .cloned()
+            .collect();
+        let (_, expected_root) = build_tree(&remaining);
+        let removed = items.len() - remaining.len();
+
+        // Remove from a local tree.
+        let (mut tree, _) = build_tree(&items);
+        match end {
+            None => tree.remove_prefix(Context::background(), start),
+            Some(end) => tree.remove_range(Context::background(), start, end),
+        }
+        .expect("remove");
+        for (key, value) in &items {
+            let expected = remaining
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|_| value.clone());
+            assert_eq!(tree.get(Context::background(), key).expect("get"), expected);
+        }
+        let hash =
+            Tree::commit(&mut tree, Context::background(), Default::default(), 0).expect("commit");
+        assert_eq!(hash, expected_root, "root after local removal");
+
+        // Remove from a remote tree.
+        let (tree, _) = build_tree(&items);
+        let stats = StatsCollector::new(Box::new(tree));
+        let mut remote_tree = Tree::make()
+            .with_capacity(0, 0)
+            .with_root(Root {
+                root_type: RootType::State,
+                hash: root,
+                ..Default::default()
+            })
+            .new(Box::new(stats));
+        match end {
+            None => remote_tree.remove_prefix(Context::background(), start),
+            Some(end) => remote_tree.remove_range(Context::background(), start, end),
+        }
+        .expect("remove");
+        let hash = Tree::commit(
+            &mut remote_tree,
+            Context::background(),
+            Default::default(),
+            0,
+        )
+        .expect("commit");
+        assert_eq!(hash, expected_root, "root after remote removal");
+
+        // Removed subtrees should not need to be fetched.
+        let cache = remote_tree.cache.borrow();
+        let stats = cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<StatsCollector>()
+            .expect("stats");
+        assert!(
+            removed < 2 || stats.sync_get_count < removed,
+            "sync_get count ({}) should be smaller than number of removed keys ({})",
+            stats.sync_get_count,
+            removed
+        );
+    }
+}
+
 #[test]
 fn test_syncer_basic() {
     let server = ProtocolServer::new();
@@ -951,6 +1055,485 @@ fn test_syncer_prefetch_prefixes() {
     assert_eq!(0, stats.sync_iterate_count, "sync_iterate count");
 }
 
+#[test]
+fn test_syncer_prefetch_keys() {
+    let mut tree = Tree::make()
+        .with_root_type(RootType::State)
+        .new(Box::new(NoopReadSyncer));
+
+    let (keys, values) = generate_key_value_pairs();
+    for i in 0..keys.len() {
+        tree.insert(
+            Context::background(),
+            keys[i].as_slice(),
+            values[i].as_slice(),
+        )
+        .expect("insert");
+    }
+    let hash = tree
+        .commit(Context::background(), Default::default(), 0)
+        .expect("commit");
+
+    let stats = StatsCollector::new(Box::new(tree));
+    let remote_tree = Tree::make()
+        .with_capacity(0, 0)
+        .with_root(Root {
+            root_type: RootType::State,
+            hash,
+            ..Default::default()
+        })
+        .new(Box::new(stats));
+
+    // Prefetch every other key together with some keys which don't exist.
+    let mut prefetch: Vec<Vec<u8>> = keys.iter().step_by(2).cloned().collect();
+    prefetch.push(b"missing key".to_vec());
+    prefetch.push(b"key".to_vec());
+    remote_tree
+        .prefetch_keys(Context::background(), &prefetch)
+        .expect("prefetch_keys");
+
+    for i in (0..keys.len()).step_by(2) {
+        let value = remote_tree
+            .get(Context::background(), keys[i].as_slice())
+            .expect("get")
+            .expect("get_some");
+        assert_eq!(values[i], value.as_slice());
+    }
+    assert_eq!(
+        None,
+        remote_tree
+            .get(Context::background(), b"missing key")
+            .expect("get")
+    );
+
+    // Prefetching keys which are already available should not sync.
+    remote_tree
+        .prefetch_keys(Context::background(), &prefetch)
+        .expect("prefetch_keys");
+
+    let cache = remote_tree.cache.borrow();
+    let stats = cache
+        .get_read_syncer()
+        .as_any()
+        .downcast_ref::<StatsCollector>()
+        .expect("stats");
+    assert_eq!(0, stats.sync_get_count, "sync_get count");
+    assert_eq!(1, stats.sync_get_many_count, "sync_get_many count");
+    assert_eq!(0, stats.sync_get_prefixes_count, "sync_get_prefixes count");
+    assert_eq!(0, stats.sync_iterate_count, "sync_iterate count");
+}
+
+/// Read syncer which does not support batched fetches.
+struct UnbatchedReadSyncer(Box<dyn ReadSync>);
+
+impl ReadSync for UnbatchedReadSyncer {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn sync_get(&mut self, ctx: Context, request: GetRequest) -> anyhow::Result<ProofResponse> {
+        self.0.sync_get(ctx, request)
+    }
+
+    fn sync_get_many(
+        &mut self,
+        _ctx: Context,
+        _request: GetManyRequest,
+    ) -> anyhow::Result<ProofResponse> {
+        Err(SyncerError::Unsupported.into())
+    }
+
+    fn sync_get_prefixes(
+        &mut self,
+        ctx: Context,
+        request: GetPrefixesRequest,
+    ) -> anyhow::Result<ProofResponse> {
+        self.0.sync_get_prefixes(ctx, request)
+    }
+
+    fn sync_iterate(
+        &mut self,
+        ctx: Context,
+        request: IterateRequest,
+    ) -> anyhow::Result<ProofResponse> {
+        self.0.sync_iterate(ctx, request)
+    }
+}
+
+#[test]
+fn test_syncer_prefetch_keys_unbatched() {
+    let mut tree = Tree::make()
+        .with_root_type(RootType::State)
+        .new(Box::new(NoopReadSyncer));
+
+    let (keys, values) = generate_key_value_pairs();
+    for i in 0..keys.len() {
+        tree.insert(
+            Context::background(),
+            keys[i].as_slice(),
+            values[i].as_slice(),
+        )
+        .expect("insert");
+    }
+    let hash = tree
+        .commit(Context::background(), Default::default(), 0)
+        .expect("commit");
+
+    let stats = StatsCollector::new(Box::new(UnbatchedReadSyncer(Box::new(tree))));
+    let remote_tree = Tree::make()
+        .with_capacity(0, 0)
+        .with_root(Root {
+            root_type: RootType::State,
+            hash,
+            ..Default::default()
+        })
+        .new(Box::new(stats));
+
+    // Keys should be fetched one by one in case batched fetches are not supported.
+    let prefetch: Vec<Vec<u8>> = keys.iter().take(10).cloned().collect();
+    remote_tree
+        .prefetch_keys(Context::background(), &prefetch)
+        .expect("prefetch_keys");
+
+    let stats_count = |f: fn(&StatsCollector) -> usize| {
+        let cache = remote_tree.cache.borrow();
+        f(cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<StatsCollector>()
+            .expect("stats"))
+    };
+    let sync_get_count = stats_count(|stats| stats.sync_get_count);
+    assert!(sync_get_count > 0, "sync_get count");
+    assert_eq!(1, stats_count(|stats| stats.sync_get_many_count));
+
+    for i in 0..10 {
+        let value = remote_tree
+            .get(Context::background(), keys[i].as_slice())
+            .expect("get")
+            .expect("get_some");
+        assert_eq!(values[i], value.as_slice());
+    }
+    assert_eq!(sync_get_count, stats_count(|stats| stats.sync_get_count));
+}
+
+#[test]
+fn test_syncer_merge_modified() {
+    let (keys, values) = generate_key_value_pairs();
+    let build = |keys: &[Vec<u8>], values: &[Vec<u8>]| {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..keys.len() {
+            tree.insert(
+                Context::background(),
+                keys[i].as_slice(),
+                values[i].as_slice(),
+            )
+            .expect("insert");
+        }
+        let hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+        (tree, hash)
+    };
+    let (tree, hash) = build(&keys, &values);
+
+    let mut remote_tree = Tree::make()
+        .with_capacity(0, 0)
+        .with_root(Root {
+            root_type: RootType::State,
+            hash,
+            ..Default::default()
+        })
+        .new(Box::new(StatsCollector::new(Box::new(tree))));
+
+    // Modify the tree structure before fetching the rest of the tree.
+    let new_key = b"key 1a".to_vec();
+    remote_tree
+        .insert(Context::background(), &new_key, b"new value")
+        .expect("insert");
+    remote_tree
+        .remove(Context::background(), &keys[1])
+        .expect("remove");
+
+    // Fetch all keys, merging them into the modified tree.
+    remote_tree
+        .prefetch_prefixes(Context::background(), &vec![b"key".to_vec().into()], 1000)
+        .expect("prefetch_prefixes");
+
+    let sync_get_count = {
+        let cache = remote_tree.cache.borrow();
+        let stats = cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<StatsCollector>()
+            .expect("stats");
+        assert_eq!(1, stats.sync_get_prefixes_count, "sync_get_prefixes count");
+        stats.sync_get_count
+    };
+
+    for i in 0..keys.len() {
+        let value = remote_tree
+            .get(Context::background(), keys[i].as_slice())
+            .expect("get");
+        if i == 1 {
+            assert_eq!(None, value);
+        } else {
+            assert_eq!(Some(values[i].clone()), value);
+        }
+    }
+    assert_eq!(
+        Some(b"new value".to_vec()),
+        remote_tree
+            .get(Context::background(), &new_key)
+            .expect("get")
+    );
+
+    {
+        let cache = remote_tree.cache.borrow();
+        let stats = cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<StatsCollector>()
+            .expect("stats");
+        assert_eq!(sync_get_count, stats.sync_get_count, "sync_get count");
+    }
+
+    // The resulting tree should be the same as one built from scratch.
+    let mut expected_keys = keys.clone();
+    let mut expected_values = values.clone();
+    expected_keys.remove(1);
+    expected_values.remove(1);
+    expected_keys.push(new_key);
+    expected_values.push(b"new value".to_vec());
+    let (_, expected_hash) = build(&expected_keys, &expected_values);
+
+    let hash = remote_tree
+        .commit(Context::background(), Default::default(), 0)
+        .expect("commit");
+    assert_eq!(expected_hash, hash);
+}
+
+#[test]
+fn test_syncer_node_cache() {
+    let (keys, values) = generate_key_value_pairs();
+    let build = |values: &[Vec<u8>]| {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..keys.len() {
+            tree.insert(
+                Context::background(),
+                keys[i].as_slice(),
+                values[i].as_slice(),
+            )
+            .expect("insert");
+        }
+        let hash = tree
+            .commit(Context::background(), Default::default(), 0)
+            .expect("commit");
+        let root = Root {
+            root_type: RootType::State,
+            hash,
+            ..Default::default()
+        };
+        (tree, root)
+    };
+
+    let mut new_values = values.clone();
+    new_values[1] = b"new value".to_vec();
+
+    let node_cache = NodeCache::new(0, 0);
+    let fetch_all = |tree: Tree, root: Root, values: &[Vec<u8>]| -> usize {
+        let remote_tree = Tree::make()
+            .with_node_cache(node_cache.clone())
+            .with_root(root)
+            .new(Box::new(StatsCollector::new(Box::new(tree))));
+        for i in 0..keys.len() {
+            let value = remote_tree
+                .get(Context::background(), keys[i].as_slice())
+                .expect("get");
+            assert_eq!(Some(values[i].clone()), value);
+        }
+
+        let cache = remote_tree.cache.borrow();
+        let stats = cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<StatsCollector>()
+            .expect("stats");
+        stats.sync_get_count
+    };
+
+    // Fetching the first root should populate the node cache.
+    let (tree, root) = build(&values);
+    let first_count = fetch_all(tree, root, &values);
+    assert!(first_count > 0, "first root should be fetched remotely");
+    assert!(!node_cache.is_empty(), "node cache should be populated");
+
+    // Reopening the same root should not require any fetches.
+    let (tree, root) = build(&values);
+    assert_eq!(0, fetch_all(tree, root, &values), "sync_get count");
+
+    // A different root should only need to fetch the nodes that changed.
+    let (tree, root) = build(&new_values);
+    let second_count = fetch_all(tree, root, &new_values);
+    assert!(second_count > 0, "changed nodes should be fetched remotely");
+    assert!(
+        second_count < first_count,
+        "unchanged nodes should be cached"
+    );
+}
+
+#[test]
+fn test_syncer_node_cache_hash_mismatch() {
+    let mut tree = Tree::make()
+        .with_root_type(RootType::State)
+        .new(Box::new(NoopReadSyncer));
+    tree.insert(Context::background(), b"foo", b"bar")
+        .expect("insert");
+    let hash = tree
+        .commit(Context::background(), Default::default(), 0)
+        .expect("commit");
+    let root = Root {
+        root_type: RootType::State,
+        hash,
+        ..Default::default()
+    };
+
+    // Cache a node which claims to be the root but does not match it.
+    let node_cache = NodeCache::new(0, 0);
+    node_cache
+        .insert(&NodeBox::Leaf(LeafNode {
+            clean: true,
+            hash,
+            key: b"foo".to_vec(),
+            value: b"forged".to_vec(),
+            ..Default::default()
+        }))
+        .expect("insert into node cache");
+
+    let remote_tree = Tree::make()
+        .with_node_cache(node_cache)
+        .with_root(root)
+        .new(Box::new(StatsCollector::new(Box::new(tree))));
+    let value = remote_tree.get(Context::background(), b"foo").expect("get");
+    assert_eq!(Some(b"bar".to_vec()), value);
+
+    let cache = remote_tree.cache.borrow();
+    let stats = cache
+        .get_read_syncer()
+        .as_any()
+        .downcast_ref::<StatsCollector>()
+        .expect("stats");
+    assert_eq!(
+        1, stats.sync_get_count,
+        "mismatched node should be refetched"
+    );
+}
+
+#[test]
+fn test_negative_cache() {
+    let (keys, values) = generate_key_value_pairs();
+    let mut tree = Tree::make()
+        .with_root_type(RootType::State)
+        .new(Box::new(NoopReadSyncer));
+    for i in 0..keys.len() {
+        tree.insert(
+            Context::background(),
+            keys[i].as_slice(),
+            values[i].as_slice(),
+        )
+        .expect("insert");
+    }
+    let hash = tree
+        .commit(Context::background(), Default::default(), 0)
+        .expect("commit");
+    let root = Root {
+        root_type: RootType::State,
+        hash,
+        ..Default::default()
+    };
+
+    // Use a small cache so that nodes on the lookup path get evicted.
+    let mut remote_tree = Tree::make()
+        .with_capacity(50, 0)
+        .with_root(root)
+        .new(Box::new(StatsCollector::new(Box::new(tree))));
+    let sync_get_count = |tree: &Tree| -> usize {
+        let cache = tree.cache.borrow();
+        let stats = cache
+            .get_read_syncer()
+            .as_any()
+            .downcast_ref::<StatsCollector>()
+            .expect("stats");
+        stats.sync_get_count
+    };
+
+    // The missing key shares a long prefix with existing keys, so its lookup path
+    // is deep in the tree.
+    let missing = b"key 1000".to_vec();
+    assert_eq!(
+        remote_tree
+            .get(Context::background(), &missing)
+            .expect("get"),
+        None
+    );
+    assert_eq!(1, sync_get_count(&remote_tree), "sync_get count");
+    for i in 0..keys.len() {
+        remote_tree
+            .get(Context::background(), keys[i].as_slice())
+            .expect("get");
+    }
+
+    // Repeated misses should not require any fetches.
+    let count = sync_get_count(&remote_tree);
+    for _ in 0..10 {
+        assert_eq!(
+            remote_tree
+                .get(Context::background(), &missing)
+                .expect("get"),
+            None
+        );
+        assert!(!remote_tree.cache_contains_key(Context::background(), &missing));
+    }
+    assert_eq!(count, sync_get_count(&remote_tree), "sync_get count");
+
+    // Inserting the key should invalidate the negative cache.
+    remote_tree
+        .insert(Context::background(), &missing, b"value")
+        .expect("insert");
+    assert_eq!(
+        remote_tree
+            .get(Context::background(), &missing)
+            .expect("get"),
+        Some(b"value".to_vec())
+    );
+    remote_tree
+        .remove(Context::background(), &missing)
+        .expect("remove");
+    assert_eq!(
+        remote_tree
+            .get(Context::background(), &missing)
+            .expect("get"),
+        None
+    );
+
+    // The same should hold for inserts through an overlay.
+    let mut overlay = OverlayTree::new(&mut remote_tree);
+    overlay
+        .insert(Context::background(), &missing, b"value")
+        .expect("insert");
+    overlay.commit(Context::background()).expect("commit");
+    assert_eq!(
+        remote_tree
+            .get(Context::background(), &missing)
+            .expect("get"),
+        Some(b"value".to_vec())
+    );
+}
+
 #[test]
 fn test_value_eviction() {
     let mut tree = Tree::make()
@@ -1024,6 +1607,84 @@ fn test_node_eviction() {
     );
 }
 
+#[test]
+fn test_size_limits() {
+    let mut tree = Tree::make()
+        .with_root_type(RootType::State)
+        .with_max_key_size(8)
+        .with_max_value_size(16)
+        .new(Box::new(NoopReadSyncer));
+
+    tree.insert(Context::background(), b"key", b"value")
+        .expect("insert");
+    tree.insert(Context::background(), b"12345678", &[0u8; 16])
+        .expect("insert at the limits");
+
+    let err = tree
+        .insert(Context::background(), b"123456789", b"value")
+        .expect_err("insert of a large key should fail");
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(TreeError::KeyTooLarge { size: 9, max: 8 })
+    ));
+    let err = tree
+        .insert(Context::background(), b"key", &[0u8; 17])
+        .expect_err("insert of a large value should fail");
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(TreeError::ValueTooLarge { size: 17, max: 16 })
+    ));
+    assert_eq!(
+        tree.get(Context::background(), b"key").expect("get"),
+        Some(b"value".to_vec())
+    );
+
+    // Limits of the inner tree should be enforced by overlays.
+    let mut overlay = OverlayTree::new(&mut tree);
+    let err = overlay
+        .insert(Context::background(), b"key", &[0u8; 17])
+        .expect_err("insert of a large value should fail");
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(TreeError::ValueTooLarge { .. })
+    ));
+    assert_eq!(
+        overlay.get(Context::background(), b"key").expect("get"),
+        Some(b"value".to_vec())
+    );
+
+    // Limits should also be checkable through the panicking MKVS interface.
+    {
+        let mkvs: &dyn MKVS = &overlay;
+        assert!(mkvs.check_limits(b"key", &[0u8; 16]).is_ok());
+        let err = mkvs
+            .check_limits(b"123456789", b"value")
+            .expect_err("check of a large key should fail");
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(TreeError::KeyTooLarge { .. })
+        ));
+    }
+    drop(overlay);
+
+    // Write logs with large entries should be rejected without modifying the tree.
+    let write_log = vec![
+        LogEntry::new(b"key", b"modified"),
+        LogEntry::new(b"large key", b"value"),
+    ];
+    let err = tree
+        .apply_write_log(Context::background(), &write_log, Default::default(), 0)
+        .expect_err("apply_write_log should fail");
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(TreeError::KeyTooLarge { .. })
+    ));
+    assert_eq!(
+        tree.get(Context::background(), b"key").expect("get"),
+        Some(b"value".to_vec())
+    );
+}
+
 /// Location of the test vectors directory (from Go).
 const TEST_VECTORS_DIR: &'static str = "../go/storage/mkvs/testdata";
 
@@ -5,9 +5,14 @@ use anyhow::Result;
 
 pub mod context;
 pub mod mkvs;
+pub mod typed;
 
 // Re-exports.
-pub use self::{context::StorageContext, mkvs::MKVS};
+pub use self::{
+    context::StorageContext,
+    mkvs::MKVS,
+    typed::{TypedMap, TypedSet, TypedValue},
+};
 
 /// Trivial Key/Value storage.
 pub trait KeyValue: Send + Sync {
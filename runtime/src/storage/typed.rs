@@ -0,0 +1,422 @@
+//! Typed storage primitives layered on top of MKVS.
+//!
+//! Keys are encoded using `KeyFormat` and values are serialized using CBOR.
+//! The underlying MKVS remains available for raw access.
+use std::marker::PhantomData;
+
+use anyhow::{anyhow, Result};
+use io_context::Context;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    common::{cbor, key_format::KeyFormat},
+    storage::mkvs::{self, MKVS},
+};
+
+fn decode_key<K: KeyFormat>(key: &[u8]) -> Result<K> {
+    if key.len() < 1 + K::size() {
+        return Err(anyhow!("typed storage: malformed key"));
+    }
+    K::decode(key).ok_or_else(|| anyhow!("typed storage: unexpected key prefix"))
+}
+
+fn decode_value<V: DeserializeOwned>(value: Option<Vec<u8>>) -> Result<Option<V>> {
+    match value {
+        Some(value) => Ok(Some(cbor::from_slice(&value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Iterator over raw entries with keys starting with a given prefix.
+struct PrefixIter<'a> {
+    it: Box<dyn mkvs::Iterator + 'a>,
+    done: bool,
+}
+
+impl<'a> PrefixIter<'a> {
+    fn new<M: MKVS + ?Sized>(ctx: Context, mkvs: &'a M, prefix: Vec<u8>) -> Self {
+        Self {
+            it: mkvs.iter_prefix(ctx, &prefix),
+            done: false,
+        }
+    }
+}
+
+impl<'a> Iterator for PrefixIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(error) = self.it.error() {
+            self.done = true;
+            return Some(Err(anyhow!("typed storage: {}", error)));
+        }
+        let item = self.it.next();
+        self.done = item.is_none();
+        item.map(Ok)
+    }
+}
+
+/// A map from keys of the given key format to CBOR-serialized values.
+///
+/// All entries are stored under the prefix of the key format.
+pub struct TypedMap<K, V> {
+    _key: PhantomData<K>,
+    _value: PhantomData<V>,
+}
+
+impl<K, V> Default for TypedMap<K, V> {
+    fn default() -> Self {
+        Self {
+            _key: PhantomData,
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<K: KeyFormat, V: Serialize + DeserializeOwned> TypedMap<K, V> {
+    /// Create a new typed map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fetch the value for the given key.
+    pub fn get<M: MKVS + ?Sized>(&self, ctx: Context, mkvs: &M, key: K) -> Result<Option<V>> {
+        decode_value(mkvs.get(ctx, &key.encode()))
+    }
+
+    /// Update the value for the given key, returning the previous value if any.
+    pub fn insert<M: MKVS + ?Sized>(
+        &self,
+        ctx: Context,
+        mkvs: &mut M,
+        key: K,
+        value: &V,
+    ) -> Result<Option<V>> {
+        decode_value(mkvs.insert(ctx, &key.encode(), &cbor::to_vec(value)))
+    }
+
+    /// Remove the given key, returning the previous value if any.
+    pub fn remove<M: MKVS + ?Sized>(
+        &self,
+        ctx: Context,
+        mkvs: &mut M,
+        key: K,
+    ) -> Result<Option<V>> {
+        decode_value(mkvs.remove(ctx, &key.encode()))
+    }
+
+    /// Iterate over all entries in the map in key order.
+    pub fn iter<'a, M: MKVS + ?Sized>(&self, ctx: Context, mkvs: &'a M) -> TypedMapIter<'a, K, V> {
+        TypedMapIter::new(PrefixIter::new(ctx, mkvs, vec![K::prefix()]))
+    }
+
+    /// Iterate over all entries with keys sharing the first `count` atoms of
+    /// the given key.
+    pub fn iter_prefix<'a, M: MKVS + ?Sized>(
+        &self,
+        ctx: Context,
+        mkvs: &'a M,
+        key: K,
+        count: usize,
+    ) -> TypedMapIter<'a, K, V> {
+        TypedMapIter::new(PrefixIter::new(ctx, mkvs, key.encode_partial(count)))
+    }
+}
+
+/// Iterator over entries of a `TypedMap`.
+pub struct TypedMapIter<'a, K, V> {
+    inner: PrefixIter<'a>,
+    _key: PhantomData<K>,
+    _value: PhantomData<V>,
+}
+
+impl<'a, K, V> TypedMapIter<'a, K, V> {
+    fn new(inner: PrefixIter<'a>) -> Self {
+        Self {
+            inner,
+            _key: PhantomData,
+            _value: PhantomData,
+        }
+    }
+}
+
+impl<'a, K: KeyFormat, V: DeserializeOwned> Iterator for TypedMapIter<'a, K, V> {
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(
+            self.inner
+                .next()?
+                .and_then(|(key, value)| Ok((decode_key(&key)?, cbor::from_slice(&value)?))),
+        )
+    }
+}
+
+/// A set of keys of the given key format.
+///
+/// Members are stored under the prefix of the key format with empty values.
+pub struct TypedSet<K> {
+    _key: PhantomData<K>,
+}
+
+impl<K> Default for TypedSet<K> {
+    fn default() -> Self {
+        Self { _key: PhantomData }
+    }
+}
+
+impl<K: KeyFormat> TypedSet<K> {
+    /// Create a new typed set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether the set contains the given key.
+    pub fn contains<M: MKVS + ?Sized>(&self, ctx: Context, mkvs: &M, key: K) -> bool {
+        mkvs.get(ctx, &key.encode()).is_some()
+    }
+
+    /// Add the given key to the set, returning whether it was not present.
+    pub fn insert<M: MKVS + ?Sized>(&self, ctx: Context, mkvs: &mut M, key: K) -> bool {
+        mkvs.insert(ctx, &key.encode(), &[]).is_none()
+    }
+
+    /// Remove the given key from the set, returning whether it was present.
+    pub fn remove<M: MKVS + ?Sized>(&self, ctx: Context, mkvs: &mut M, key: K) -> bool {
+        mkvs.remove(ctx, &key.encode()).is_some()
+    }
+
+    /// Iterate over all members of the set in key order.
+    pub fn iter<'a, M: MKVS + ?Sized>(&self, ctx: Context, mkvs: &'a M) -> TypedSetIter<'a, K> {
+        TypedSetIter::new(PrefixIter::new(ctx, mkvs, vec![K::prefix()]))
+    }
+
+    /// Iterate over all members sharing the first `count` atoms of the given key.
+    pub fn iter_prefix<'a, M: MKVS + ?Sized>(
+        &self,
+        ctx: Context,
+        mkvs: &'a M,
+        key: K,
+        count: usize,
+    ) -> TypedSetIter<'a, K> {
+        TypedSetIter::new(PrefixIter::new(ctx, mkvs, key.encode_partial(count)))
+    }
+}
+
+/// Iterator over members of a `TypedSet`.
+pub struct TypedSetIter<'a, K> {
+    inner: PrefixIter<'a>,
+    _key: PhantomData<K>,
+}
+
+impl<'a, K> TypedSetIter<'a, K> {
+    fn new(inner: PrefixIter<'a>) -> Self {
+        Self {
+            inner,
+            _key: PhantomData,
+        }
+    }
+}
+
+impl<'a, K: KeyFormat> Iterator for TypedSetIter<'a, K> {
+    type Item = Result<K>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.inner.next()?.and_then(|(key, _)| decode_key(&key)))
+    }
+}
+
+/// A single CBOR-serialized value stored under a fixed key.
+pub struct TypedValue<V> {
+    key: Vec<u8>,
+    _value: PhantomData<V>,
+}
+
+impl<V: Serialize + DeserializeOwned> TypedValue<V> {
+    /// Create a new typed value stored under the given key.
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            key: key.to_vec(),
+            _value: PhantomData,
+        }
+    }
+
+    /// Fetch the value.
+    pub fn get<M: MKVS + ?Sized>(&self, ctx: Context, mkvs: &M) -> Result<Option<V>> {
+        decode_value(mkvs.get(ctx, &self.key))
+    }
+
+    /// Update the value, returning the previous value if any.
+    pub fn insert<M: MKVS + ?Sized>(
+        &self,
+        ctx: Context,
+        mkvs: &mut M,
+        value: &V,
+    ) -> Result<Option<V>> {
+        decode_value(mkvs.insert(ctx, &self.key, &cbor::to_vec(value)))
+    }
+
+    /// Remove the value, returning the previous value if any.
+    pub fn remove<M: MKVS + ?Sized>(&self, ctx: Context, mkvs: &mut M) -> Result<Option<V>> {
+        decode_value(mkvs.remove(ctx, &self.key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+
+    use crate::storage::mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct AccountKeyFormat {
+        owner: u8,
+        index: u32,
+    }
+
+    impl KeyFormat for AccountKeyFormat {
+        fn prefix() -> u8 {
+            0x01
+        }
+
+        fn size() -> usize {
+            5
+        }
+
+        fn encode_atoms(self, atoms: &mut Vec<Vec<u8>>) {
+            atoms.push(vec![self.owner]);
+            atoms.push(self.index.to_be_bytes().to_vec());
+        }
+
+        fn decode_atoms(data: &[u8]) -> Self {
+            let mut index = [0u8; 4];
+            index.copy_from_slice(&data[1..5]);
+            Self {
+                owner: data[0],
+                index: u32::from_be_bytes(index),
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        balance: u64,
+    }
+
+    fn account(owner: u8, index: u32) -> AccountKeyFormat {
+        AccountKeyFormat { owner, index }
+    }
+
+    #[test]
+    fn test_typed_storage() {
+        let tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        let mut mkvs = OverlayTree::new(tree);
+        let ctx = || Context::background();
+
+        let accounts: TypedMap<AccountKeyFormat, Account> = TypedMap::new();
+        let set: TypedSet<AccountKeyFormat> = TypedSet::new();
+        let total: TypedValue<u64> = TypedValue::new(b"total");
+
+        // Keys outside the key format prefix should not be visible.
+        mkvs.insert(ctx(), &[0x00, 0xff], b"raw").expect("insert");
+        mkvs.insert(ctx(), &[0x02], b"raw").expect("insert");
+
+        for owner in 0..3 {
+            for index in 0..3 {
+                let prev = accounts
+                    .insert(
+                        ctx(),
+                        &mut mkvs,
+                        account(owner, index),
+                        &Account {
+                            balance: (owner as u64) * 10 + index as u64,
+                        },
+                    )
+                    .unwrap();
+                assert_eq!(prev, None);
+            }
+        }
+        assert_eq!(
+            accounts.get(ctx(), &mkvs, account(1, 2)).unwrap(),
+            Some(Account { balance: 12 })
+        );
+        assert_eq!(accounts.get(ctx(), &mkvs, account(3, 0)).unwrap(), None);
+        assert_eq!(
+            accounts
+                .insert(ctx(), &mut mkvs, account(1, 2), &Account { balance: 100 })
+                .unwrap(),
+            Some(Account { balance: 12 })
+        );
+
+        let all: Vec<_> = accounts.iter(ctx(), &mkvs).collect::<Result<_>>().unwrap();
+        assert_eq!(all.len(), 9);
+        assert_eq!(all[0].0, account(0, 0));
+        assert_eq!(all[8].0, account(2, 2));
+
+        let owned: Vec<_> = accounts
+            .iter_prefix(ctx(), &mkvs, account(1, 0), 1)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            owned,
+            vec![
+                (account(1, 0), Account { balance: 10 }),
+                (account(1, 1), Account { balance: 11 }),
+                (account(1, 2), Account { balance: 100 }),
+            ]
+        );
+
+        assert_eq!(
+            accounts.remove(ctx(), &mut mkvs, account(1, 1)).unwrap(),
+            Some(Account { balance: 11 })
+        );
+        assert_eq!(
+            accounts.remove(ctx(), &mut mkvs, account(1, 1)).unwrap(),
+            None
+        );
+        assert_eq!(accounts.iter(ctx(), &mkvs).count(), 8);
+
+        // Values of an unexpected type should result in errors.
+        mkvs.insert(ctx(), &account(3, 0).encode(), b"garbage")
+            .expect("insert");
+        assert!(accounts.get(ctx(), &mkvs, account(3, 0)).is_err());
+        assert!(accounts.iter(ctx(), &mkvs).last().unwrap().is_err());
+        mkvs.remove(ctx(), &account(3, 0).encode()).expect("remove");
+
+        // Sets.
+        let mut mkvs = OverlayTree::new(
+            Tree::make()
+                .with_root_type(RootType::State)
+                .new(Box::new(NoopReadSyncer)),
+        );
+        assert!(set.insert(ctx(), &mut mkvs, account(0, 1)));
+        assert!(set.insert(ctx(), &mut mkvs, account(0, 2)));
+        assert!(set.insert(ctx(), &mut mkvs, account(1, 1)));
+        assert!(!set.insert(ctx(), &mut mkvs, account(1, 1)));
+        assert!(set.contains(ctx(), &mkvs, account(0, 2)));
+        assert!(!set.contains(ctx(), &mkvs, account(0, 3)));
+        let members: Vec<_> = set
+            .iter_prefix(ctx(), &mkvs, account(0, 0), 1)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(members, vec![account(0, 1), account(0, 2)]);
+        assert!(set.remove(ctx(), &mut mkvs, account(0, 1)));
+        assert!(!set.remove(ctx(), &mut mkvs, account(0, 1)));
+        assert_eq!(set.iter(ctx(), &mkvs).count(), 2);
+
+        // Values.
+        assert_eq!(total.get(ctx(), &mkvs).unwrap(), None);
+        assert_eq!(total.insert(ctx(), &mut mkvs, &42).unwrap(), None);
+        assert_eq!(total.insert(ctx(), &mut mkvs, &43).unwrap(), Some(42));
+        assert_eq!(total.get(ctx(), &mkvs).unwrap(), Some(43));
+        assert_eq!(total.remove(ctx(), &mut mkvs).unwrap(), Some(43));
+        assert_eq!(total.get(ctx(), &mkvs).unwrap(), None);
+    }
+}
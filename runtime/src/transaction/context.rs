@@ -81,4 +81,14 @@ impl<'a> Context<'a> {
         self.messages.push(message);
         self.messages.len() as u32 - 1
     }
+
+    /// Number of messages emitted so far.
+    pub(crate) fn message_count(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Discard all messages emitted after the first `count` messages.
+    pub(crate) fn discard_messages(&mut self, count: usize) {
+        self.messages.truncate(count)
+    }
 }
@@ -19,6 +19,7 @@ use super::{
 use crate::{
     common::{cbor, crypto::hash::Hash},
     consensus::roothash,
+    storage::StorageContext,
     types::{CheckTxResult, Error as RuntimeError},
 };
 
@@ -28,6 +29,11 @@ use crate::{
 /// to process transactions.
 pub trait Dispatcher {
     /// Execute the transactions in the given batch.
+    ///
+    /// The batch is executed within a storage context entered via
+    /// `StorageContext::enter_overlay`. Each transaction should be executed via
+    /// `StorageContext::with_scope` so that its updates are discarded in case
+    /// it fails.
     fn execute_batch(
         &self,
         ctx: Context,
@@ -61,6 +67,17 @@ pub trait Dispatcher {
         // Default implementation returns an error.
         Err(RuntimeError::new("dispatcher", 1, "query not supported"))
     }
+
+    /// Return a handler for processing queries in parallel, if supported.
+    ///
+    /// When a handler is returned, the runtime dispatcher processes queries
+    /// using it on a pool of `QueryHandler::threads` threads instead of
+    /// calling `query`. In case no threads are available, queries are
+    /// processed using the handler on the dispatcher thread.
+    fn query_handler(&self) -> Option<Arc<dyn QueryHandler>> {
+        // Default implementation does not support parallel queries.
+        None
+    }
 }
 
 impl<T: Dispatcher + ?Sized> Dispatcher for Box<T> {
@@ -96,6 +113,33 @@ impl<T: Dispatcher + ?Sized> Dispatcher for Box<T> {
     ) -> Result<cbor::Value, RuntimeError> {
         T::query(&*self, ctx, method, args)
     }
+
+    fn query_handler(&self) -> Option<Arc<dyn QueryHandler>> {
+        T::query_handler(&*self)
+    }
+}
+
+/// Handler for read-only queries which may be invoked from multiple threads
+/// at once.
+///
+/// Each query is processed within its own storage context backed by a view of
+/// the state at the queried block.
+pub trait QueryHandler: Send + Sync {
+    /// Number of threads which should process queries in parallel.
+    ///
+    /// Each thread is an additional thread of the runtime. Runtimes running in
+    /// an SGX enclave must provide a thread control structure (TCS) for each of
+    /// them by increasing the `threads` setting in the
+    /// `[package.metadata.fortanix-sgx]` section of their manifest accordingly.
+    fn threads(&self) -> usize;
+
+    /// Process a query.
+    fn query(
+        &self,
+        ctx: Context,
+        method: &str,
+        args: cbor::Value,
+    ) -> Result<cbor::Value, RuntimeError>;
 }
 
 /// Result of processing an ExecuteTx.
@@ -329,6 +373,8 @@ pub struct MethodDispatcher {
     finalizer: Option<Box<dyn Finalizer>>,
     /// Abort batch flag.
     abort_batch: Option<Arc<AtomicBool>>,
+    /// Registered query handler.
+    query_handler: Option<Arc<dyn QueryHandler>>,
 }
 
 impl MethodDispatcher {
@@ -340,6 +386,7 @@ impl MethodDispatcher {
             ctx_initializer: None,
             finalizer: None,
             abort_batch: None,
+            query_handler: None,
         }
     }
 
@@ -372,6 +419,14 @@ impl MethodDispatcher {
         self.finalizer = Some(Box::new(finalizer));
     }
 
+    /// Configure query handler.
+    pub fn set_query_handler<H>(&mut self, handler: H)
+    where
+        H: QueryHandler + 'static,
+    {
+        self.query_handler = Some(Arc::new(handler));
+    }
+
     /// Dispatches a raw runtime check request.
     fn dispatch_check(&self, call: &Vec<u8>, ctx: &mut Context) -> CheckTxResult {
         match self.dispatch_fallible(call, ctx) {
@@ -397,10 +452,20 @@ impl MethodDispatcher {
     }
 
     /// Dispatches a raw runtime invocation request.
+    ///
+    /// Any storage updates and roothash messages of a failed call are discarded.
+    ///
+    /// Note that storage updates can only be discarded when the storage context was entered
+    /// via `StorageContext::enter_overlay`, as done by the runtime dispatcher when executing
+    /// batches. Otherwise the updates of failed calls are kept.
     fn dispatch_execute(&self, call: &Vec<u8>, ctx: &mut Context) -> ExecuteTxResult {
-        let rsp = match self.dispatch_fallible(call, ctx) {
+        let message_count = ctx.message_count();
+        let rsp = match StorageContext::with_scope(|| self.dispatch_fallible(call, ctx)) {
             Ok(response) => TxnOutput::Success(response),
-            Err(error) => TxnOutput::Error(format!("{:#}", error)),
+            Err(error) => {
+                ctx.discard_messages(message_count);
+                TxnOutput::Error(format!("{:#}", error))
+            }
         };
 
         ExecuteTxResult {
@@ -504,6 +569,22 @@ impl Dispatcher for MethodDispatcher {
     fn set_abort_batch_flag(&mut self, abort_batch: Arc<AtomicBool>) {
         self.abort_batch = Some(abort_batch);
     }
+
+    fn query(
+        &self,
+        ctx: Context,
+        method: &str,
+        args: cbor::Value,
+    ) -> Result<cbor::Value, RuntimeError> {
+        match self.query_handler {
+            Some(ref handler) => handler.query(ctx, method, args),
+            None => Err(RuntimeError::new("dispatcher", 1, "query not supported")),
+        }
+    }
+
+    fn query_handler(&self) -> Option<Arc<dyn QueryHandler>> {
+        self.query_handler.clone()
+    }
 }
 
 #[cfg(test)]
@@ -511,7 +592,16 @@ mod tests {
     use io_context::Context as IoContext;
     use serde::{Deserialize, Serialize};
 
-    use crate::{common::cbor, consensus::roothash::Header};
+    use std::panic::{self, AssertUnwindSafe};
+
+    use crate::{
+        common::cbor,
+        consensus::roothash::{Header, Message, StakingMessage},
+        storage::{
+            mkvs::{sync::NoopReadSyncer, OverlayTree, RootType, Tree},
+            KeyValue,
+        },
+    };
 
     use super::*;
 
@@ -541,6 +631,40 @@ mod tests {
         ));
     }
 
+    /// Register a method which stores a value and emits a message and then fails if requested.
+    fn register_store_method(dispatcher: &mut MethodDispatcher) {
+        dispatcher.add_method(Method::new(
+            MethodDescriptor {
+                name: "store".to_owned(),
+            },
+            |call: &Complex, ctx: &mut Context| -> AnyResult<()> {
+                StorageContext::with_current(|mkvs, _untrusted_local| {
+                    mkvs.insert(IoContext::background(), call.text.as_bytes(), b"value");
+                });
+                ctx.emit_message(Message::Staking {
+                    v: 0,
+                    msg: StakingMessage::Transfer(Default::default()),
+                });
+                if call.number > 0 {
+                    return Err(anyhow::anyhow!("store failed"));
+                }
+                Ok(())
+            },
+        ));
+    }
+
+    struct NoopKeyValue;
+
+    impl KeyValue for NoopKeyValue {
+        fn get(&self, _key: Vec<u8>) -> AnyResult<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn insert(&self, _key: Vec<u8>, _value: Vec<u8>) -> AnyResult<()> {
+            Ok(())
+        }
+    }
+
     #[test]
     fn test_dispatcher() {
         let mut dispatcher = MethodDispatcher::new();
@@ -582,4 +706,92 @@ mod tests {
             _ => panic!("txn call should return success"),
         }
     }
+
+    #[test]
+    fn test_dispatcher_rollback() {
+        let mut dispatcher = MethodDispatcher::new();
+        register_store_method(&mut dispatcher);
+
+        let store = |text: &str, number| {
+            cbor::to_vec(&TxnCall {
+                method: "store".to_owned(),
+                args: cbor::to_value(Complex {
+                    text: text.to_owned(),
+                    number,
+                }),
+            })
+        };
+        let batch = TxnBatch::new(vec![
+            store("first", 0),
+            store("failed", 1),
+            store("last", 0),
+        ]);
+
+        let header = Header::default();
+        let ctx = Context::new(IoContext::background().freeze(), &header, &[], false);
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        let mut overlay = OverlayTree::new(&mut tree);
+        let result = StorageContext::enter_overlay(&mut overlay, Arc::new(NoopKeyValue), || {
+            dispatcher.execute_batch(ctx, &batch)
+        })
+        .expect("batch execution should succeed");
+
+        // Updates and messages of the failed call should be discarded.
+        assert_eq!(result.messages.len(), 2);
+        let outputs: Vec<TxnOutput> = result
+            .results
+            .iter()
+            .map(|result| cbor::from_slice(&result.output).unwrap())
+            .collect();
+        assert!(matches!(outputs[0], TxnOutput::Success(_)));
+        assert!(matches!(outputs[1], TxnOutput::Error(_)));
+        assert!(matches!(outputs[2], TxnOutput::Success(_)));
+        for (key, present) in &[("first", true), ("failed", false), ("last", true)] {
+            assert_eq!(
+                overlay
+                    .get(IoContext::background(), key.as_bytes())
+                    .expect("get")
+                    .is_some(),
+                *present
+            );
+        }
+    }
+
+    #[test]
+    fn test_scope_panic_and_commit() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        let mut overlay = OverlayTree::new(&mut tree);
+        StorageContext::enter_overlay(&mut overlay, Arc::new(NoopKeyValue), || {
+            // Updates made before a panic should be discarded.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                StorageContext::with_scope(|| -> Result<(), ()> {
+                    StorageContext::with_current(|mkvs, _untrusted_local| {
+                        mkvs.insert(IoContext::background(), b"panic", b"value");
+                    });
+                    panic!("scope panicked");
+                })
+            }));
+            assert!(result.is_err());
+
+            // Committing while a scope is open should fail.
+            let result = StorageContext::with_scope(|| {
+                StorageContext::with_current(|mkvs, _untrusted_local| {
+                    mkvs.insert(IoContext::background(), b"commit", b"value");
+                    mkvs.commit(IoContext::background(), Default::default(), 1)
+                })
+            });
+            assert!(result.is_err());
+        });
+
+        for key in &["panic", "commit"] {
+            assert!(overlay
+                .get(IoContext::background(), key.as_bytes())
+                .expect("get")
+                .is_none());
+        }
+    }
 }
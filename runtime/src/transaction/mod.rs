@@ -3,6 +3,7 @@
 pub mod context;
 pub mod dispatcher;
 pub mod macros;
+pub mod query;
 pub mod rwset;
 pub mod tags;
 pub mod tree;
@@ -0,0 +1,300 @@
+//! Parallel query processing.
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crossbeam::channel;
+use io_context::Context as IoContext;
+
+use super::{context::Context, dispatcher::QueryHandler};
+use crate::{
+    common::cbor,
+    consensus::roothash::Header,
+    dispatcher::AbortOnPanic,
+    storage::{
+        mkvs::{FallibleMKVS, OverlayTree, Root, RootType, SharedTree},
+        KeyValue, StorageContext,
+    },
+    types::Error as RuntimeError,
+};
+
+/// Maximum amount of queries that can be waiting for a query thread.
+const BACKLOG_SIZE: usize = 1000;
+
+/// Callback receiving the result of a query processed by the query pool.
+pub type QueryResponder = Box<dyn FnOnce(Result<cbor::Value, RuntimeError>) + Send>;
+
+type QueueItem = (IoContext, Header, String, cbor::Value, QueryResponder);
+
+/// Process a query against the state at the given block header.
+///
+/// The query is processed by `f` within a storage context backed by an overlay
+/// over `mkvs`, so any modifications made while processing it are discarded.
+pub fn process_query<T, F>(
+    ctx: Arc<IoContext>,
+    header: &Header,
+    mkvs: T,
+    untrusted_local: Arc<dyn KeyValue>,
+    f: F,
+) -> Result<cbor::Value, RuntimeError>
+where
+    T: FallibleMKVS,
+    F: FnOnce(Context) -> Result<cbor::Value, RuntimeError>,
+{
+    let txn_ctx = Context::new(ctx, header, &[], true);
+    let mut overlay = OverlayTree::new(mkvs);
+    StorageContext::enter(&mut overlay, untrusted_local, || f(txn_ctx))
+}
+
+/// Pool of threads processing read-only queries in parallel.
+///
+/// All threads read state via a shared tree for the most recently queried
+/// root, so nodes fetched by one query are available to all others.
+pub struct QueryPool {
+    queue_tx: channel::Sender<QueueItem>,
+    threads: usize,
+}
+
+impl QueryPool {
+    /// Start a new query pool with `handler.threads()` threads.
+    ///
+    /// Shared trees for queried roots are created using `new_tree` and each
+    /// query gets the untrusted local storage returned by `untrusted_local`.
+    ///
+    /// Returns `None` in case no threads could be spawned (e.g., because there
+    /// are no thread control structures left in an SGX enclave), in which case
+    /// queries must be processed inline.
+    pub fn start<N, U>(
+        handler: Arc<dyn QueryHandler>,
+        new_tree: N,
+        untrusted_local: U,
+    ) -> Option<Self>
+    where
+        N: Fn(Root) -> SharedTree + Send + Sync + 'static,
+        U: Fn(&Arc<IoContext>) -> Arc<dyn KeyValue> + Send + Sync + 'static,
+    {
+        let (tx, rx) = channel::bounded(BACKLOG_SIZE);
+        let new_tree = Arc::new(new_tree);
+        let untrusted_local = Arc::new(untrusted_local);
+        let shared_tree: Arc<Mutex<Option<SharedTree>>> = Arc::new(Mutex::new(None));
+
+        let mut threads = 0;
+        for _ in 0..handler.threads() {
+            let handler = handler.clone();
+            let new_tree = new_tree.clone();
+            let untrusted_local = untrusted_local.clone();
+            let shared_tree = shared_tree.clone();
+            let rx: channel::Receiver<QueueItem> = rx.clone();
+            let spawned = thread::Builder::new().spawn(move || {
+                let _guard = AbortOnPanic;
+                for (ctx, header, method, args, respond) in rx.iter() {
+                    let tree = Self::shared_tree(&*new_tree, &shared_tree, &header);
+                    let ctx = ctx.freeze();
+                    let untrusted_local = untrusted_local(&ctx);
+                    let result = process_query(ctx, &header, tree.tree(), untrusted_local, |ctx| {
+                        handler.query(ctx, &method, args)
+                    });
+                    respond(result);
+                }
+            });
+            if spawned.is_err() {
+                break;
+            }
+            threads += 1;
+        }
+        if threads == 0 {
+            return None;
+        }
+
+        Some(Self {
+            queue_tx: tx,
+            threads,
+        })
+    }
+
+    /// Number of threads processing queries.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Queue a query to be processed by the pool.
+    ///
+    /// Once processed, the result is passed to `respond` on the query thread.
+    pub fn queue(
+        &self,
+        ctx: IoContext,
+        header: Header,
+        method: String,
+        args: cbor::Value,
+        respond: QueryResponder,
+    ) {
+        // Block in case all query threads are busy and the queue is full.
+        self.queue_tx
+            .send((ctx, header, method, args, respond))
+            .expect("query pool must be running");
+    }
+
+    /// Return the shared tree for the queried root, replacing it if needed.
+    fn shared_tree<N>(
+        new_tree: &N,
+        shared_tree: &Mutex<Option<SharedTree>>,
+        header: &Header,
+    ) -> SharedTree
+    where
+        N: Fn(Root) -> SharedTree,
+    {
+        let root = Root {
+            namespace: header.namespace,
+            version: header.round,
+            root_type: RootType::State,
+            hash: header.state_root,
+        };
+
+        let mut shared_tree = shared_tree.lock().unwrap();
+        match *shared_tree {
+            Some(ref tree) if tree.root() == root => tree.clone(),
+            _ => {
+                let tree = new_tree(root);
+                *shared_tree = Some(tree.clone());
+                tree
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{mpsc, Barrier},
+        time::Duration,
+    };
+
+    use anyhow::Result as AnyResult;
+
+    use crate::{
+        common::namespace::Namespace,
+        storage::mkvs::{sync::NoopReadSyncer, Tree},
+    };
+
+    use super::*;
+
+    const NUM_QUERIES: usize = 10;
+
+    struct NoopKeyValue;
+
+    impl KeyValue for NoopKeyValue {
+        fn get(&self, _key: Vec<u8>) -> AnyResult<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn insert(&self, _key: Vec<u8>, _value: Vec<u8>) -> AnyResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Query handler returning the value of the key named by the method.
+    struct TestHandler {
+        threads: usize,
+        barrier: Barrier,
+    }
+
+    impl QueryHandler for TestHandler {
+        fn threads(&self) -> usize {
+            self.threads
+        }
+
+        fn query(
+            &self,
+            ctx: Context,
+            method: &str,
+            _args: cbor::Value,
+        ) -> Result<cbor::Value, RuntimeError> {
+            // Only proceed once all threads are processing a query at the same time.
+            self.barrier.wait();
+
+            let value = StorageContext::with_current(|mkvs, _untrusted_local| {
+                mkvs.get(IoContext::create_child(&ctx.io_ctx), method.as_bytes())
+            });
+            Ok(cbor::to_value(value))
+        }
+    }
+
+    #[test]
+    fn test_query_pool() {
+        let mut tree = Tree::make()
+            .with_root_type(RootType::State)
+            .new(Box::new(NoopReadSyncer));
+        for i in 0..NUM_QUERIES {
+            let key = format!("key {}", i);
+            let value = format!("value {}", i);
+            tree.insert(IoContext::background(), key.as_bytes(), value.as_bytes())
+                .expect("insert");
+        }
+        let hash = tree
+            .commit(IoContext::background(), Namespace::default(), 1)
+            .expect("commit");
+        let header = Header {
+            round: 1,
+            state_root: hash,
+            ..Default::default()
+        };
+
+        let handler = Arc::new(TestHandler {
+            threads: 2,
+            barrier: Barrier::new(2),
+        });
+        let tree = Arc::new(Mutex::new(Some(tree)));
+        let pool = QueryPool::start(
+            handler,
+            move |root| {
+                let tree = tree
+                    .lock()
+                    .unwrap()
+                    .take()
+                    .expect("tree must only be created once");
+                SharedTree::new(Box::new(tree), root)
+            },
+            |_ctx| Arc::new(NoopKeyValue),
+        )
+        .expect("query pool should start");
+        assert_eq!(pool.threads(), 2);
+
+        let (tx, rx) = mpsc::channel();
+        for i in 0..NUM_QUERIES {
+            let tx = tx.clone();
+            pool.queue(
+                IoContext::background(),
+                header.clone(),
+                format!("key {}", i),
+                cbor::Value::Null,
+                Box::new(move |result| tx.send((i, result)).unwrap()),
+            );
+        }
+
+        for _ in 0..NUM_QUERIES {
+            let (i, result) = rx
+                .recv_timeout(Duration::from_secs(10))
+                .expect("queries should be processed in parallel");
+            let value = format!("value {}", i).into_bytes();
+            assert_eq!(result.expect("query"), cbor::to_value(Some(value)));
+        }
+    }
+
+    #[test]
+    fn test_query_pool_no_threads() {
+        let handler = Arc::new(TestHandler {
+            threads: 0,
+            barrier: Barrier::new(1),
+        });
+        let pool = QueryPool::start(
+            handler,
+            |root| SharedTree::new(Box::new(NoopReadSyncer), root),
+            |_ctx| Arc::new(NoopKeyValue),
+        );
+        assert!(
+            pool.is_none(),
+            "queries should be processed inline without threads"
+        );
+    }
+}
@@ -39,6 +39,7 @@ pub struct ComputedBatch {
 #[derive(Debug, Serialize, Deserialize)]
 pub enum StorageSyncRequest {
     SyncGet(sync::GetRequest),
+    SyncGetMany(sync::GetManyRequest),
     SyncGetPrefixes(sync::GetPrefixesRequest),
     SyncIterate(sync::IterateRequest),
 }
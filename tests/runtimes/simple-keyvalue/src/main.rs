@@ -7,9 +7,10 @@ use io_context::Context as IoContext;
 use oasis_core_keymanager_client::{KeyManagerClient, KeyPairId};
 use oasis_core_runtime::{
     common::{
+        cbor,
         crypto::{
             hash::Hash,
-            mrae::deoxysii::{DeoxysII, KEY_SIZE, NONCE_SIZE, TAG_SIZE},
+            mrae::{deoxysii::NONCE_SIZE, nonce::Nonce},
         },
         key_format::KeyFormat,
         namespace::Namespace,
@@ -22,11 +23,12 @@ use oasis_core_runtime::{
     executor::Executor,
     rak::RAK,
     register_runtime_txn_methods, runtime_context,
-    storage::{StorageContext, MKVS},
+    storage::{mkvs::EncryptedMKVS, StorageContext, MKVS},
     transaction::{
-        dispatcher::{BatchHandler, CheckOnlySuccess},
+        dispatcher::{BatchHandler, CheckOnlySuccess, QueryHandler},
         Context as TxnContext,
     },
+    types::Error as RuntimeError,
     version_from_cargo, Protocol, RpcDemux, RpcDispatcher, TxnDispatcher, TxnMethDispatcher,
 };
 use simple_keymanager::trusted_policy_signers;
@@ -150,13 +152,14 @@ fn insert(args: &KeyValue, ctx: &mut TxnContext) -> Result<Option<String>> {
     ctx.emit_txn_tag(b"kv_op", b"insert");
     ctx.emit_txn_tag(b"kv_key", args.key.as_bytes());
 
-    let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
-        mkvs.insert(
+    let existing = StorageContext::with_current(|mkvs, _untrusted_local| -> Result<_> {
+        mkvs.check_limits(args.key.as_bytes(), args.value.as_bytes())?;
+        Ok(mkvs.insert(
             IoContext::create_child(&ctx.io_ctx),
             args.key.as_bytes(),
             args.value.as_bytes(),
-        )
-    });
+        ))
+    })?;
     Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
 }
 
@@ -188,8 +191,12 @@ fn remove(args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
     Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
 }
 
-/// Helper for doing encrypted MKVS operations.
-fn get_encryption_context(ctx: &mut TxnContext, key: &[u8]) -> Result<EncryptionContext> {
+/// Run the given function with an encrypted view of the runtime state, using
+/// the state key derived for the given key.
+fn with_encrypted_state<F, R>(ctx: &mut TxnContext, key: &[u8], f: F) -> Result<R>
+where
+    F: FnOnce(&mut EncryptedMKVS<&mut dyn MKVS>, IoContext) -> R,
+{
     let rctx = runtime_context!(ctx, Context);
 
     // Derive key pair ID based on key.
@@ -200,7 +207,15 @@ fn get_encryption_context(ctx: &mut TxnContext, key: &[u8]) -> Result<Encryption
     let result = rctx.km_client.get_or_create_keys(io_ctx, key_pair_id);
     let key = Executor::with_current(|executor| executor.block_on(result))?;
 
-    Ok(EncryptionContext::new(key.state_key.as_ref()))
+    // NOTE: This is only for example purposes, the correct way would be
+    //       to also generate a (deterministic) nonce.
+    let nonce = Nonce::new([0u8; NONCE_SIZE]);
+
+    Ok(StorageContext::with_current(|mkvs, _untrusted_local| {
+        // XXX: Prefix all keys by 0x01 to make sure they do not clash with pending messages.
+        let mut mkvs = EncryptedMKVS::new(mkvs, key.state_key.as_ref(), nonce).with_prefix(&[0x01]);
+        f(&mut mkvs, IoContext::create_child(&ctx.io_ctx))
+    }))
 }
 
 /// (encrypted) Insert a key/value pair.
@@ -208,20 +223,10 @@ fn enc_insert(args: &KeyValue, ctx: &mut TxnContext) -> Result<Option<String>> {
     if ctx.check_only {
         return Err(CheckOnlySuccess::default().into());
     }
-    // NOTE: This is only for example purposes, the correct way would be
-    //       to also generate a (deterministic) nonce.
-    let nonce = [0u8; NONCE_SIZE];
-
-    let enc_ctx = get_encryption_context(ctx, args.key.as_bytes())?;
-    let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
-        enc_ctx.insert(
-            mkvs,
-            IoContext::create_child(&ctx.io_ctx),
-            args.key.as_bytes(),
-            args.value.as_bytes(),
-            &nonce,
-        )
-    });
+    let existing = with_encrypted_state(ctx, args.key.as_bytes(), |mkvs, io_ctx| -> Result<_> {
+        mkvs.check_limits(args.key.as_bytes(), args.value.as_bytes())?;
+        Ok(mkvs.insert(io_ctx, args.key.as_bytes(), args.value.as_bytes()))
+    })??;
     Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
 }
 
@@ -230,14 +235,9 @@ fn enc_get(args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
     if ctx.check_only {
         return Err(CheckOnlySuccess::default().into());
     }
-    let enc_ctx = get_encryption_context(ctx, args.key.as_bytes())?;
-    let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
-        enc_ctx.get(
-            mkvs,
-            IoContext::create_child(&ctx.io_ctx),
-            args.key.as_bytes(),
-        )
-    });
+    let existing = with_encrypted_state(ctx, args.key.as_bytes(), |mkvs, io_ctx| {
+        mkvs.get(io_ctx, args.key.as_bytes())
+    })?;
     Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
 }
 
@@ -246,117 +246,48 @@ fn enc_remove(args: &Key, ctx: &mut TxnContext) -> Result<Option<String>> {
     if ctx.check_only {
         return Err(CheckOnlySuccess::default().into());
     }
-    let enc_ctx = get_encryption_context(ctx, args.key.as_bytes())?;
-    let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
-        enc_ctx.remove(
-            mkvs,
-            IoContext::create_child(&ctx.io_ctx),
-            args.key.as_bytes(),
-        )
-    });
+    let existing = with_encrypted_state(ctx, args.key.as_bytes(), |mkvs, io_ctx| {
+        mkvs.remove(io_ctx, args.key.as_bytes())
+    })?;
     Ok(existing.map(|v| String::from_utf8(v)).transpose()?)
 }
 
-/// A keyed storage encryption context, for use with a MKVS instance.
-struct EncryptionContext {
-    d2: DeoxysII,
-}
-
-impl EncryptionContext {
-    /// Initialize a new EncryptionContext with the given MRAE key.
-    pub fn new(key: &[u8]) -> Self {
-        if key.len() != KEY_SIZE {
-            panic!("mkvs: invalid encryption key size {}", key.len());
-        }
-        let mut raw_key = [0u8; KEY_SIZE];
-        raw_key.copy_from_slice(&key[..KEY_SIZE]);
-
-        let d2 = DeoxysII::new(&raw_key);
-        //raw_key.zeroize();
-
-        Self { d2 }
-    }
+/// Number of threads processing queries in parallel.
+///
+/// Each of them needs an additional TCS, which is accounted for in the `threads`
+/// setting of the SGX metadata in Cargo.toml.
+const QUERY_THREADS: usize = 2;
 
-    /// Get encrypted MKVS entry.
-    pub fn get(&self, mkvs: &dyn MKVS, ctx: IoContext, key: &[u8]) -> Option<Vec<u8>> {
-        let key = self.derive_encrypted_key(key);
-        let ciphertext = match mkvs.get(ctx, &key) {
-            Some(ciphertext) => ciphertext,
-            None => return None,
-        };
+/// Handler for queries which read key/value pairs in parallel.
+struct KeyValueQueryHandler;
 
-        self.open(&ciphertext)
+impl QueryHandler for KeyValueQueryHandler {
+    fn threads(&self) -> usize {
+        QUERY_THREADS
     }
 
-    /// Insert encrypted MKVS entry.
-    pub fn insert(
+    fn query(
         &self,
-        mkvs: &mut dyn MKVS,
-        ctx: IoContext,
-        key: &[u8],
-        value: &[u8],
-        nonce: &[u8],
-    ) -> Option<Vec<u8>> {
-        let nonce = Self::derive_nonce(&nonce);
-        let mut ciphertext = self.d2.seal(&nonce, value.to_vec(), vec![]);
-        ciphertext.extend_from_slice(&nonce);
-
-        let key = self.derive_encrypted_key(key);
-        let ciphertext = match mkvs.insert(ctx, &key, &ciphertext) {
-            Some(ciphertext) => ciphertext,
-            None => return None,
-        };
-
-        self.open(&ciphertext)
-    }
-
-    /// Remove encrypted MKVS entry.
-    pub fn remove(&self, mkvs: &mut dyn MKVS, ctx: IoContext, key: &[u8]) -> Option<Vec<u8>> {
-        let key = self.derive_encrypted_key(key);
-        let ciphertext = match mkvs.remove(ctx, &key) {
-            Some(ciphertext) => ciphertext,
-            None => return None,
-        };
-
-        self.open(&ciphertext)
-    }
-
-    fn open(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
-        // ciphertext || tag || nonce.
-        if ciphertext.len() < TAG_SIZE + NONCE_SIZE {
-            return None;
-        }
-
-        let nonce_offset = ciphertext.len() - NONCE_SIZE;
-        let mut nonce = [0u8; NONCE_SIZE];
-        nonce.copy_from_slice(&ciphertext[nonce_offset..]);
-        let ciphertext = &ciphertext[..nonce_offset];
-
-        let plaintext = self.d2.open(&nonce, ciphertext.to_vec(), vec![]);
-        plaintext.ok()
-    }
-
-    fn derive_encrypted_key(&self, key: &[u8]) -> Vec<u8> {
-        // XXX: The plan is eventually to use a lighter weight transform
-        // for the key instead of a full fledged MRAE algorithm.  For now
-        // approximate it with a Deoxys-II call with an all 0 nonce.
-
-        let nonce = [0u8; NONCE_SIZE];
-        // XXX: Prefix all keys by 0x01 to make sure they do not clash with pending messages.
-        let mut key = vec![0x01];
-        key.append(&mut self.d2.seal(&nonce, key.to_vec(), vec![]));
-        key
-    }
-
-    fn derive_nonce(nonce: &[u8]) -> [u8; NONCE_SIZE] {
-        // Just a copy for type safety.
-        let mut n = [0u8; NONCE_SIZE];
-        if nonce.len() != NONCE_SIZE {
-            panic!("invalid nonce size: {}", nonce.len());
+        ctx: TxnContext,
+        method: &str,
+        args: cbor::Value,
+    ) -> Result<cbor::Value, RuntimeError> {
+        match method {
+            "get" => {
+                let args: Key = cbor::from_value(args).map_err(|err| {
+                    RuntimeError::new("simple-keyvalue", 1, &format!("bad arguments: {}", err))
+                })?;
+                let existing = StorageContext::with_current(|mkvs, _untrusted_local| {
+                    mkvs.get(IoContext::create_child(&ctx.io_ctx), args.key.as_bytes())
+                });
+                let existing = existing
+                    .map(|v| String::from_utf8(v))
+                    .transpose()
+                    .map_err(|err| RuntimeError::new("simple-keyvalue", 2, &format!("{}", err)))?;
+                Ok(cbor::to_value(existing))
+            }
+            _ => Err(RuntimeError::new("simple-keyvalue", 3, "method not found")),
         }
-        n.copy_from_slice(nonce);
-
-        n
     }
 }
 
@@ -448,6 +379,7 @@ pub fn main() {
         })));
 
         txn.set_batch_handler(BlockHandler);
+        txn.set_query_handler(KeyValueQueryHandler);
         txn.set_context_initializer(move |ctx: &mut TxnContext| {
             ctx.runtime = Box::new(Context {
                 test_runtime_id: rt_id.clone(),